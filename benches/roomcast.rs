@@ -0,0 +1,139 @@
+//! Concurrent roomcasts while other people register (and so hash passwords).
+//!
+//! Run with `cargo bench --bench roomcast`. Prints how long each speaker waits
+//! to hear their own `say` echoed back.
+
+extern crate much;
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures_util::sink::SinkExt;
+use much::*;
+use tokio::net::TcpStream;
+use tokio::stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+const ADDR: &str = "127.0.0.1:4090";
+const SPEAKERS: usize = 8;
+const SAYS: usize = 100;
+const REGISTRANTS: usize = 4;
+
+type Lines = Framed<TcpStream, LinesCodec>;
+
+async fn connect() -> Lines {
+    let stream = TcpStream::connect(ADDR).await.expect("connected");
+    Framed::new(stream, LinesCodec::new())
+}
+
+async fn expect_line(lines: &mut Lines) -> String {
+    lines.next().await.expect("line").expect("line")
+}
+
+async fn login(name: &str, password: &str) -> Lines {
+    let mut lines = connect().await;
+    let _prompt = expect_line(&mut lines).await;
+    lines.send(name).await.expect("sent name");
+    let _prompt = expect_line(&mut lines).await;
+    lines.send(password).await.expect("sent password");
+    let _login = expect_line(&mut lines).await;
+    lines
+}
+
+async fn register(name: &str) {
+    let mut lines = connect().await;
+    let _prompt = expect_line(&mut lines).await;
+    lines.send(name).await.expect("sent name");
+    let _new = expect_line(&mut lines).await;
+    let _prompt = expect_line(&mut lines).await;
+    lines.send("password").await.expect("sent password");
+    let _prompt = expect_line(&mut lines).await;
+    lines.send("password").await.expect("sent password");
+    let _login = expect_line(&mut lines).await;
+}
+
+/// Say something `SAYS` times, returning how long each echo took
+async fn speak(mut lines: Lines) -> Vec<Duration> {
+    let mut latencies = Vec::with_capacity(SAYS);
+    for i in 0..SAYS {
+        let start = Instant::now();
+        lines.send(format!("message {}", i)).await.expect("sent say");
+        loop {
+            if expect_line(&mut lines).await.contains("You say") {
+                break;
+            }
+        }
+        latencies.push(start.elapsed());
+    }
+    latencies
+}
+
+fn main() {
+    let mut runtime = tokio::runtime::Runtime::new().expect("runtime");
+
+    runtime.block_on(async {
+        let config = Config {
+            flood_rate: 1_000_000.0,
+            flood_burst: 1_000_000,
+            ..Config::default()
+        };
+
+        let state = much::init(&config);
+        for i in 0..SPEAKERS {
+            let _ = state
+                .lock()
+                .await
+                .new_person(&format!("@speaker{}", i), "password");
+        }
+        tokio::spawn(tcp_serve(state.clone(), ADDR, None));
+        tokio::time::delay_for(Duration::from_millis(50)).await;
+
+        let mut speakers = Vec::new();
+        for i in 0..SPEAKERS {
+            speakers.push(login(&format!("@speaker{}", i), "password").await);
+        }
+
+        let done = Arc::new(AtomicBool::new(false));
+        let registered = Arc::new(AtomicUsize::new(0));
+        for r in 0..REGISTRANTS {
+            let done = done.clone();
+            let registered = registered.clone();
+            tokio::spawn(async move {
+                let mut i = 0;
+                while !done.load(Ordering::Relaxed) {
+                    register(&format!("@new{}_{}", r, i)).await;
+                    registered.fetch_add(1, Ordering::Relaxed);
+                    i += 1;
+                }
+            });
+        }
+
+        let start = Instant::now();
+        let handles: Vec<_> = speakers
+            .into_iter()
+            .map(|lines| tokio::spawn(speak(lines)))
+            .collect();
+        let mut latencies = Vec::new();
+        for handle in handles {
+            latencies.extend(handle.await.expect("speaker"));
+        }
+        let elapsed = start.elapsed();
+        done.store(true, Ordering::Relaxed);
+
+        latencies.sort();
+        let mean = latencies.iter().sum::<Duration>() / latencies.len() as u32;
+        let p99 = latencies[latencies.len() * 99 / 100];
+        println!(
+            "{} speakers x {} says with {} registrants: {:?} total, {:?} mean, {:?} p99, {:?} max; {} registrations",
+            SPEAKERS,
+            SAYS,
+            REGISTRANTS,
+            elapsed,
+            mean,
+            p99,
+            latencies.last().expect("latencies"),
+            registered.load(Ordering::Relaxed)
+        );
+    });
+}
@@ -6,34 +6,59 @@ use std::convert::Infallible;
 use std::error::Error;
 use std::fmt;
 use std::io;
-use std::net::{SocketAddr,Shutdown};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::task::{Context, Poll};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use hyper::server::conn::AddrStream;
+use hyper::server::conn::{AddrStream, Http};
 use hyper::service::{make_service_fn, service_fn};
+use hyper::body::HttpBody;
+use hyper::header::{
+    HeaderValue, CACHE_CONTROL, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, LOCATION, SET_COOKIE,
+};
 use hyper::{Body, Method, Request, Response, Server, StatusCode};
 
 use futures::{SinkExt};
-use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use tokio::net::{UnixListener, UnixStream};
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::stream::{Stream, StreamExt};
 use tokio::sync::{mpsc, Mutex};
-use tokio::time::DelayQueue;
 use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
 
-use tracing::{error, info, span, trace, Level};
+use tracing::{debug, error, info, span, trace, Level};
 
-use clap::{App, Arg};
+use chrono::format::{Item, StrftimeItems};
+
+use tokio_rustls::rustls::internal::pemfile;
+use tokio_rustls::rustls::{NoClientAuth, ServerConfig};
+use tokio_rustls::{server::TlsStream, TlsAcceptor};
+
+use clap::{value_t, App, Arg};
 
 mod world;
+pub mod testing;
 
 use world::command::*;
-use world::message::*;
+use world::flood::*;
+use world::metrics::*;
 use world::person::*;
 use world::room::*;
 use world::state::*;
+use world::storage::Reservation;
+
+pub use world::state::GameState;
+
+pub use world::message::{DepartReason, Message};
+use world::message::{format_duration, WhoEntry};
+use world::paging::paginate;
 
 ////////////////////////////////////////////////////////////////////////////////
 // DRIVER AND CONFIGURATION
@@ -43,12 +68,113 @@ pub const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 const NAME: &'static str = env!("CARGO_PKG_NAME");
 const AUTHORS: &'static str = env!("CARGO_PKG_AUTHORS");
 
+/// What people see right after logging in, unless there's a `--greeting-file`
+pub const DEFAULT_GREETING: &str =
+    "Welcome! Type 'look' to see where you are, 'who' to see who's online, or just type to talk.";
+
 pub struct Config {
-    pub timeout: Option<u64>,
+    /// How long to run before shutting down (`None` means forever)
+    pub timeout: Option<Duration>,
     pub addr: String,
     pub tcp_port: String,
     pub http_port: String,
+    /// Port for both line-oriented sessions and HTTP, told apart by how each
+    /// connection starts (`None` means don't)
+    pub unified_port: Option<String>,
     pub verbosity: Level,
+    /// How to write logs to stderr
+    pub log_format: LogFormat,
+    /// Messages per second a user may sustain before being throttled
+    pub flood_rate: f64,
+    /// Messages a user may send in a quick burst before being throttled
+    pub flood_burst: u32,
+    /// `strftime`-style format for timestamps on speech (`None` means no timestamps)
+    pub timestamp_format: Option<String>,
+    /// Unix domain socket to listen on for local connections (`None` means don't)
+    pub admin_socket: Option<PathBuf>,
+    /// Longest text, in bytes, a user may say (or shout, tell, etc.)
+    pub max_message_length: usize,
+    /// Largest HTTP request body, in bytes, we'll read (e.g., a login form)
+    pub max_body_size: usize,
+    /// How many recent messages each room replays to people arriving
+    pub scrollback: usize,
+    /// How many lines long listings (e.g., `who`) show at a time
+    pub page_size: usize,
+    /// Most TCP connections at once, logged in or not (`None` means no limit)
+    pub max_connections: Option<usize>,
+    /// Names of administrators, who may run admin commands
+    pub admins: Vec<String>,
+    /// Whether anyone may search accounts with `find` (otherwise only admins)
+    pub public_find: bool,
+    /// Whether unknown names may register (otherwise only admins can make
+    /// accounts, with `invite`)
+    pub open_registration: bool,
+    /// Wrong passwords someone may give before we hang up on them
+    pub max_password_attempts: usize,
+    /// Words to mask in speech (empty means no filtering)
+    pub filter_words: Vec<String>,
+    /// Whether commands must start with `/` (otherwise, lines that start with
+    /// a verb are commands, too); either way, everything else is speech
+    pub require_slash: bool,
+    /// Password hashing memory cost, in KiB
+    ///
+    /// Raising the argon2 costs makes stolen hashes harder to crack, but every
+    /// login and registration pays for it in memory and CPU time.
+    pub argon2_mem_kib: u32,
+    /// Password hashing time cost, i.e., passes over memory
+    pub argon2_iterations: u32,
+    /// Password hashing parallelism, i.e., lanes (computed on one thread)
+    pub argon2_lanes: u32,
+    /// Name of the room everyone starts in
+    pub lobby_name: String,
+    /// What people see when they `look` around the lobby (`None` means nothing)
+    pub lobby_description: Option<String>,
+    /// Orientation sent right after each login (`None` means nothing)
+    pub greeting: Option<String>,
+    /// How often to tell everyone how many people are online, keeping idle
+    /// connections alive (`None` means never)
+    pub heartbeat: Option<Duration>,
+    /// How long a line-oriented session may go without input before we
+    /// disconnect it (`None` means forever)
+    pub idle_timeout: Option<Duration>,
+    /// How long before an idle disconnect to warn them (zero means don't)
+    pub idle_warning: Duration,
+    /// How long a web session lasts without requests, unless they asked to be
+    /// remembered
+    pub session_timeout: Duration,
+    /// Whether to look up hostnames for connecting addresses, for the logs and
+    /// `sessions` (off by default: it's slow, and it tells DNS who's here)
+    pub resolve_hostnames: bool,
+    /// PEM certificate chain for TLS on the TCP port (`None` means plaintext)
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key for `tls_cert`
+    pub tls_key: Option<PathBuf>,
+    /// File to save accounts and rooms in, and restore them from at startup
+    /// (`None` means they're lost when the server stops)
+    pub db: Option<PathBuf>,
+    /// How often to save to `db`, if anything has changed
+    pub autosave: Duration,
+}
+
+/// How logs are written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    Text,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("'{}' is not a log format (try text or json)", s)),
+        }
+    }
 }
 
 impl Default for Config {
@@ -58,7 +184,39 @@ impl Default for Config {
             addr: "0.0.0.0".to_string(),
             tcp_port: "4000".to_string(),
             http_port: "4080".to_string(),
+            unified_port: None,
             verbosity: Level::INFO,
+            log_format: LogFormat::Text,
+            flood_rate: 1.0,
+            flood_burst: 5,
+            timestamp_format: None,
+            admin_socket: None,
+            max_message_length: 1024,
+            max_body_size: 16 * 1024,
+            scrollback: 50,
+            page_size: 20,
+            max_connections: None,
+            admins: Vec::new(),
+            public_find: false,
+            open_registration: true,
+            max_password_attempts: 3,
+            filter_words: Vec::new(),
+            require_slash: false,
+            argon2_mem_kib: 4096,
+            argon2_iterations: 3,
+            argon2_lanes: 1,
+            lobby_name: INITIAL_ROOM_NAME.to_string(),
+            lobby_description: Some(DEFAULT_LOBBY_DESCRIPTION.to_string()),
+            greeting: Some(DEFAULT_GREETING.to_string()),
+            heartbeat: None,
+            idle_timeout: None,
+            idle_warning: Duration::from_secs(60),
+            session_timeout: Duration::from_secs(60 * 60),
+            resolve_hostnames: false,
+            tls_cert: None,
+            tls_key: None,
+            db: None,
+            autosave: Duration::from_secs(5 * 60),
         }
     }
 }
@@ -74,9 +232,10 @@ impl Config {
                     .short("t")
                     .long("timeout")
                     .takes_value(true)
-                    .value_name("SECONDS")
+                    .value_name("DURATION")
                     .default_value("forever")
-                    .help("Time after which the server will shutdown"),
+                    .validator(|timeout| parse_duration(&timeout).map(|_| ()))
+                    .help("Time after which the server will shutdown (e.g., 90, 30s, 1h30m, forever)"),
             )
             .arg(
                 Arg::with_name("addr")
@@ -103,6 +262,267 @@ impl Config {
                     .default_value("4080")
                     .help("Sets the port to listen for HTTP connections on"),
             )
+            .arg(
+                Arg::with_name("unified port")
+                    .long("unified-port")
+                    .takes_value(true)
+                    .value_name("PORT")
+                    .help("Also serves TCP sessions and HTTP together on PORT (plaintext only)"),
+            )
+            .arg(
+                Arg::with_name("flood rate")
+                    .long("flood-rate")
+                    .takes_value(true)
+                    .value_name("MSGS_PER_SEC")
+                    .default_value("1")
+                    .help("Sets how many messages per second a user may sustain"),
+            )
+            .arg(
+                Arg::with_name("flood burst")
+                    .long("flood-burst")
+                    .takes_value(true)
+                    .value_name("MSGS")
+                    .default_value("5")
+                    .help("Sets how many messages a user may send in a quick burst"),
+            )
+            .arg(
+                Arg::with_name("timestamps")
+                    .long("timestamps")
+                    .help("Prefixes speech sent to TCP clients with the time it was said"),
+            )
+            .arg(
+                Arg::with_name("timestamp format")
+                    .long("timestamp-format")
+                    .takes_value(true)
+                    .value_name("FORMAT")
+                    .default_value("%H:%M")
+                    .validator(|format| {
+                        if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+                            Err(format!("'{}' is not a valid strftime format", format))
+                        } else {
+                            Ok(())
+                        }
+                    })
+                    .help("Sets the strftime-style format used by --timestamps"),
+            )
+            .arg(
+                Arg::with_name("admin socket")
+                    .long("admin-socket")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Sets a Unix domain socket to listen for local connections on"),
+            )
+            .arg(
+                Arg::with_name("max message length")
+                    .long("max-message-length")
+                    .takes_value(true)
+                    .value_name("BYTES")
+                    .default_value("1024")
+                    .help("Sets the longest message a user may send"),
+            )
+            .arg(
+                Arg::with_name("max body size")
+                    .long("max-body-size")
+                    .takes_value(true)
+                    .value_name("BYTES")
+                    .default_value("16384")
+                    .help("Sets the largest HTTP request body we'll read; bigger ones get 413 Payload Too Large"),
+            )
+            .arg(
+                Arg::with_name("scrollback")
+                    .long("scrollback")
+                    .takes_value(true)
+                    .value_name("MSGS")
+                    .default_value("50")
+                    .help("Sets how many recent messages a room replays to arrivals (0 disables)"),
+            )
+            .arg(
+                Arg::with_name("page size")
+                    .long("page-size")
+                    .takes_value(true)
+                    .value_name("LINES")
+                    .default_value("20")
+                    .help("Sets how many lines long listings (e.g., who) show at a time"),
+            )
+            .arg(
+                Arg::with_name("max connections")
+                    .long("max-connections")
+                    .takes_value(true)
+                    .value_name("N")
+                    .help("Sets the most TCP connections to allow at once (by default, no limit)"),
+            )
+            .arg(
+                Arg::with_name("admin")
+                    .long("admin")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("NAME")
+                    .help("Makes NAME an administrator (may be repeated)"),
+            )
+            .arg(
+                Arg::with_name("public find")
+                    .long("public-find")
+                    .help("Lets anyone search accounts by name (by default, only admins may)"),
+            )
+            .arg(
+                Arg::with_name("require slash")
+                    .long("require-slash")
+                    .help("Only lines starting with / are commands (e.g., /who); everything else is speech"),
+            )
+            .arg(
+                Arg::with_name("resolve hostnames")
+                    .long("resolve-hostnames")
+                    .help("Looks up hostnames for connecting addresses, for the logs and sessions"),
+            )
+            .arg(
+                Arg::with_name("no open registration")
+                    .long("no-open-registration")
+                    .help("Only admins may make accounts (with invite), and logins don't reveal which names exist"),
+            )
+            .arg(
+                Arg::with_name("max password attempts")
+                    .long("max-password-attempts")
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("3")
+                    .help("Sets how many wrong passwords to allow before hanging up"),
+            )
+            .arg(
+                Arg::with_name("filter file")
+                    .long("filter-file")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Sets a list of words (one per line) to mask in speech"),
+            )
+            .arg(
+                Arg::with_name("argon2 memory")
+                    .long("argon2-mem-kib")
+                    .takes_value(true)
+                    .value_name("KIB")
+                    .default_value("4096")
+                    .help("Sets the memory cost of password hashing (higher is safer but slower)"),
+            )
+            .arg(
+                Arg::with_name("argon2 iterations")
+                    .long("argon2-iterations")
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("3")
+                    .help("Sets the time cost of password hashing (higher is safer but slower)"),
+            )
+            .arg(
+                Arg::with_name("argon2 lanes")
+                    .long("argon2-lanes")
+                    .takes_value(true)
+                    .value_name("N")
+                    .default_value("1")
+                    .help("Sets the parallelism of password hashing"),
+            )
+            .arg(
+                Arg::with_name("lobby name")
+                    .long("lobby-name")
+                    .takes_value(true)
+                    .value_name("NAME")
+                    .default_value(INITIAL_ROOM_NAME)
+                    .help("Sets the name of the room everyone starts in"),
+            )
+            .arg(
+                Arg::with_name("lobby description")
+                    .long("lobby-desc")
+                    .takes_value(true)
+                    .value_name("TEXT")
+                    .default_value(DEFAULT_LOBBY_DESCRIPTION)
+                    .help("Sets what people see when they look around the lobby (empty for nothing)"),
+            )
+            .arg(
+                Arg::with_name("greeting file")
+                    .long("greeting-file")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .help("Sets what people see right after logging in (an empty file for nothing)"),
+            )
+            .arg(
+                Arg::with_name("heartbeat")
+                    .long("heartbeat")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .validator(|heartbeat| parse_duration(&heartbeat).map(|_| ()))
+                    .help("Sends web clients a count of who's online every DURATION (e.g., 30s), keeping idle connections alive"),
+            )
+            .arg(
+                Arg::with_name("idle timeout")
+                    .long("idle-timeout")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .default_value("forever")
+                    .validator(|timeout| parse_duration(&timeout).map(|_| ()))
+                    .help("Disconnects terminal sessions that send nothing for DURATION (e.g., 30m)"),
+            )
+            .arg(
+                Arg::with_name("idle warning")
+                    .long("idle-warning")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .default_value("60s")
+                    .validator(|warning| match parse_duration(&warning)? {
+                        Some(_) => Ok(()),
+                        None => Err("--idle-warning can't be forever".to_string()),
+                    })
+                    .help("Warns idle terminal sessions DURATION before --idle-timeout disconnects them (0 means don't)"),
+            )
+            .arg(
+                Arg::with_name("session timeout")
+                    .long("session-timeout")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .default_value("1h")
+                    .validator(|timeout| match parse_duration(&timeout)? {
+                        Some(_) => Ok(()),
+                        None => Err("--session-timeout can't be forever".to_string()),
+                    })
+                    .help("Forgets web sessions after DURATION without requests, unless they asked to be remembered"),
+            )
+            .arg(
+                Arg::with_name("TLS cert")
+                    .long("tls-cert")
+                    .takes_value(true)
+                    .value_name("PEM")
+                    .requires("TLS key")
+                    .help("Sets the certificate chain to serve TLS on the TCP port with"),
+            )
+            .arg(
+                Arg::with_name("TLS key")
+                    .long("tls-key")
+                    .takes_value(true)
+                    .value_name("PEM")
+                    .requires("TLS cert")
+                    .help("Sets the private key for --tls-cert"),
+            )
+            .arg(
+                Arg::with_name("db")
+                    .long("db")
+                    .takes_value(true)
+                    .value_name("PATH")
+                    .help("Sets a file to save accounts and rooms in (and restore them from)"),
+            )
+            .arg(
+                Arg::with_name("autosave secs")
+                    .long("autosave-secs")
+                    .takes_value(true)
+                    .value_name("SECS")
+                    .default_value("300")
+                    .help("Sets how often to save to --db, if anything has changed"),
+            )
+            .arg(
+                Arg::with_name("log format")
+                    .long("log-format")
+                    .takes_value(true)
+                    .value_name("FORMAT")
+                    .possible_values(&["text", "json"])
+                    .default_value("text")
+                    .help("Sets how logs are written: for people (text) or for log aggregators (json)"),
+            )
             .arg(
                 Arg::with_name("v")
                     .short("v")
@@ -114,558 +534,3128 @@ impl Config {
         let addr = config.value_of("addr").expect("interface address").to_string();
         let tcp_port = config.value_of("TCP port").expect("TCP port").to_string();
         let http_port = config.value_of("HTTP port").expect("HTTP port").to_string();
-        let timeout: Option<u64> = config.value_of("timeout").expect("timeout in seconds").parse().ok();
+        let unified_port = config.value_of("unified port").map(str::to_string);
+        let timeout = parse_duration(config.value_of("timeout").expect("timeout"))
+            .expect("validated timeout");
+        let flood_rate = value_t!(config, "flood rate", f64).unwrap_or_else(|e| e.exit());
+        let flood_burst = value_t!(config, "flood burst", u32).unwrap_or_else(|e| e.exit());
+
+        let timestamp_format = if config.is_present("timestamps") {
+            Some(config.value_of("timestamp format").expect("timestamp format").to_string())
+        } else {
+            None
+        };
+
+        let admin_socket = config.value_of("admin socket").map(PathBuf::from);
+        let max_message_length =
+            value_t!(config, "max message length", usize).unwrap_or_else(|e| e.exit());
+        let max_body_size = value_t!(config, "max body size", usize).unwrap_or_else(|e| e.exit());
+        let scrollback = value_t!(config, "scrollback", usize).unwrap_or_else(|e| e.exit());
+        let page_size = value_t!(config, "page size", usize).unwrap_or_else(|e| e.exit());
+        let max_connections = if config.is_present("max connections") {
+            Some(value_t!(config, "max connections", usize).unwrap_or_else(|e| e.exit()))
+        } else {
+            None
+        };
+        let admins = config
+            .values_of("admin")
+            .map_or_else(Vec::new, |names| names.map(str::to_string).collect());
+        let public_find = config.is_present("public find");
+        let open_registration = !config.is_present("no open registration");
+        let resolve_hostnames = config.is_present("resolve hostnames");
+        let require_slash = config.is_present("require slash");
+        let max_password_attempts =
+            value_t!(config, "max password attempts", usize).unwrap_or_else(|e| e.exit());
+        let filter_words = match config.value_of("filter file") {
+            None => Vec::new(),
+            Some(path) => load_word_list(Path::new(path)).unwrap_or_else(|e| {
+                clap::Error::with_description(
+                    &format!("couldn't read filter file {}: {}", path, e),
+                    clap::ErrorKind::Io,
+                )
+                .exit()
+            }),
+        };
+        let argon2_mem_kib = value_t!(config, "argon2 memory", u32).unwrap_or_else(|e| e.exit());
+        let argon2_iterations =
+            value_t!(config, "argon2 iterations", u32).unwrap_or_else(|e| e.exit());
+        let argon2_lanes = value_t!(config, "argon2 lanes", u32).unwrap_or_else(|e| e.exit());
+        let lobby_name = config.value_of("lobby name").expect("lobby name").to_string();
+        let lobby_description = config
+            .value_of("lobby description")
+            .filter(|description| !description.is_empty())
+            .map(str::to_string);
+        let greeting = match config.value_of("greeting file") {
+            None => Some(DEFAULT_GREETING.to_string()),
+            Some(path) => match std::fs::read_to_string(path) {
+                Ok(text) if text.trim().is_empty() => None,
+                Ok(text) => Some(text.trim_end().to_string()),
+                Err(e) => clap::Error::with_description(
+                    &format!("couldn't read greeting file {}: {}", path, e),
+                    clap::ErrorKind::Io,
+                )
+                .exit(),
+            },
+        };
+        let heartbeat = match config.value_of("heartbeat") {
+            None => None,
+            Some(heartbeat) => parse_duration(heartbeat).expect("validated heartbeat"),
+        };
+        let idle_timeout = parse_duration(config.value_of("idle timeout").expect("idle timeout"))
+            .expect("validated idle timeout");
+        let idle_warning = parse_duration(config.value_of("idle warning").expect("idle warning"))
+            .expect("validated idle warning")
+            .expect("finite idle warning");
+        let session_timeout =
+            parse_duration(config.value_of("session timeout").expect("session timeout"))
+                .expect("validated session timeout")
+                .expect("finite session timeout");
+        let tls_cert = config.value_of("TLS cert").map(PathBuf::from);
+        let tls_key = config.value_of("TLS key").map(PathBuf::from);
+        let db = config.value_of("db").map(PathBuf::from);
+        let autosave = Duration::from_secs(
+            value_t!(config, "autosave secs", u64).unwrap_or_else(|e| e.exit()),
+        );
 
         let verbosity = match config.occurrences_of("v") {
             0 => Level::INFO,
             1 => Level::DEBUG,
             2 | _ => Level::TRACE,
         };
+        let log_format = value_t!(config, "log format", LogFormat).unwrap_or_else(|e| e.exit());
 
-        Config {
+        let config = Config {
             timeout,
             addr,
             tcp_port,
             http_port,
-            verbosity
+            unified_port,
+            verbosity,
+            log_format,
+            flood_rate,
+            flood_burst,
+            timestamp_format,
+            admin_socket,
+            max_message_length,
+            max_body_size,
+            scrollback,
+            page_size,
+            max_connections,
+            admins,
+            public_find,
+            open_registration,
+            max_password_attempts,
+            filter_words,
+            require_slash,
+            argon2_mem_kib,
+            argon2_iterations,
+            argon2_lanes,
+            lobby_name,
+            lobby_description,
+            greeting,
+            heartbeat,
+            idle_timeout,
+            idle_warning,
+            session_timeout,
+            resolve_hostnames,
+            tls_cert,
+            tls_key,
+            db,
+            autosave,
+        };
+
+        if let Err(msg) = config.validate() {
+            clap::Error::with_description(&msg, clap::ErrorKind::ValueValidation).exit();
         }
-    }
 
-    pub fn tcp_addr(&self) -> String {
-        format!("{}:{}", self.addr, self.tcp_port)
+        config
     }
 
-    pub fn http_addr(&self) -> String {
-        format!("{}:{}", self.addr, self.http_port)
-    }
-}
+    /// Check settings that clap can't check one flag at a time
+    pub fn validate(&self) -> Result<(), String> {
+        if !is_bind_address(&self.addr) {
+            return Err(format!("invalid bind address '{}'", self.addr));
+        }
 
-pub fn run(config: &Config, state: GameState) -> Result<(), Box<dyn Error>> {
-    let tcp_server = tcp_serve(state.clone(), config.tcp_addr());
-    let http_server = http_serve(state.clone(), config.http_addr());
+        let ports = [
+            ("--tcp-port", Some(&self.tcp_port)),
+            ("--http-port", Some(&self.http_port)),
+            ("--unified-port", self.unified_port.as_ref()),
+        ];
+        for (flag, port) in ports.iter() {
+            if let Some(port) = port {
+                if port.parse::<u16>().is_err() {
+                    return Err(format!("invalid {} '{}' (ports go up to 65535)", flag, port));
+                }
+            }
+        }
 
-    let runtime = tokio::runtime::Runtime::new()?;
-    info!("initialized tokio runtime");
+        if self.max_connections == Some(0) {
+            return Err("--max-connections must be at least 1".to_string());
+        }
 
-    runtime.spawn(tcp_server);
-    info!("started TCP server on {}", config.tcp_addr());
+        if self.max_body_size == 0 {
+            return Err("--max-body-size must be at least 1".to_string());
+        }
 
-    runtime.spawn(http_server);
-    info!("started HTTP server on {}", config.http_addr());
+        if self.max_password_attempts < 1 {
+            return Err("--max-password-attempts must be at least 1".to_string());
+        }
 
-    if let Some(secs) = config.timeout {
-        info!("shutdown timer: {} seconds", secs);
-        runtime.shutdown_timeout(Duration::from_secs(secs));
-    } else {
-        loop {}
-    }
+        if self.page_size < 1 {
+            return Err("--page-size must be at least 1".to_string());
+        }
 
-    info!("shutting down");
-    Ok(())
-}
+        if self.flood_rate.is_nan() || self.flood_rate <= 0.0 {
+            return Err("--flood-rate must be a positive number".to_string());
+        }
 
-pub type GameState = Arc<Mutex<State>>;
+        if self.flood_burst < 1 {
+            return Err("--flood-burst must be at least 1".to_string());
+        }
 
-pub fn init() -> GameState {
-    Arc::new(Mutex::new(State::new()))
-}
+        if room_slug(&self.lobby_name).is_empty() {
+            return Err("--lobby-name needs a letter or number".to_string());
+        }
 
-////////////////////////////////////////////////////////////////////////////////
-// TCP STUFF
-////////////////////////////////////////////////////////////////////////////////
+        if self.autosave == Duration::from_secs(0) {
+            return Err("--autosave-secs must be at least 1".to_string());
+        }
 
-/// Internal messages for managing a peer's `MessageQueue`
-#[derive(Clone, Debug)]
-enum PeerMessage {
-    LineFromPeer(String),
-    SendToPeer(Message),
-}
+        if self.heartbeat == Some(Duration::from_secs(0)) {
+            return Err("--heartbeat must be at least 1s".to_string());
+        }
 
-struct TCPPeer {
-    /// Line-oriented TCP socket (poor-man's telnet)
-    ///     
-    /// This is the actual place we read from!
-    // TODO support IAC codes, MCCP, etc.
-    lines: Framed<TcpStream, LinesCodec>,
-    /// Receive-end of the message queue for this connection
-    rx: MessageQueueRX,
-}
+        if let Some(timeout) = self.idle_timeout {
+            if timeout == Duration::from_secs(0) {
+                return Err("--idle-timeout must be at least 1s".to_string());
+            }
+            if self.idle_warning >= timeout {
+                return Err("--idle-warning must be shorter than --idle-timeout".to_string());
+            }
+        }
 
-impl TCPPeer {
-    async fn new(
-        state: GameState,
-        lines: Framed<TcpStream, LinesCodec>,
-        person: &Person,
-    ) -> io::Result<Self> {
-        let addr = lines.get_ref().peer_addr()?;
+        if self.session_timeout == Duration::from_secs(0) {
+            return Err("--session-timeout must be at least 1s".to_string());
+        }
 
-        let (tx, rx) = mpsc::unbounded_channel();
+        if self.argon2_iterations < 1 {
+            return Err("--argon2-iterations must be at least 1".to_string());
+        }
 
-        state
-            .lock()
-            .await
-            .register_connection(person.id, Connection::TCP { addr }, tx);
+        if self.argon2_lanes < 1 || self.argon2_lanes > 0xFF_FFFF {
+            return Err("--argon2-lanes must be between 1 and 16777215".to_string());
+        }
 
-        Ok(TCPPeer {
-            lines,
-            rx,
-        })
-    }
-}
+        if self.argon2_mem_kib < 8 * self.argon2_lanes {
+            return Err(format!(
+                "--argon2-mem-kib must be at least 8 per lane (i.e., {})",
+                8 * self.argon2_lanes
+            ));
+        }
 
-impl Stream for TCPPeer {
-    type Item = Result<PeerMessage, LinesCodecError>;
+        Ok(())
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        // send pending messages to the peer
-        if let Poll::Ready(Some(v)) = Pin::new(&mut self.rx).poll_next(cx) {
-            return Poll::Ready(Some(Ok(PeerMessage::SendToPeer(v))));
+    /// Password hashing configuration (see `validate`)
+    pub fn password_config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            mem_cost: self.argon2_mem_kib,
+            time_cost: self.argon2_iterations,
+            lanes: self.argon2_lanes,
+            ..argon2::Config::default()
         }
+    }
 
-        // connection-dependent read from the peer
-        let result: Option<_> = futures::ready!(Pin::new(&mut self.lines).poll_next(cx));
-
-        Poll::Ready(match result {
-            Some(Ok(message)) => Some(Ok(PeerMessage::LineFromPeer(message))),
-            Some(Err(e)) => Some(Err(e)),
-            None => None,
-        })
+    pub fn tcp_addr(&self) -> String {
+        self.with_port(&self.tcp_port)
     }
-}
 
-#[derive(Debug)]
-struct LoginAbortedError {
-    addr: SocketAddr,
-    name: Option<String>,
-}
+    pub fn http_addr(&self) -> String {
+        self.with_port(&self.http_port)
+    }
 
-impl Error for LoginAbortedError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+    pub fn unified_addr(&self) -> Option<String> {
+        self.unified_port.as_ref().map(|port| self.with_port(port))
     }
-}
 
-impl fmt::Display for LoginAbortedError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match &self.name {
-            None => write!(f, "Login error: connection with {} reset.", self.addr),
-            Some(name) => write!(
-                f,
-                "Login error: connection with {} from {} reset.",
-                name, self.addr
-            ),
+    /// The TLS acceptor for the TCP port, if a certificate and key are configured
+    pub fn tls_acceptor(&self) -> io::Result<Option<TlsAcceptor>> {
+        let (cert, key) = match (&self.tls_cert, &self.tls_key) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "TLS needs both a certificate and a key",
+                ))
+            }
+        };
+
+        let invalid = |what: &str, path: &Path| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("no valid {} in {}", what, path.display()),
+            )
+        };
+
+        let certs = pemfile::certs(&mut io::BufReader::new(std::fs::File::open(cert)?))
+            .map_err(|()| invalid("certificates", cert))?;
+
+        // PKCS#8 or, failing that, PKCS#1 (i.e., `BEGIN RSA PRIVATE KEY`)
+        let mut keys =
+            pemfile::pkcs8_private_keys(&mut io::BufReader::new(std::fs::File::open(key)?))
+                .map_err(|()| invalid("private key", key))?;
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut io::BufReader::new(std::fs::File::open(key)?))
+                .map_err(|()| invalid("private key", key))?;
         }
+        let key = keys.pop().ok_or_else(|| invalid("private key", key))?;
+
+        let mut tls = ServerConfig::new(NoClientAuth::new());
+        tls.set_single_cert(certs, key)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(Some(TlsAcceptor::from(Arc::new(tls))))
     }
-}
 
-#[derive(Debug)]
-struct TooManyPasswordAttemptsError {
-    addr: SocketAddr,
-    name: String,
+    /// `addr:port`, bracketing IPv6 literals (e.g., `[::1]:4000`)
+    fn with_port(&self, port: &str) -> String {
+        if self.addr.contains(':') && !self.addr.starts_with('[') {
+            format!("[{}]:{}", self.addr, port)
+        } else {
+            format!("{}:{}", self.addr, port)
+        }
+    }
 }
 
-impl Error for TooManyPasswordAttemptsError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+/// Could we listen on `addr`? It's an IP address (IPv6 ones may be
+/// bracketed) or a hostname; hostnames aren't looked up until we bind.
+fn is_bind_address(addr: &str) -> bool {
+    let unbracketed = addr
+        .strip_prefix('[')
+        .and_then(|addr| addr.strip_suffix(']'))
+        .unwrap_or(addr);
+    if unbracketed.parse::<IpAddr>().is_ok() {
+        return true;
     }
+
+    !addr.is_empty()
+        && addr.len() <= 253
+        && addr.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
 }
 
-impl fmt::Display for TooManyPasswordAttemptsError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Login error: too many password attempts as {} from {}; connection reset.",
-            self.name, self.addr
+/// Parse a duration like `90` (seconds), `30s`, `10m`, `2d`, or `1h30m`. The
+/// duration `forever` is `None`.
+pub fn parse_duration(s: &str) -> Result<Option<Duration>, String> {
+    let s = s.trim();
+    let invalid = || {
+        format!(
+            "'{}' is not a valid duration (try, e.g., 90, 30s, 1h30m, or forever)",
+            s
         )
+    };
+
+    if s == "forever" {
+        return Ok(None);
     }
-}
 
-#[derive(Debug)]
-struct PasswordsDontMatchError {
-    addr: SocketAddr,
-    name: String,
-}
+    // bare seconds, for backwards compatibility
+    if let Ok(secs) = s.parse::<u64>() {
+        return Ok(Some(Duration::from_secs(secs)));
+    }
 
-impl Error for PasswordsDontMatchError {
-    fn source(&self) -> Option<&(dyn Error + 'static)> {
-        None
+    let mut secs: u64 = 0;
+    let mut digits = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            digits.push(c);
+            continue;
+        }
+
+        let unit = match c {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return Err(invalid()),
+        };
+
+        let n: u64 = digits.parse().map_err(|_| invalid())?;
+        secs = n
+            .checked_mul(unit)
+            .and_then(|n| secs.checked_add(n))
+            .ok_or_else(invalid)?;
+        digits.clear();
     }
-}
 
-impl fmt::Display for PasswordsDontMatchError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "Registration error: passwords don't match for {} on {}.",
-            self.name, self.addr
-        )
+    // empty, or a number with no unit at the end (e.g., `1h30`)
+    if s.is_empty() || !digits.is_empty() {
+        return Err(invalid());
     }
+
+    Ok(Some(Duration::from_secs(secs)))
 }
 
-pub async fn prompt<F, Ferr, Ftimeout>(
-    lines: &mut Framed<TcpStream, LinesCodec>,
-    prompt: &str,
-    reprompt: &str,
-    valid: F,
-    check_tries: Ferr,
-    timeout: Ftimeout,
-) -> Result<String, Box<dyn Error>>
-where
-    F: Fn(&str) -> bool,
-    Ferr: Fn(usize) -> Option<Box<dyn Error>>,
-    Ftimeout: FnOnce() -> Box<dyn Error>,
-{
-    let mut num_tries = 0;
-    loop {
-        lines.send(prompt).await?;
+/// Read a word list: one word per line, ignoring blank lines and `#` comments
+pub fn load_word_list(path: &Path) -> io::Result<Vec<String>> {
+    let words = std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|word| !word.is_empty() && !word.starts_with('#'))
+        .map(str::to_ascii_lowercase)
+        .collect();
 
-        match lines.next().await {
-            Some(Ok(line)) => {
-                let line = line.trim();
+    Ok(words)
+}
 
-                if valid(&line) {
-                    return Ok(line.to_string());
-                }
+/// Run a server as configured on the command line, until it shuts down (or
+/// the timeout expires, or we get a termination signal)
+pub fn run(config: &Config, state: GameState) -> Result<(), Box<dyn Error>> {
+    config.validate()?;
 
-                num_tries += 1;
-                if let Some(error) = check_tries(num_tries) {
-                    return Err(error);
+    let mut runtime = tokio::runtime::Runtime::new()?;
+    info!("initialized tokio runtime");
+
+    runtime.block_on(async {
+        let mut server = ServerBuilder::from_config(config)?
+            .with_state(state.clone())
+            .spawn();
+        let mut shutdown = state.lock().await.shutdown_signal();
+
+        let timeout = async {
+            match config.timeout {
+                Some(timeout) => {
+                    info!("shutdown timer: {} seconds", timeout.as_secs());
+                    tokio::time::delay_for(timeout).await
                 }
+                None => futures::future::pending().await,
+            }
+        };
 
-                lines.send(reprompt).await?;
+        // e.g., a port already in use shouldn't leave us running, serving nothing
+        let failure = tokio::select! {
+            _ = shutdown.recv() => {
+                // e.g., so the last autosave finishes
+                server.stopped().await;
+                return Ok(());
             }
-            _ => return Err(timeout()),
+            _ = termination_signal() => None,
+            _ = timeout => None,
+            failure = server.failed() => Some(failure),
+        };
+        server.shutdown().await;
+
+        match failure {
+            None => Ok(()),
+            Some(failure) => Err(Box::new(failure) as Box<dyn Error>),
         }
-    }
+    })?;
+
+    info!("shutting down");
+    // give sessions a moment to deliver their goodbyes
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    Ok(())
 }
 
-pub async fn login(
-    state: GameState,
-    lines: &mut Framed<TcpStream, LinesCodec>,
-    addr: SocketAddr,
-) -> Result<Person, Box<dyn Error>> {
-    // TODO welcome header, instructions, etc.
+/// Sets up a server to run on the caller's tokio runtime, e.g., to embed one
+/// in another program. Nothing listens unless you ask for it.
+#[derive(Default)]
+pub struct ServerBuilder {
+    tcp_addr: Option<String>,
+    tls: Option<TlsAcceptor>,
+    http_addr: Option<String>,
+    unified_addr: Option<String>,
+    admin_socket: Option<PathBuf>,
+    heartbeat: Option<Duration>,
+    autosave: Option<Duration>,
+    state: Option<GameState>,
+}
 
-    let name = prompt(
-        lines,
-        "What is your email address or Twitter handle? ",
-        "Please enter a valid email address or Twitter handle.",
-        |name| !name.is_empty() && name.contains('@'),
-        |_| None, // unlimited tries
-        || Box::new(LoginAbortedError { addr, name: None }),
-    )
-    .await?;
+impl ServerBuilder {
+    pub fn new() -> Self {
+        ServerBuilder::default()
+    }
 
-    let conn = Connection::TCP { addr };
-    let person = state.lock().await.person_by_name(&name);
+    /// Listen where `config` says to, with fresh state (see `init`). Fails if
+    /// the TLS certificate or key can't be loaded.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        Ok(ServerBuilder {
+            tcp_addr: Some(config.tcp_addr()),
+            tls: config.tls_acceptor()?,
+            http_addr: Some(config.http_addr()),
+            unified_addr: config.unified_addr(),
+            admin_socket: config.admin_socket.clone(),
+            heartbeat: config.heartbeat,
+            autosave: Some(config.autosave),
+            state: None,
+        }
+        .with_state(init(config)))
+    }
 
-    match person {
-        Some(person) => {
-            info!(person.id, "found {}", person.name);
+    /// Serve line-oriented sessions over TCP on `addr`, e.g., `127.0.0.1:4000`
+    pub fn tcp_addr<A: Into<String>>(mut self, addr: A) -> Self {
+        self.tcp_addr = Some(addr.into());
+        self
+    }
 
-            let _password = prompt(
-                lines,
-                "Password: ",
-                "Password incorrect.",
-                |password| {
-                    argon2::verify_encoded(&person.password, password.as_bytes()).unwrap_or(false)
-                },
-                |failed_tries| {
-                    if failed_tries >= 3 {
-                        Some(Box::new(TooManyPasswordAttemptsError {
-                            name: name.clone(),
-                            addr,
-                        }))
-                    } else {
-                        None
-                    }
-                },
-                || {
-                    Box::new(LoginAbortedError {
-                        addr,
-                        name: Some(name.clone()),
-                    })
-                },
-            )
-            .await?;
-            
-            return Ok(Person::new(&person, conn));
-        }
-        None => loop {
-            info!("no user {}, registering", name);
-
-            lines.send("You must be new here!").await?;
-
-            let password1 = prompt(
-                lines,
-                "Please enter a password: ",
-                "That is not a valid password. It should be at least 8 characters.",
-                |password| password.len() >= 8,
-                |_| None,
-                || {
-                    Box::new(LoginAbortedError {
-                        addr,
-                        name: Some(name.clone()),
-                    })
-                },
-            )
-            .await?;
+    /// Speak TLS on the TCP port
+    pub fn tls(mut self, tls: TlsAcceptor) -> Self {
+        self.tls = Some(tls);
+        self
+    }
 
-            lines.send("Please re-enter your password: ").await?;
+    /// Serve HTTP on `addr`
+    pub fn http_addr<A: Into<String>>(mut self, addr: A) -> Self {
+        self.http_addr = Some(addr.into());
+        self
+    }
 
-            match lines.next().await {
-                Some(Ok(password2)) => {
-                    if password1 != password2.trim() {
-                        lines.send("Passwords don't match.").await?;
-                        continue;
-                    }
+    /// Serve both TCP sessions and HTTP on `addr` (see `unified_serve`)
+    pub fn unified_addr<A: Into<String>>(mut self, addr: A) -> Self {
+        self.unified_addr = Some(addr.into());
+        self
+    }
 
-                    let person = state.lock().await.new_person(&name, &password1);
-                    info!(person.id, "registered");
-                    return Ok(Person::new(&person, conn));
-                }
-                _ => {
-                    return Err(Box::new(LoginAbortedError {
-                        addr,
-                        name: Some(name),
-                    }))
-                }
-            }
-        },
-    };
-}
+    /// Serve local (admin) sessions on a Unix domain socket at `path`
+    pub fn admin_socket<P: Into<PathBuf>>(mut self, path: P) -> Self {
+        self.admin_socket = Some(path.into());
+        self
+    }
 
+    /// Tell everyone how many people are online every `interval`
+    pub fn heartbeat(mut self, interval: Duration) -> Self {
+        self.heartbeat = Some(interval);
+        self
+    }
 
-pub async fn process(
-    state: GameState,
-    stream: TcpStream,
-    addr: SocketAddr,
-) -> Result<(), Box<dyn Error>> {
-    let mut lines = Framed::new(stream, LinesCodec::new());
+    /// Save every `interval` (if anything has changed) and at shutdown, when
+    /// the state has somewhere to save to (see `Config::db`)
+    pub fn autosave(mut self, interval: Duration) -> Self {
+        self.autosave = Some(interval);
+        self
+    }
 
-    let login_span = span!(Level::INFO, "login/registration", ?addr);
-    let mut person = login_span.in_scope(|| login(state.clone(), &mut lines, addr)).await?;
-    lines.send(format!("Logged in as {}...", person.name)).await?;
+    /// Use existing state, e.g., to set up accounts before serving
+    pub fn with_state(mut self, state: GameState) -> Self {
+        self.state = Some(state);
+        self
+    }
 
-    let span = span!(Level::INFO, "session", id = person.id);
-    let _guard = span.enter();
-    info!("logged in");
-    
-    let mut peer = TCPPeer::new(state.clone(), lines, &person).await?;
+    /// Start serving. Must be called from within a tokio runtime.
+    pub fn spawn(self) -> ServerHandle {
+        let state = self
+            .state
+            .unwrap_or_else(|| init(&Config::default()));
+        let mut tasks = Vec::new();
+        let (failed, failures) = mpsc::unbounded_channel();
+
+        if let Some(addr) = self.tcp_addr {
+            info!("starting TCP server on {}", addr);
+            let server = tcp_serve(state.clone(), addr, self.tls);
+            tasks.push(spawn_server("TCP", server, failed.clone()));
+        }
 
-    let loc = person.loc;
-    state.lock().await.arrive(&mut person, loc).await;
+        if let Some(addr) = self.http_addr {
+            info!("starting HTTP server on {}", addr);
+            let server = http_serve(state.clone(), addr);
+            tasks.push(spawn_server("HTTP", server, failed.clone()));
+        }
 
-    while let Some(result) = peer.next().await {
-        match result {
-            Ok(PeerMessage::LineFromPeer(msg)) => {
-                let cmd = Command::parse(msg)?;
+        if let Some(addr) = self.unified_addr {
+            info!("starting unified server on {}", addr);
+            let server = unified_serve(state.clone(), addr);
+            tasks.push(spawn_server("unified", server, failed.clone()));
+        }
 
-                cmd.run(state.clone(), &mut person).await;
-            }
+        if let Some(interval) = self.heartbeat {
+            info!("sending heartbeats every {} seconds", interval.as_secs());
+            tasks.push(tokio::spawn(heartbeat(state.clone(), interval)));
+        }
 
-            Ok(PeerMessage::SendToPeer(msg)) => {
-                let s = msg.render(person.id).await;
-                peer.lines.send(s).await?;
+        if let Some(interval) = self.autosave.filter(|_| state.persists()) {
+            info!("autosaving every {} seconds", interval.as_secs());
+            tasks.push(tokio::spawn(autosave(state.clone(), interval)));
+        }
 
-                if let Message::Logout = msg {
-                    info!(id = person.id, "logout");
-                    if let Err(e) = peer.lines.get_ref().shutdown(Shutdown::Both) {
-                        error!(?e, id = person.id, "logout");
-                    }
-                    return Ok(())
-                }
+        if let Some(path) = self.admin_socket {
+            #[cfg(unix)]
+            {
+                info!("starting Unix socket server on {}", path.display());
+                let server = uds_serve(state.clone(), path);
+                tasks.push(spawn_server("Unix socket", server, failed.clone()));
             }
 
-            Err(e) => {
-                error!(?e, id = person.id);
-            }
+            #[cfg(not(unix))]
+            error!(path = %path.display(), "Unix sockets aren't supported on this platform");
+        }
+
+        ServerHandle {
+            state,
+            tasks,
+            failures,
         }
     }
+}
 
-    {
-        let mut state = state.lock().await;
+/// Run `server` in the background, reporting on `failed` if it fails
+fn spawn_server<F, E>(
+    name: &'static str,
+    server: F,
+    failed: mpsc::UnboundedSender<ServerFailedError>,
+) -> tokio::task::JoinHandle<()>
+where
+    F: std::future::Future<Output = Result<(), E>> + Send + 'static,
+    E: fmt::Debug + fmt::Display + Send + 'static,
+{
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(?e, "{} server failed", name);
+            let _ = failed.send(ServerFailedError {
+                server: name,
+                message: e.to_string(),
+            });
+        }
+    })
+}
 
-        // actually log them off
-        state.unregister_connection(person.id);
+/// A server that stopped with an error, e.g., because its port was in use
+#[derive(Debug)]
+pub struct ServerFailedError {
+    /// Which server, e.g., "TCP"
+    pub server: &'static str,
+    pub message: String,
+}
 
-        // announce it to everyone
-        state.depart(&person).await;
+impl Error for ServerFailedError {}
+
+impl fmt::Display for ServerFailedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} server failed: {}", self.server, self.message)
     }
-    info!(id = person.id, "logout (disconnected)");
+}
 
-    trace!("disconnected");
-    Ok(())
+/// A running server (see `ServerBuilder::spawn`)
+pub struct ServerHandle {
+    state: GameState,
+    tasks: Vec<tokio::task::JoinHandle<()>>,
+    failures: mpsc::UnboundedReceiver<ServerFailedError>,
 }
 
-pub async fn tcp_serve<A: ToSocketAddrs>(state: Arc<Mutex<State>>, addr: A) -> io::Result<()> {
-    let mut listener = TcpListener::bind(addr).await?;
+impl ServerHandle {
+    pub fn state(&self) -> &GameState {
+        &self.state
+    }
 
-    loop {
-        let (stream, addr) = listener.accept().await?;
+    /// Wait for one of the servers to fail, e.g., because it couldn't bind its
+    /// address. Servers that stop cleanly don't count, so this may never
+    /// finish.
+    pub async fn failed(&mut self) -> ServerFailedError {
+        match self.failures.recv().await {
+            Some(failure) => failure,
+            None => futures::future::pending().await,
+        }
+    }
 
-        let span = span!(Level::INFO, "TCP connection");
-        let _guard = span.enter();
-        info!(?addr, "connected");
+    /// Say goodbye to everyone and wait for the servers to stop
+    pub async fn shutdown(self) {
+        self.state.lock().await.shutdown().await;
+        self.stopped().await;
+    }
 
-        let state = state.clone();
-        tokio::spawn(async move {
-            if let Err(e) = process(state, stream, addr).await {
-                error!(?e);
+    /// Wait for the servers to stop, e.g., after an admin's `shutdown`
+    pub async fn stopped(self) {
+        for task in self.tasks {
+            if let Err(e) = task.await {
+                error!(?e, "server task failed");
             }
-        });
+        }
+    }
+}
+
+/// Resolves on SIGINT (i.e., Ctrl-C) or, on Unix, SIGTERM
+async fn termination_signal() {
+    #[cfg(unix)]
+    let result = match signal(SignalKind::terminate()) {
+        Ok(mut term) => tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = term.recv() => Ok(()),
+        },
+        Err(e) => Err(e),
+    };
+
+    #[cfg(not(unix))]
+    let result = tokio::signal::ctrl_c().await;
+
+    match result {
+        Ok(()) => info!("caught termination signal"),
+        Err(e) => {
+            error!(?e, "couldn't listen for termination signals");
+            futures::future::pending().await
+        }
     }
 }
 
+pub fn init(config: &Config) -> GameState {
+    GameState::new(State::new(config))
+}
+
+/// Like `init`, but with the accounts and rooms last saved to `--db`, if
+/// there are any
+pub fn restore(config: &Config) -> Result<GameState, Box<dyn Error>> {
+    let path = match &config.db {
+        Some(path) if path.exists() => path,
+        _ => return Ok(init(config)),
+    };
+
+    let saved = Saved::read(path).map_err(|e| format!("couldn't read {}: {}", path.display(), e))?;
+    info!(people = saved.num_people(), ?path, "restored");
+    Ok(GameState::new(State::restore(config, saved)))
+}
+
 ////////////////////////////////////////////////////////////////////////////////
-// HTTP STUFF
+// TCP STUFF
 ////////////////////////////////////////////////////////////////////////////////
 
-/// The cookie in which we store sessions
-const SESSIONID: &'static str = "id";
+/// Internal messages for managing a peer's `MessageQueue`
+#[derive(Clone, Debug)]
+enum PeerMessage {
+    LineFromPeer(String),
+    SendToPeer(Message),
+}
 
-/// The name of the CSRF token variable for POST requests
-const CSRFTOKEN: &'static str = "tok";
+/// A stream we can run a line-oriented session over
+pub trait Transport: AsyncRead + AsyncWrite + Unpin {
+    /// Describes the peer on the other end of the stream
+    fn connection(&self) -> io::Result<Connection>;
+}
 
-/// Time-to-live in a room between calls to `/api/be`
-const HTTP_TTL_SECS: u64 = 30;
+impl Transport for TcpStream {
+    fn connection(&self) -> io::Result<Connection> {
+        Ok(Connection::TCP {
+            addr: self.peer_addr()?,
+        })
+    }
+}
 
-pub type SessionId = String;
+impl<S: Transport> Transport for TlsStream<S> {
+    fn connection(&self) -> io::Result<Connection> {
+        self.get_ref().0.connection()
+    }
+}
 
-type CSRFToken = String;
+/// Unix socket peers are (almost always) unnamed, so we go by our end
+#[cfg(unix)]
+impl Transport for UnixStream {
+    fn connection(&self) -> io::Result<Connection> {
+        let addr = self.local_addr()?;
+        let path = addr.as_pathname().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "unnamed Unix socket")
+        })?;
+
+        Ok(Connection::Unix {
+            path: path.to_path_buf(),
+        })
+    }
+}
 
-pub struct HTTPState {
-    /// CSPRNG for session and CSRF tokens
-    csprng: rand::rngs::StdRng,
-    sessions: HashMap<SessionId, PersonId>,
-    tokens: HashMap<SessionId, CSRFToken>,
-    // TODO call reset on a hit to /do or /be
-    // TODO someone needs to be polling this queue and dropping people from rooms
-    timeouts: DelayQueue<(SessionId, RoomId)>,
+/// Most queued messages we write out together before checking for input
+const MAX_BATCHED_MESSAGES: usize = 64;
+
+struct LinePeer<S> {
+    /// Line-oriented socket (poor-man's telnet), either TCP or Unix
+    ///     
+    /// This is the actual place we read from!
+    // TODO support IAC codes, MCCP, etc.
+    lines: Framed<S, LinesCodec>,
+    /// Receive-end of the message queue for this connection
+    rx: MessageQueueRX,
+    /// Which source to poll first next time; we alternate so that neither a
+    /// chatty room nor a chatty peer can starve the other
+    rx_first: bool,
 }
 
-impl HTTPState {
-    pub fn new() -> Self {
-        HTTPState {
-            csprng: rand::SeedableRng::from_rng(rand::thread_rng()).unwrap(),
-            sessions: HashMap::new(),
-            tokens: HashMap::new(),
-            timeouts: DelayQueue::new(),
+impl<S> LinePeer<S> {
+    async fn new(state: GameState, lines: Framed<S, LinesCodec>, person: &Person) -> Self {
+        let rx = {
+            let mut state = state.lock().await;
+            let (tx, rx) = state.message_queue();
+            state.register_connection(person.id, person.conn.clone(), tx);
+            rx
+        };
+
+        LinePeer {
+            lines,
+            rx,
+            rx_first: true,
         }
     }
 
-    fn gen_token(&mut self) -> String {
-        // generate random value
-        let mut buf: [u8; 16] = [0; 16];
-        self.csprng.fill_bytes(&mut buf);
-
-        // make it text
-        base64::encode(buf)
+    /// The next queued message, if one is waiting right now
+    fn ready_message(&mut self) -> Option<Message> {
+        self.rx.try_recv().ok()
     }
+}
 
-    pub fn gen_session_id_for(&mut self, id: PersonId) -> CSRFToken {
-        let session = self.gen_token();
+impl<S: Transport> Stream for LinePeer<S> {
+    type Item = Result<PeerMessage, LinesCodecError>;
 
-        // record the session
-        self.sessions.insert(session.clone(), id);
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let rx_first = self.rx_first;
+        self.rx_first = !rx_first;
 
-        session
+        if rx_first {
+            if let Poll::Ready(v) = self.poll_outgoing(cx) {
+                return Poll::Ready(v);
+            }
+            self.poll_incoming(cx)
+        } else {
+            if let Poll::Ready(v) = self.poll_incoming(cx) {
+                return Poll::Ready(v);
+            }
+            self.poll_outgoing(cx)
+        }
     }
+}
 
-    pub fn gen_csrf_token_for(&mut self, session: SessionId) -> SessionId {
-        let token = self.gen_token();
+impl<S: Transport> LinePeer<S> {
+    /// Pending messages to send to the peer
+    fn poll_outgoing(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<PeerMessage, LinesCodecError>>> {
+        match Pin::new(&mut self.rx).poll_next(cx) {
+            Poll::Ready(Some(v)) => Poll::Ready(Some(Ok(PeerMessage::SendToPeer(v)))),
+            // the queue closes after a `Logout` (which ends the session
+            // anyway) or when they couldn't keep up with their messages
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
 
-        // record the token for the session
-        // TODO if we already have one... old pages are now out of date... keep a set of them?
-        self.tokens.insert(session.clone(), token.clone());
+    /// Connection-dependent read from the peer
+    fn poll_incoming(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<PeerMessage, LinesCodecError>>> {
+        let result: Option<_> = futures::ready!(Pin::new(&mut self.lines).poll_next(cx));
 
-        token
+        Poll::Ready(match result {
+            Some(Ok(message)) => Some(Ok(PeerMessage::LineFromPeer(message))),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        })
     }
 }
 
-pub async fn http_serve<A: std::net::ToSocketAddrs + std::fmt::Display>(
-    state: Arc<Mutex<State>>,
-    addr_spec: A,
-) -> Result<(), Box<dyn Error + Send>> {
-    let mut addrs = addr_spec.to_socket_addrs().unwrap();
-    let addr = addrs.next().unwrap();
-    assert_eq!(
-        addrs.next(),
-        None,
-        "expected a unique bind location for the HTTP server, but {} resolves to at least two",
-        addr_spec
-    );
-
-    let make_svc = make_service_fn(move |conn: &AddrStream| {
-        let state = state.clone();
-        let remote_addr = conn.remote_addr();
+/// Why a line-oriented session ended early (see `process`)
+#[derive(Debug)]
+pub enum SessionError {
+    LoginAborted(LoginAbortedError),
+    TooManyPasswordAttempts(TooManyPasswordAttemptsError),
+    Banned(BannedError),
+    PasswordsDontMatch(PasswordsDontMatchError),
+    /// Their room was full when they logged in
+    RoomFull(RoomFullError),
+    /// The connection itself failed, e.g., we couldn't find the peer's address
+    Io(io::Error),
+    /// Reading or writing a line failed
+    Codec(LinesCodecError),
+}
 
-        async move {
-            Ok::<_, Infallible>(service_fn(move |req| {
-                http_route(state.clone(), remote_addr, req)
-            }))
+impl SessionError {
+    /// Is this just someone leaving or being turned away, rather than
+    /// something going wrong on our end?
+    pub fn is_routine(&self) -> bool {
+        match self {
+            SessionError::LoginAborted(_)
+            | SessionError::TooManyPasswordAttempts(_)
+            | SessionError::Banned(_)
+            | SessionError::PasswordsDontMatch(_)
+            | SessionError::RoomFull(_) => true,
+            SessionError::Io(e) | SessionError::Codec(LinesCodecError::Io(e)) => is_hangup(e),
+            SessionError::Codec(LinesCodecError::MaxLineLengthExceeded) => false,
         }
-    });
-
-    let server = Server::bind(&addr).serve(make_svc);
-    match server.await {
-        Ok(()) => Ok(()),
-        Err(e) => Err(Box::new(e)),
     }
 }
 
-async fn http_route(
-    state: Arc<Mutex<State>>,
-    client: SocketAddr,
-    req: Request<Body>,
-) -> Result<Response<Body>, Infallible> {
-    let span = span!(Level::INFO, "HTTP request", client = ?client, method = ?req.method(), uri = ?req.uri());
-    let _guard = span.enter();
-
-    let mut resp = Response::new(Body::empty());
+impl Error for SessionError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SessionError::LoginAborted(e) => Some(e),
+            SessionError::TooManyPasswordAttempts(e) => Some(e),
+            SessionError::Banned(e) => Some(e),
+            SessionError::PasswordsDontMatch(e) => Some(e),
+            SessionError::RoomFull(e) => Some(e),
+            SessionError::Io(e) => Some(e),
+            SessionError::Codec(e) => Some(e),
+        }
+    }
+}
 
-    // TODO session info
-    // need to thread a session table through everywhere (keep it separate from the state? it's HTTP only...)
-    // see if cookie exists. if not, generate a new session (and store it in the table)
-    // if so, get peer information appropriately (in the handler? not everyone needs the info...)
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::LoginAborted(e) => e.fmt(f),
+            SessionError::TooManyPasswordAttempts(e) => e.fmt(f),
+            SessionError::Banned(e) => e.fmt(f),
+            SessionError::PasswordsDontMatch(e) => e.fmt(f),
+            SessionError::RoomFull(e) => e.fmt(f),
+            SessionError::Io(e) => write!(f, "Connection error: {}", e),
+            SessionError::Codec(e) => write!(f, "Connection error: {}", e),
+        }
+    }
+}
 
-    trace!("routing");
-    match (req.method(), req.uri().path()) {
-        (&Method::GET, "/") => http_unimplemented(state, req, &mut resp).await,
+impl From<io::Error> for SessionError {
+    fn from(e: io::Error) -> Self {
+        SessionError::Io(e)
+    }
+}
 
-        (&Method::GET, "/register") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/register") => http_unimplemented(state, req, &mut resp).await,
+impl From<LinesCodecError> for SessionError {
+    fn from(e: LinesCodecError) -> Self {
+        SessionError::Codec(e)
+    }
+}
 
-        (&Method::GET, "/user") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::GET, "/room") => http_unimplemented(state, req, &mut resp).await,
+/// Log how a session ended: routine endings aren't errors
+fn log_session_end(result: Result<(), SessionError>) {
+    match result {
+        Ok(()) => (),
+        Err(e) if e.is_routine() => info!(%e, "session ended"),
+        Err(e) => error!(%e, ?e, "session failed"),
+    }
+}
 
-        (&Method::GET, "/who") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::GET, "/help") => http_unimplemented(state, req, &mut resp).await,
+#[derive(Debug)]
+pub struct LoginAbortedError {
+    conn: Connection,
+    name: Option<String>,
+}
 
-        (&Method::GET, "/admin") => http_unimplemented(state, req, &mut resp).await,
+impl Error for LoginAbortedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
 
-        // TODO cache-control on these end points
-        (&Method::GET, "/api/be") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/api/do") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/api/leave") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/api/login") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/api/logout") => http_unimplemented(state, req, &mut resp).await,
-        (&Method::POST, "/api/who") => http_unimplemented(state, req, &mut resp).await,
-        _ => {
-            *resp.status_mut() = StatusCode::NOT_FOUND;
-            *resp.body_mut() = Body::from("404 Not Found");
+impl fmt::Display for LoginAbortedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            None => write!(f, "Login error: connection with {} reset.", self.conn),
+            Some(name) => write!(
+                f,
+                "Login error: connection with {} from {} reset.",
+                name, self.conn
+            ),
         }
-    };
+    }
+}
 
-    info!(status = ?resp.status());
-    Ok(resp)
+#[derive(Debug)]
+pub struct TooManyPasswordAttemptsError {
+    conn: Connection,
+    name: String,
 }
 
-async fn http_unimplemented(
-    _state: Arc<Mutex<State>>,
-    _req: Request<Body>,
-    resp: &mut Response<Body>,
-) {
-    *resp.status_mut() = StatusCode::NOT_IMPLEMENTED;
-    *resp.body_mut() = Body::from("501 Not Implemented");
+impl Error for TooManyPasswordAttemptsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for TooManyPasswordAttemptsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Login error: too many password attempts as {} from {}; connection reset.",
+            self.name, self.conn
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct BannedError {
+    conn: Connection,
+    name: String,
+}
+
+impl Error for BannedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for BannedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Login error: {} is banned; connection from {} reset.",
+            self.name, self.conn
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct PasswordsDontMatchError {
+    conn: Connection,
+    name: String,
+}
+
+impl Error for PasswordsDontMatchError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for PasswordsDontMatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Registration error: passwords don't match for {} on {}.",
+            self.name, self.conn
+        )
+    }
+}
+
+/// Ask for a line until `valid` accepts it, telling the peer why each rejected
+/// line was no good
+pub async fn prompt<S, F, Ferr, Ftimeout>(
+    lines: &mut Framed<S, LinesCodec>,
+    prompt: &str,
+    valid: F,
+    check_tries: Ferr,
+    timeout: Ftimeout,
+) -> Result<String, SessionError>
+where
+    S: Transport,
+    F: Fn(&str) -> Result<(), String>,
+    Ferr: Fn(usize) -> Option<SessionError>,
+    Ftimeout: FnOnce() -> SessionError,
+{
+    let mut num_tries = 0;
+    loop {
+        lines.send(prompt).await?;
+
+        match lines.next().await {
+            Some(Ok(line)) => {
+                let line = line.trim();
+
+                let reprompt = match valid(line) {
+                    Ok(()) => return Ok(line.to_string()),
+                    Err(reprompt) => reprompt,
+                };
+
+                num_tries += 1;
+                if let Some(error) = check_tries(num_tries) {
+                    return Err(error);
+                }
+
+                lines.send(reprompt).await?;
+            }
+            _ => return Err(timeout()),
+        }
+    }
+}
+
+/// How many times someone registering may mistype their password's
+/// confirmation before we hang up
+const MAX_PASSWORD_MISMATCHES: usize = 5;
+
+/// What the name someone gave at login turned out to be
+enum Account {
+    Existing(PersonRecord),
+    /// A free name, held for them while they register it
+    New(Reservation),
+}
+
+pub async fn login<S: Transport>(
+    state: GameState,
+    lines: &mut Framed<S, LinesCodec>,
+    conn: Connection,
+) -> Result<Person, SessionError> {
+    // TODO welcome header (instructions come after login; see `Config::greeting`)
+
+    let mut name = prompt(
+        lines,
+        "What is your email address or Twitter handle? ",
+        validate_name,
+        |_| None, // unlimited tries
+        || {
+            SessionError::LoginAborted(LoginAbortedError {
+                conn: conn.clone(),
+                name: None,
+            })
+        },
+    )
+    .await?;
+
+    let (open_registration, max_password_attempts) = {
+        let state = state.lock().await;
+        (state.open_registration(), state.max_password_attempts())
+    };
+    if !open_registration {
+        return login_closed(state, lines, conn, name, max_password_attempts).await;
+    }
+
+    // hold on to unknown names while we register them, so two people can't
+    // sign up with the same name at once
+    let account = loop {
+        if let Some(person) = state.person_by_name(&name) {
+            break Account::Existing(person);
+        }
+        if let Some(reservation) = state.reserve_name(&name) {
+            break Account::New(reservation);
+        }
+
+        info!("{} is mid-registration", name);
+        lines
+            .send("Someone else is registering that name right now.")
+            .await?;
+        name = prompt(
+            lines,
+            "What is your email address or Twitter handle? ",
+            validate_name,
+            |_| None,
+            || {
+                SessionError::LoginAborted(LoginAbortedError {
+                    conn: conn.clone(),
+                    name: None,
+                })
+            },
+        )
+        .await?;
+    };
+
+    match account {
+        Account::Existing(person) => {
+            info!(person.id, "found {}", person.name);
+
+            refuse_if_banned(lines, &person, &conn).await?;
+
+            // verifying is slow, so it can't happen in `prompt`'s check
+            let mut failed_tries = 0;
+            let password = loop {
+                let password = prompt(
+                    lines,
+                    "Password: ",
+                    |_| Ok(()),
+                    |_| None,
+                    || {
+                        SessionError::LoginAborted(LoginAbortedError {
+                            conn: conn.clone(),
+                            name: Some(name.clone()),
+                        })
+                    },
+                )
+                .await?;
+
+                if state.check_password(&name, &password).await.is_some() {
+                    break password;
+                }
+
+                failed_tries += 1;
+                if failed_tries >= max_password_attempts {
+                    return Err(SessionError::TooManyPasswordAttempts(
+                        TooManyPasswordAttemptsError { name, conn },
+                    ));
+                }
+                lines.send("Password incorrect.").await?;
+            };
+
+            // bring the hash up to the current policy while we have the plaintext
+            if state.needs_rehash(&person.password) {
+                info!(person.id, "rehashing password");
+                state.set_password(person.id, &password).await;
+            }
+
+            return Ok(Person::new(&person, conn));
+        }
+        Account::New(reservation) => {
+            let mut mismatches = 0;
+            loop {
+                info!("no user {}, registering", name);
+
+                lines.send("You must be new here!").await?;
+
+                let password1 = prompt(
+                    lines,
+                    "Please enter a password: ",
+                    |password| {
+                        if password.len() >= 8 {
+                            Ok(())
+                        } else {
+                            Err("That is not a valid password. It should be at least 8 characters."
+                                .to_string())
+                        }
+                    },
+                    |_| None,
+                    || {
+                        SessionError::LoginAborted(LoginAbortedError {
+                            conn: conn.clone(),
+                            name: Some(name.clone()),
+                        })
+                    },
+                )
+                .await?;
+
+                lines.send("Please re-enter your password: ").await?;
+
+                match lines.next().await {
+                    Some(Ok(password2)) => {
+                        if password1 != password2.trim() {
+                            lines.send("Passwords don't match.").await?;
+                            mismatches += 1;
+                            if mismatches >= MAX_PASSWORD_MISMATCHES {
+                                return Err(SessionError::PasswordsDontMatch(
+                                    PasswordsDontMatchError { conn, name },
+                                ));
+                            }
+                            continue;
+                        }
+
+                        let person = state.register(reservation, &password1).await;
+                        return Ok(Person::new(&person, conn));
+                    }
+                    _ => {
+                        return Err(SessionError::LoginAborted(LoginAbortedError {
+                            conn,
+                            name: Some(name),
+                        }))
+                    }
+                }
+            }
+        }
+    };
+}
+
+
+/// Log in when registration is closed: unknown names fail just like wrong
+/// passwords, so nobody can find out who has an account
+async fn login_closed<S: Transport>(
+    state: GameState,
+    lines: &mut Framed<S, LinesCodec>,
+    conn: Connection,
+    mut name: String,
+    max_attempts: usize,
+) -> Result<Person, SessionError> {
+    let mut failures = 0;
+    loop {
+        let password = prompt(
+            lines,
+            "Password: ",
+            |_| Ok(()),
+            |_| None,
+            || {
+                SessionError::LoginAborted(LoginAbortedError {
+                    conn: conn.clone(),
+                    name: Some(name.clone()),
+                })
+            },
+        )
+        .await?;
+
+        let person = state.check_password(&name, &password).await;
+
+        if let Some(person) = person {
+            info!(person.id, "found {}", person.name);
+            refuse_if_banned(lines, &person, &conn).await?;
+
+            // bring the hash up to the current policy while we have the plaintext
+            if state.needs_rehash(&person.password) {
+                info!(person.id, "rehashing password");
+                state.set_password(person.id, &password).await;
+            }
+
+            return Ok(Person::new(&person, conn));
+        }
+
+        info!("login failed for {}", name);
+        lines.send("Login failed.").await?;
+        failures += 1;
+        if failures >= max_attempts {
+            return Err(SessionError::TooManyPasswordAttempts(
+                TooManyPasswordAttemptsError { name, conn },
+            ));
+        }
+
+        name = prompt(
+            lines,
+            "What is your email address or Twitter handle? ",
+            validate_name,
+            |_| None,
+            || {
+                SessionError::LoginAborted(LoginAbortedError {
+                    conn: conn.clone(),
+                    name: None,
+                })
+            },
+        )
+        .await?;
+    }
+}
+
+/// Tell banned people why they can't come in (and end their login)
+async fn refuse_if_banned<S: Transport>(
+    lines: &mut Framed<S, LinesCodec>,
+    person: &PersonRecord,
+    conn: &Connection,
+) -> Result<(), SessionError> {
+    if !person.banned {
+        return Ok(());
+    }
+
+    let why = match &person.ban_reason {
+        Some(reason) => format!("You are banned: {}", reason),
+        None => "You are banned.".to_string(),
+    };
+    lines.send(why).await?;
+    Err(SessionError::Banned(BannedError {
+        conn: conn.clone(),
+        name: person.name.clone(),
+    }))
+}
+
+/// Run a line-oriented session (login, then commands) over `stream`
+pub async fn process<S: Transport>(state: GameState, stream: S) -> Result<(), SessionError> {
+    let conn = stream.connection()?;
+    let max_line_length = state.lock().await.max_line_length();
+    let mut lines = Framed::new(stream, LinesCodec::new_with_max_length(max_line_length));
+
+    let login_span = span!(Level::INFO, "login/registration", %conn);
+    let mut person = login_span
+        .in_scope(|| login(state.clone(), &mut lines, conn.clone()))
+        .await?;
+    lines.send(format!("Logged in as {}...", person.name)).await?;
+    if let Some((at, from)) = state.record_login(person.id, &conn) {
+        lines.send(last_login_notice(at, &from)).await?;
+    }
+    let (greeting, motd, shutdown_in) = {
+        let state = state.lock().await;
+        (state.greeting(), state.motd(), state.shutdown_in())
+    };
+    if let Some(greeting) = greeting {
+        lines.send(greeting).await?;
+    }
+    if let Some(motd) = motd {
+        lines.send(motd).await?;
+    }
+    if let Some(left) = shutdown_in {
+        lines.send(restart_notice(left)).await?;
+    }
+
+    let span = span!(Level::INFO, "session", id = person.id);
+    let _guard = span.enter();
+    info!("logged in");
+    
+    let mut peer = LinePeer::new(state.clone(), lines, &person).await;
+    let (mut flood, require_slash, mut idle) = {
+        let state = state.lock().await;
+        person.render.timestamp_format = state.timestamp_format();
+        (
+            state.flood_control(),
+            state.require_slash(),
+            IdleClock::new(state.idle_limits()),
+        )
+    };
+
+    let arrived = {
+        let mut state = state.lock().await;
+        let arrived = state.rejoin(&mut person).await;
+        if arrived.is_ok() {
+            state.look(&person);
+        }
+        arrived
+    };
+    if let Err(e) = arrived {
+        state.lock().await.unregister_connection(person.id);
+        peer.lines.send(e.to_string()).await?;
+        return Err(SessionError::RoomFull(e));
+    }
+
+    // every way out but logging out (which cleans up after itself) has to
+    // leave the room, or they'll haunt it
+    let ended = async {
+        loop {
+            let result = match idle.next_alarm() {
+                None => peer.next().await,
+                Some((at, alarm)) => match tokio::time::timeout_at(at.into(), peer.next()).await {
+                    Ok(result) => result,
+                    Err(_) => match alarm {
+                        IdleAlarm::Warn(left) => {
+                            idle.warned = true;
+                            let warning = format!(
+                                "You will be disconnected in {} due to inactivity.",
+                                format_duration(left)
+                            );
+                            peer.lines.send(warning).await?;
+                            continue;
+                        }
+                        IdleAlarm::Kick => {
+                            info!(id = person.id, "idle");
+                            peer.lines
+                                .send("You have been disconnected for inactivity.")
+                                .await?;
+                            break;
+                        }
+                    },
+                },
+            };
+            let result = match result {
+                Some(result) => result,
+                None => break,
+            };
+
+            match result {
+                Ok(PeerMessage::LineFromPeer(msg)) => {
+                    state.mark_active(person.id);
+                    idle.reset();
+
+                    // render the error before awaiting: `Box<dyn Error>` isn't `Send`
+                    let line = sanitize(&msg);
+                    let cmd = Command::parse_with(line.clone(), require_slash)
+                        .map_err(|e| e.to_string());
+                    if cmd.as_ref().map(Command::is_recallable).unwrap_or(true) {
+                        state.remember(person.id, &line);
+                    }
+                    let cmd = match cmd {
+                        Ok(cmd) => cmd,
+                        Err(e) => {
+                            peer.lines.send(e).await?;
+                            continue;
+                        }
+                    };
+
+                    if cmd.is_speech() && !flood.allow() {
+                        trace!("flood control");
+                        peer.lines.send("You are sending messages too quickly.").await?;
+                        continue;
+                    }
+
+                    cmd.run(state.clone(), &mut person).await;
+                }
+
+                Ok(PeerMessage::SendToPeer(msg)) => {
+                    // write out everything that's already waiting at once,
+                    // rather than a syscall per line
+                    let mut next = Some(msg);
+                    let mut lines = Vec::new();
+                    let mut batched = 0;
+                    let mut logged_out = false;
+                    while let Some(msg) = next.take() {
+                        if msg.for_terminals() {
+                            lines.push(msg.render_with_opts(person.id, &person.render).await);
+                        }
+
+                        if let Message::Logout = msg {
+                            logged_out = true;
+                            break;
+                        }
+
+                        batched += 1;
+                        if batched < MAX_BATCHED_MESSAGES {
+                            next = peer.ready_message();
+                        }
+                    }
+                    if !lines.is_empty() {
+                        // the codec ends the last line for us
+                        peer.lines.send(lines.join("\n")).await?;
+                    }
+
+                    if logged_out {
+                        info!(id = person.id, "logout");
+                        if let Err(e) = peer.lines.get_mut().shutdown().await {
+                            error!(?e, id = person.id, "logout");
+                        }
+                        return Ok(true);
+                    }
+                }
+
+                // the codec skips the rest of the line, so we can carry on
+                Err(LinesCodecError::MaxLineLengthExceeded) => {
+                    peer.lines.send("Message too long.").await?;
+                }
+
+                Err(e) => {
+                    error!(?e, id = person.id);
+                }
+            }
+        }
+        Ok::<bool, LinesCodecError>(false)
+    }
+    .await;
+    if let Ok(true) = ended {
+        return Ok(());
+    }
+
+    {
+        let mut state = state.lock().await;
+
+        // actually log them off
+        state.unregister_connection(person.id);
+
+        // announce it to everyone
+        state.depart(&person, DepartReason::Disconnected).await;
+    }
+    info!(id = person.id, "logout (disconnected)");
+
+    trace!("disconnected");
+    match ended {
+        // they're gone, and we've cleaned up after them: that's not an error
+        Err(LinesCodecError::Io(e)) if is_hangup(&e) => {
+            info!(id = person.id, ?e, "connection lost");
+            Ok(())
+        }
+        ended => {
+            ended?;
+            Ok(())
+        }
+    }
+}
+
+/// How long a line-oriented session has gone without input: first a warning
+/// comes due, then the disconnect. Only lines they send wind it back, not
+/// messages sent to them.
+struct IdleClock {
+    /// The timeout and how long before it to warn (`None` means never)
+    limits: Option<(Duration, Duration)>,
+    last_input: Instant,
+    warned: bool,
+}
+
+/// What's due when an `IdleClock` runs out
+enum IdleAlarm {
+    /// Warn them, with this long left
+    Warn(Duration),
+    Kick,
+}
+
+impl IdleClock {
+    fn new(limits: Option<(Duration, Duration)>) -> Self {
+        IdleClock {
+            limits,
+            last_input: Instant::now(),
+            warned: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.last_input = Instant::now();
+        self.warned = false;
+    }
+
+    /// When the next alarm goes off, and what it's for
+    fn next_alarm(&self) -> Option<(Instant, IdleAlarm)> {
+        let (timeout, warning) = self.limits?;
+        let kick = self.last_input + timeout;
+        if self.warned || warning == Duration::from_secs(0) {
+            Some((kick, IdleAlarm::Kick))
+        } else {
+            Some((kick - warning, IdleAlarm::Warn(warning)))
+        }
+    }
+}
+
+/// Does `e` just mean the other end went away (e.g., writing to a client
+/// that vanished without closing its connection)?
+fn is_hangup(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::BrokenPipe
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Every distinct address `addr` resolves to, e.g., both the IPv4 and IPv6
+/// addresses of a dual-stack hostname
+async fn resolve_all<A: ToSocketAddrs>(addr: A) -> io::Result<Vec<SocketAddr>> {
+    let mut addrs = Vec::new();
+    for addr in lookup_host(addr).await? {
+        if !addrs.contains(&addr) {
+            addrs.push(addr);
+        }
+    }
+
+    if addrs.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrNotAvailable,
+            "address resolved to nothing",
+        ));
+    }
+
+    Ok(addrs)
+}
+
+/// Look up `addr`'s hostname in the background, if `--resolve-hostnames` is
+/// on, so it's in the logs (and cached for `sessions`) without holding up the
+/// connection
+fn log_hostname(state: &GameState, addr: SocketAddr) {
+    if !state.resolves_hostnames() {
+        return;
+    }
+
+    let state = state.clone();
+    tokio::spawn(async move {
+        if let Some(host) = state.hostname(addr.ip()).await {
+            info!(?addr, %host, "resolved");
+        }
+    });
+}
+
+/// How long a TCP connection may sit idle before the OS checks that the other
+/// end is still there, so clients that vanish without a word (e.g., a laptop
+/// closing) get departed even if nobody writes to them
+const TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+
+/// Listen for TCP connections on every address `addr` resolves to, speaking
+/// TLS if given an acceptor
+pub async fn tcp_serve<A: ToSocketAddrs>(
+    state: GameState,
+    addr: A,
+    tls: Option<TlsAcceptor>,
+) -> io::Result<()> {
+    let mut listeners = Vec::new();
+    for addr in resolve_all(addr).await? {
+        listeners.push(TcpListener::bind(addr).await?);
+        info!(%addr, "TCP server listening");
+    }
+
+    // every address shares one limit
+    let limit = ConnectionLimit::new(state.lock().await.max_connections());
+
+    futures::future::try_join_all(listeners.into_iter().map(|listener| {
+        tcp_listen(state.clone(), listener, tls.clone(), limit.clone())
+    }))
+    .await?;
+    Ok(())
+}
+
+/// Counts live TCP connections against `--max-connections`
+#[derive(Clone)]
+struct ConnectionLimit {
+    live: Arc<AtomicUsize>,
+    max: Option<usize>,
+}
+
+/// One live connection's place under the `ConnectionLimit`, given back when
+/// it drops, however the session ends
+struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionLimit {
+    fn new(max: Option<usize>) -> Self {
+        ConnectionLimit {
+            live: Arc::new(AtomicUsize::new(0)),
+            max,
+        }
+    }
+
+    /// A slot for a new connection, if there's room
+    fn claim(&self) -> Option<ConnectionSlot> {
+        let live = self.live.fetch_add(1, Ordering::SeqCst);
+        if matches!(self.max, Some(max) if live >= max) {
+            self.live.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        Some(ConnectionSlot(self.live.clone()))
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Run a session if there's a slot for it; otherwise, apologize and hang up
+async fn process_or_refuse<S: Transport>(
+    state: GameState,
+    mut stream: S,
+    slot: Option<ConnectionSlot>,
+) -> Result<(), SessionError> {
+    match slot {
+        Some(_slot) => process(state, stream).await,
+        None => {
+            stream
+                .write_all(b"Server is full, please try again later.\n")
+                .await?;
+            stream.shutdown().await?;
+            Ok(())
+        }
+    }
+}
+
+async fn tcp_listen(
+    state: GameState,
+    mut listener: TcpListener,
+    tls: Option<TlsAcceptor>,
+    limit: ConnectionLimit,
+) -> io::Result<()> {
+    let mut shutdown = state.lock().await.shutdown_signal();
+
+    loop {
+        let (stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.recv() => {
+                info!("TCP server stopped");
+                return Ok(());
+            }
+        };
+
+        let span = span!(Level::INFO, "TCP connection");
+        let _guard = span.enter();
+        info!(?addr, "connected");
+        log_hostname(&state, addr);
+        if let Err(e) = stream.set_keepalive(Some(TCP_KEEPALIVE)) {
+            error!(?e, ?addr, "couldn't set keepalive");
+        }
+
+        let slot = limit.claim();
+        if slot.is_none() {
+            info!(?addr, "server full, refusing");
+        }
+
+        let state = state.clone();
+        let tls = tls.clone();
+        tokio::spawn(async move {
+            // handshake here, so a slow client can't hold up the listener
+            let result = match tls {
+                None => process_or_refuse(state, stream, slot).await,
+                Some(tls) => match tls.accept(stream).await {
+                    Ok(stream) => process_or_refuse(state, stream, slot).await,
+                    Err(e) => Err(e.into()),
+                },
+            };
+            log_session_end(result);
+        });
+    }
+}
+
+/// How long a connection on the unified port has to start an HTTP request
+/// before we take it for a line-oriented session: telnet clients wait for us
+/// to speak first, so they'll send nothing at all
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// How HTTP requests start (i.e., a method and a space)
+const HTTP_METHODS: &[&[u8]] = &[
+    b"GET ", b"HEAD ", b"POST ", b"PUT ", b"DELETE ", b"OPTIONS ", b"PATCH ",
+];
+
+/// Does the client on `stream` seem to be speaking HTTP? This only peeks, so
+/// whoever handles the connection reads it from the start.
+async fn sniff_http(stream: &mut TcpStream) -> io::Result<bool> {
+    let mut buf = [0; 8];
+    let sniff = async {
+        loop {
+            let n = stream.peek(&mut buf).await?;
+            let start = &buf[..n];
+            if start.is_empty() {
+                return Ok(false);
+            }
+            if HTTP_METHODS.iter().any(|method| start.starts_with(method)) {
+                return Ok(true);
+            }
+            if !HTTP_METHODS.iter().any(|method| method.starts_with(start)) {
+                return Ok(false);
+            }
+
+            // part of a method so far: wait for the rest
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+    };
+
+    match tokio::time::timeout(SNIFF_TIMEOUT, sniff).await {
+        Ok(result) => result,
+        Err(_) => Ok(false),
+    }
+}
+
+/// Serve both line-oriented sessions and HTTP on every address `addr`
+/// resolves to, for operators who can only expose one port. Connections that
+/// start with an HTTP request go to the web server; everything else gets a
+/// session as on the TCP port (without TLS).
+pub async fn unified_serve<A: ToSocketAddrs>(state: GameState, addr: A) -> io::Result<()> {
+    let mut listeners = Vec::new();
+    for addr in resolve_all(addr).await? {
+        listeners.push(TcpListener::bind(addr).await?);
+        info!(%addr, "unified server listening");
+    }
+
+    let (limit, metrics) = {
+        let state = state.lock().await;
+        (ConnectionLimit::new(state.max_connections()), state.metrics())
+    };
+    let http = Arc::new(Mutex::new(HTTPState::new(metrics.clone())));
+
+    let servers = futures::future::try_join_all(listeners.into_iter().map(|listener| {
+        unified_listen(
+            state.clone(),
+            listener,
+            http.clone(),
+            metrics.clone(),
+            limit.clone(),
+        )
+    }));
+
+    // the sweep never finishes, so we're done when the listeners are
+    tokio::select! {
+        result = servers => result.map(|_| ()),
+        _ = http_sweep(state, http) => Ok(()),
+    }
+}
+
+async fn unified_listen(
+    state: GameState,
+    mut listener: TcpListener,
+    http: Arc<Mutex<HTTPState>>,
+    metrics: Arc<Metrics>,
+    limit: ConnectionLimit,
+) -> io::Result<()> {
+    let mut shutdown = state.lock().await.shutdown_signal();
+
+    loop {
+        let (mut stream, addr) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.recv() => {
+                info!("unified server stopped");
+                return Ok(());
+            }
+        };
+
+        let span = span!(Level::INFO, "unified connection");
+        let _guard = span.enter();
+        info!(?addr, "connected");
+        log_hostname(&state, addr);
+        if let Err(e) = stream.set_keepalive(Some(TCP_KEEPALIVE)) {
+            error!(?e, ?addr, "couldn't set keepalive");
+        }
+
+        let state = state.clone();
+        let http = http.clone();
+        let metrics = metrics.clone();
+        let limit = limit.clone();
+        tokio::spawn(async move {
+            // sniff here, so a quiet client can't hold up the listener
+            match sniff_http(&mut stream).await {
+                Ok(true) => {
+                    trace!(?addr, "speaking HTTP");
+                    let service = service_fn(move |req| {
+                        http_route(state.clone(), http.clone(), metrics.clone(), addr, req)
+                    });
+                    if let Err(e) = Http::new().serve_connection(stream, service).await {
+                        error!(?e);
+                    }
+                }
+                Ok(false) => {
+                    let slot = limit.claim();
+                    if slot.is_none() {
+                        info!(?addr, "server full, refusing");
+                    }
+                    log_session_end(process_or_refuse(state, stream, slot).await);
+                }
+                Err(e) => log_session_end(Err(e.into())),
+            }
+        });
+    }
+}
+
+/// Listen for local connections on the Unix domain socket at `path`, removing
+/// the socket file when the server shuts down
+#[cfg(unix)]
+pub async fn uds_serve(state: GameState, path: PathBuf) -> io::Result<()> {
+    // a stale socket from an unclean exit would make the bind fail
+    remove_stale_socket(&path)?;
+
+    let mut listener = UnixListener::bind(&path)?;
+    info!(path = %path.display(), "Unix socket server listening");
+
+    let mut shutdown = state.lock().await.shutdown_signal();
+    let result = loop {
+        let stream = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _addr)) => stream,
+                Err(e) => break Err(e),
+            },
+            _ = shutdown.recv() => {
+                info!("Unix socket server stopped");
+                break Ok(());
+            }
+        };
+
+        let span = span!(Level::INFO, "Unix socket connection");
+        let _guard = span.enter();
+        info!(path = %path.display(), "connected");
+
+        let state = state.clone();
+        tokio::spawn(async move {
+            log_session_end(process(state, stream).await);
+        });
+    };
+
+    remove_socket_file(&path);
+    result
+}
+
+/// Clear away a socket left at `path`, but nothing else: a mistyped
+/// `--admin-socket` shouldn't delete someone's file
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+        Ok(meta) if meta.file_type().is_socket() => {
+            remove_socket_file(path);
+            Ok(())
+        }
+        Ok(_) => Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and isn't a socket", path.display()),
+        )),
+    }
+}
+
+#[cfg(unix)]
+fn remove_socket_file(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => trace!(path = %path.display(), "removed socket file"),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+        Err(e) => error!(?e, path = %path.display(), "couldn't remove socket file"),
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// HTTP STUFF
+////////////////////////////////////////////////////////////////////////////////
+
+/// The cookie in which we store sessions
+const SESSIONID: &'static str = "id";
+
+/// The name of the CSRF token variable for POST requests
+const CSRFTOKEN: &'static str = "tok";
+
+/// The cookie holding the registration form's CSRF token, since there's no
+/// session yet to keep it in
+const REGISTERTOKEN: &str = "regtok";
+
+/// Time-to-live in a room between calls to `/api/be`
+const HTTP_TTL_SECS: u64 = 30;
+
+/// How long `/api/be` waits for something to happen before answering with
+/// nothing; comfortably less than the TTL, so a polling client stays present
+const HTTP_POLL_SECS: u64 = 25;
+
+/// How long a session lasts without any requests, when they asked us to
+/// remember them at login (otherwise, it's `--session-timeout`)
+const HTTP_REMEMBER_SECS: u64 = 30 * 24 * 60 * 60;
+
+pub type SessionId = String;
+
+type CSRFToken = String;
+
+pub struct HTTPState {
+    /// CSPRNG for session and CSRF tokens
+    csprng: rand::rngs::StdRng,
+    sessions: HashMap<SessionId, HTTPSession>,
+    tokens: HashMap<SessionId, CSRFToken>,
+    /// Where we count `sessions`
+    metrics: Arc<Metrics>,
+}
+
+/// A web user, logged in via `/api/login`
+///
+/// Web users are in the world only while they keep polling `/api/be`; once
+/// they've been quiet for `HTTP_TTL_SECS`, they leave their room, and their
+/// next request brings them back.
+struct HTTPSession {
+    person: Person,
+    /// Receive-end of the message queue, when they're in the world
+    ///
+    /// It's shared so `/api/be` can wait on it without holding the `HTTPState`
+    /// lock.
+    rx: Option<Arc<Mutex<MessageQueueRX>>>,
+    flood: FloodControl,
+    /// The last hit on `/api/be` or `/api/do`
+    last_seen: Instant,
+    /// How long the session lasts past `last_seen` (see `--session-timeout`
+    /// and `HTTP_REMEMBER_SECS`)
+    lifetime: Duration,
+    /// When the session ends, unless they come back first
+    expires: Instant,
+}
+
+impl HTTPState {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        HTTPState {
+            csprng: rand::SeedableRng::from_rng(rand::thread_rng()).unwrap(),
+            sessions: HashMap::new(),
+            tokens: HashMap::new(),
+            metrics,
+        }
+    }
+
+    fn update_gauges(&self) {
+        self.metrics
+            .http_sessions
+            .store(self.sessions.len(), Ordering::Relaxed);
+    }
+
+    fn gen_token(&mut self) -> String {
+        // generate random value
+        let mut buf: [u8; 16] = [0; 16];
+        self.csprng.fill_bytes(&mut buf);
+
+        // make it text that's safe in cookies and form fields
+        base64::encode_config(buf, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Start a session for `person`, who isn't in the world yet, lasting for
+    /// `lifetime` past each request
+    fn gen_session_id_for(
+        &mut self,
+        person: &PersonRecord,
+        flood: FloodControl,
+        lifetime: Duration,
+    ) -> SessionId {
+        let session = self.gen_token();
+        let now = Instant::now();
+
+        // record the session
+        let conn = Connection::HTTP {
+            session: session.clone(),
+        };
+        self.sessions.insert(
+            session.clone(),
+            HTTPSession {
+                person: Person::new(person, conn),
+                rx: None,
+                flood,
+                last_seen: now,
+                lifetime,
+                expires: now + lifetime,
+            },
+        );
+        self.update_gauges();
+
+        session
+    }
+
+    pub fn gen_csrf_token_for(&mut self, session: SessionId) -> CSRFToken {
+        let token = self.gen_token();
+
+        // record the token for the session
+        // TODO if we already have one... old pages are now out of date... keep a set of them?
+        self.tokens.insert(session, token.clone());
+
+        token
+    }
+
+    fn end_session(&mut self, session: &SessionId) {
+        self.sessions.remove(session);
+        self.tokens.remove(session);
+        self.update_gauges();
+    }
+
+    /// End `session`, taking them out of the world if they're in it
+    async fn forget_session(&mut self, state: &GameState, session: &SessionId) {
+        if let Some(s) = self.sessions.get(session) {
+            if s.rx.is_some() {
+                let mut state = state.lock().await;
+                if state.is_connected(s.person.id) {
+                    state.unregister_connection(s.person.id);
+                    state.depart(&s.person, DepartReason::Disconnected).await;
+                }
+            }
+        }
+        self.end_session(session);
+    }
+
+    /// The session for a request's cookie, unless it's expired; expired
+    /// sessions are forgotten now, rather than waiting for the sweep
+    async fn live_session(
+        &mut self,
+        state: &GameState,
+        session: &SessionId,
+    ) -> Result<&mut HTTPSession, HttpError> {
+        let expires = self
+            .sessions
+            .get(session)
+            .ok_or(HttpError::Unauthorized)?
+            .expires;
+        if expires <= Instant::now() {
+            if let Some(s) = self.sessions.get(session) {
+                info!(id = s.person.id, "HTTP session expired");
+            }
+            self.forget_session(state, session).await;
+            return Err(HttpError::Unauthorized);
+        }
+
+        self.sessions.get_mut(session).ok_or(HttpError::Unauthorized)
+    }
+}
+
+impl HTTPSession {
+    /// Note a request on the session, pushing back when it expires
+    fn touch(&mut self) {
+        self.last_seen = Instant::now();
+        self.expires = self.last_seen + self.lifetime;
+    }
+
+    /// Put them (back) in the world: their connection, their room, and a
+    /// fresh message queue
+    async fn join(&mut self, state: &GameState) -> Result<(), HttpError> {
+        let id = self.person.id;
+
+        let mut state = state.lock().await;
+        // they may have deleted their account from elsewhere
+        if state.person_by_id(id).is_none() {
+            return Err(HttpError::Unauthorized);
+        }
+
+        let (tx, rx) = state.message_queue();
+        state.register_connection(id, self.person.conn.clone(), tx);
+
+        if let Err(e) = state.rejoin(&mut self.person).await {
+            state.unregister_connection(id);
+            return Err(HttpError::Unavailable(e.to_string()));
+        }
+        state.look(&self.person);
+
+        self.rx = Some(Arc::new(Mutex::new(rx)));
+        Ok(())
+    }
+}
+
+/// Tell everyone how many people are online every `interval`, until the
+/// server shuts down. Terminals don't see it; it's for web clients, and for
+/// the proxies between us and them that drop quiet connections.
+async fn heartbeat(state: GameState, interval: Duration) {
+    let mut shutdown = state.lock().await.shutdown_signal();
+    let mut interval = tokio::time::interval(interval);
+    // the first tick is immediate, and nobody is online yet
+    interval.tick().await;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => (),
+            _ = shutdown.recv() => return,
+        }
+
+        let mut state = state.lock().await;
+        let online = state.stats().connected;
+        state.broadcast(Message::Heartbeat { online }).await;
+    }
+}
+
+/// Save every `interval`, if anything has changed, and once more when the
+/// server shuts down, so a crash loses at most one interval's changes
+async fn autosave(state: GameState, interval: Duration) {
+    let mut shutdown = state.lock().await.shutdown_signal();
+    let mut interval = tokio::time::interval(interval);
+    // the first tick is immediate, and nothing has changed yet
+    interval.tick().await;
+
+    loop {
+        let stopping = tokio::select! {
+            _ = interval.tick() => false,
+            _ = shutdown.recv() => true,
+        };
+
+        match state.autosave().await {
+            Ok(None) => trace!("nothing to autosave"),
+            Ok(Some(people)) => debug!(people, "autosaved"),
+            Err(e) => error!(%e, "autosave failed"),
+        }
+
+        if stopping {
+            return;
+        }
+    }
+}
+
+/// Take web users who've stopped polling out of the world, and forget
+/// sessions that have expired
+async fn http_sweep(state: GameState, http: Arc<Mutex<HTTPState>>) {
+    let ttl = Duration::from_secs(HTTP_TTL_SECS);
+    let session_timeout = state.lock().await.session_timeout();
+    let mut interval = tokio::time::interval(ttl.min(session_timeout) / 6);
+
+    loop {
+        interval.tick().await;
+
+        let mut http = http.lock().await;
+        for session in http.sessions.values_mut() {
+            if session.rx.is_none() || session.last_seen.elapsed() < ttl {
+                continue;
+            }
+
+            session.rx = None;
+
+            let mut state = state.lock().await;
+            state.unregister_connection(session.person.id);
+            state
+                .depart(&session.person, DepartReason::Disconnected)
+                .await;
+            info!(id = session.person.id, "HTTP session idle");
+        }
+
+        let now = Instant::now();
+        let expired: Vec<SessionId> = http
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.expires <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            if let Some(session) = http.sessions.get(&id) {
+                info!(id = session.person.id, "HTTP session expired");
+            }
+            http.forget_session(&state, &id).await;
+        }
+    }
+}
+
+/// Serve HTTP on every address `addr` resolves to
+pub async fn http_serve<A: ToSocketAddrs>(
+    state: GameState,
+    addr: A,
+) -> Result<(), Box<dyn Error + Send>> {
+    let addrs = match resolve_all(addr).await {
+        Ok(addrs) => addrs,
+        Err(e) => return Err(Box::new(e)),
+    };
+
+    let (metrics, shutdown) = {
+        let state = state.lock().await;
+        (state.metrics(), state.shutdown_signal())
+    };
+
+    let http = Arc::new(Mutex::new(HTTPState::new(metrics.clone())));
+
+    let mut servers = Vec::new();
+    for addr in addrs {
+        let state = state.clone();
+        let http = http.clone();
+        let metrics = metrics.clone();
+        let mut shutdown = shutdown.clone();
+
+        let make_svc = make_service_fn(move |conn: &AddrStream| {
+            let state = state.clone();
+            let http = http.clone();
+            let metrics = metrics.clone();
+            let remote_addr = conn.remote_addr();
+
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req| {
+                    http_route(
+                        state.clone(),
+                        http.clone(),
+                        metrics.clone(),
+                        remote_addr,
+                        req,
+                    )
+                }))
+            }
+        });
+
+        let server = match Server::try_bind(&addr) {
+            Ok(server) => server,
+            Err(e) => return Err(Box::new(e)),
+        };
+        servers.push(
+            server
+                .serve(make_svc)
+                .with_graceful_shutdown(async move { shutdown.recv().await }),
+        );
+        info!(%addr, "HTTP server listening");
+    }
+
+    // the sweep never finishes, so we're done when the servers are
+    tokio::select! {
+        result = futures::future::try_join_all(servers) => match result {
+            Ok(_) => Ok(()),
+            Err(e) => Err(Box::new(e)),
+        },
+        _ = http_sweep(state, http) => Ok(()),
+    }
+}
+
+async fn http_route(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    metrics: Arc<Metrics>,
+    client: SocketAddr,
+    req: Request<Body>,
+) -> Result<Response<Body>, Infallible> {
+    let span = span!(Level::INFO, "HTTP request", client = ?client, method = ?req.method(), uri = ?req.uri());
+    let _guard = span.enter();
+
+    trace!("routing");
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/") => http_unimplemented(state, req).await,
+
+        (&Method::GET, "/register") => http_register_form(state, http).await,
+        (&Method::POST, "/register") => http_register(state, http, req).await,
+
+        (&Method::GET, "/user") => http_user(state, req).await,
+        (&Method::GET, path) if path.starts_with("/user/") => http_user(state, req).await,
+        (&Method::GET, "/room") => http_room(state, req).await,
+        (&Method::GET, path) if path.starts_with("/room/") => http_room(state, req).await,
+
+        (&Method::GET, "/who") => http_unimplemented(state, req).await,
+        (&Method::GET, "/help") => http_unimplemented(state, req).await,
+
+        (&Method::GET, "/admin") => http_admin(state, http, req).await,
+
+        (&Method::GET, "/api/be") => http_be(state, http, req).await,
+        (&Method::POST, "/api/do") => http_do(state, http, req).await,
+        (&Method::POST, "/api/leave") => http_unimplemented(state, req).await,
+        (&Method::POST, "/api/login") => http_login(state, http, req).await,
+        (&Method::POST, "/api/logout") => http_logout(state, http, req).await,
+        (&Method::POST, "/api/who") => http_unimplemented(state, req).await,
+        (&Method::GET, "/api/find") => http_find(state, req).await,
+
+        (&Method::GET, "/metrics") => http_metrics(metrics, client),
+        (&Method::GET, "/version") => http_version(state).await,
+        _ => Err(HttpError::NotFound),
+    };
+
+    let resp = result.unwrap_or_else(|e| {
+        if let HttpError::Internal(msg) = &e {
+            error!(%msg, "internal error");
+        }
+        e.into_response()
+    });
+
+    info!(status = ?resp.status());
+    Ok(resp)
+}
+
+/// Why an HTTP request failed, as the client will see it
+#[derive(Debug)]
+pub enum HttpError {
+    /// The request was malformed (e.g., a missing parameter)
+    BadRequest(String),
+    /// The client needs to log in
+    Unauthorized,
+    /// The client may not do that
+    Forbidden,
+    NotFound,
+    NotImplemented,
+    /// The request body is over `--max-body-size`
+    PayloadTooLarge,
+    /// The client is sending messages too quickly
+    TooManyRequests,
+    /// The world can't take them right now (e.g., their room is full)
+    Unavailable(String),
+    /// Our fault; the details are logged, not sent
+    Internal(String),
+}
+
+impl HttpError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            HttpError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            HttpError::Unauthorized => StatusCode::UNAUTHORIZED,
+            HttpError::Forbidden => StatusCode::FORBIDDEN,
+            HttpError::NotFound => StatusCode::NOT_FOUND,
+            HttpError::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            HttpError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            HttpError::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            HttpError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            HttpError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// A JSON response, e.g., `{"error": "Not Found"}`
+    pub fn into_response(self) -> Response<Body> {
+        let status = self.status();
+        let reason = status.canonical_reason().unwrap_or("Error");
+        let body = match &self {
+            HttpError::BadRequest(msg) | HttpError::Unavailable(msg) => {
+                serde_json::json!({ "error": reason, "message": msg })
+            }
+            _ => serde_json::json!({ "error": reason }),
+        };
+
+        let mut resp = Response::new(Body::from(body.to_string()));
+        *resp.status_mut() = status;
+        resp.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        resp
+    }
+}
+
+impl fmt::Display for HttpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HttpError::BadRequest(msg) | HttpError::Unavailable(msg) | HttpError::Internal(msg) => {
+                write!(f, "{}: {}", self.status(), msg)
+            }
+            _ => write!(f, "{}", self.status()),
+        }
+    }
+}
+
+impl Error for HttpError {}
+
+impl From<hyper::Error> for HttpError {
+    fn from(e: hyper::Error) -> Self {
+        HttpError::Internal(e.to_string())
+    }
+}
+
+async fn http_unimplemented(
+    _state: GameState,
+    _req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    Err(HttpError::NotImplemented)
+}
+
+/// Log in with form fields `name` and `password`, putting them in the world.
+/// The session is in a cookie; the response has the CSRF token for `/api/do`.
+/// With `remember` (e.g., a checked checkbox), the cookie outlives the browser
+/// and the session lasts `HTTP_REMEMBER_SECS` between visits.
+async fn http_login(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    let form = form_body(&state, req).await?;
+    let name = form_field(&form, "name")?;
+    let password = form_field(&form, "password")?;
+    let remember = form_flag(&form, "remember");
+
+    let name = name.trim();
+    validate_name(name).map_err(HttpError::BadRequest)?;
+
+    // TODO registration
+    let person = state
+        .check_password(name, &password)
+        .await
+        .ok_or(HttpError::Unauthorized)?;
+
+    // only after the password, so a ban doesn't reveal that an account exists
+    if person.banned {
+        info!(person.id, "banned");
+        return Err(HttpError::Forbidden);
+    }
+
+    // bring the hash up to the current policy while we have the plaintext
+    if state.needs_rehash(&person.password) {
+        info!(person.id, "rehashing password");
+        state.set_password(person.id, &password).await;
+    }
+
+    let (session, token) = start_session(&state, &http, &person, remember).await?;
+    info!(person.id, "logged in via HTTP");
+
+    let mut resp = json_response(serde_json::json!({ "name": person.name, CSRFTOKEN: token }));
+    set_cookie(&mut resp, &session_cookie_for(&session, remember))?;
+    Ok(resp)
+}
+
+/// Start a session for `person` and put them in the world, with everything
+/// they'd see logging in at a terminal
+async fn start_session(
+    state: &GameState,
+    http: &Arc<Mutex<HTTPState>>,
+    person: &PersonRecord,
+    remember: bool,
+) -> Result<(SessionId, CSRFToken), HttpError> {
+    let (flood, session_timeout) = {
+        let state = state.lock().await;
+        (state.flood_control(), state.session_timeout())
+    };
+
+    let (session, token) = {
+        let mut http = http.lock().await;
+        let lifetime = if remember {
+            Duration::from_secs(HTTP_REMEMBER_SECS)
+        } else {
+            session_timeout
+        };
+        let session = http.gen_session_id_for(person, flood, lifetime);
+        let joined = match http.sessions.get_mut(&session) {
+            Some(s) => s.join(state).await,
+            None => Err(HttpError::Internal("lost new session".to_string())),
+        };
+        if let Err(e) = joined {
+            http.end_session(&session);
+            return Err(e);
+        }
+        let token = http.gen_csrf_token_for(session.clone());
+        (session, token)
+    };
+
+    let conn = Connection::HTTP {
+        session: session.clone(),
+    };
+    if let Some((at, from)) = state.record_login(person.id, &conn) {
+        let text = last_login_notice(at, &from);
+        state.send_to(person.id, Message::Notice { text });
+    }
+
+    let (greeting, motd, shutdown_in) = {
+        let state = state.lock().await;
+        (state.greeting(), state.motd(), state.shutdown_in())
+    };
+    if let Some(text) = greeting {
+        state.send_to(person.id, Message::Notice { text });
+    }
+    if let Some(text) = motd {
+        state.send_to(person.id, Message::Notice { text });
+    }
+    if let Some(left) = shutdown_in {
+        let text = restart_notice(left);
+        state.send_to(person.id, Message::Notice { text });
+    }
+
+    Ok((session, token))
+}
+
+/// The `Set-Cookie` value for `session`; unless they asked us to `remember`
+/// them, it goes when the browser closes
+fn session_cookie_for(session: &SessionId, remember: bool) -> String {
+    let mut cookie = format!("{}={}; Path=/; HttpOnly; SameSite=Strict", SESSIONID, session);
+    if remember {
+        cookie.push_str(&format!("; Max-Age={}", HTTP_REMEMBER_SECS));
+    }
+    cookie
+}
+
+/// The registration form, with a CSRF token (also set as a cookie, so
+/// `POST /register` can check that they match). Closed registration is
+/// forbidden.
+async fn http_register_form(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+) -> Result<Response<Body>, HttpError> {
+    if !state.lock().await.open_registration() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let token = http.lock().await.gen_token();
+    let mut resp = register_page(&token, "", &[]);
+    set_cookie(&mut resp, &register_cookie_for(&token))?;
+    Ok(resp)
+}
+
+/// Register with form fields `name`, `password`, and `confirm`, following the
+/// same rules as at a terminal, and redirect to the client, logged in. The
+/// CSRF token for `/api/do` is in the redirect's fragment, where the client
+/// can find it (and the server never sees it again). If anything's wrong, the
+/// form comes back explaining what.
+async fn http_register(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    if !state.lock().await.open_registration() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let expected = request_cookie(&req, REGISTERTOKEN).ok_or(HttpError::Forbidden)?;
+    let form = form_body(&state, req).await?;
+    if query_param(&form, CSRFTOKEN).as_ref() != Some(&expected) {
+        return Err(HttpError::Forbidden);
+    }
+
+    let name = query_param(&form, "name").unwrap_or_default();
+    let name = name.trim();
+    let password = query_param(&form, "password").unwrap_or_default();
+    let confirm = query_param(&form, "confirm").unwrap_or_default();
+
+    let mut errors = Vec::new();
+    let mut reservation = None;
+    if let Err(e) = validate_name(name) {
+        errors.push(("name", e));
+    } else if let Some(existing) = state.person_by_name(name) {
+        errors.push(("name", format!("There is already someone named {}.", existing.name)));
+    } else {
+        match state.reserve_name(name) {
+            Some(r) => reservation = Some(r),
+            None => errors.push((
+                "name",
+                "Someone else is registering that name right now.".to_string(),
+            )),
+        }
+    }
+    if password.len() < 8 {
+        errors.push((
+            "password",
+            "That is not a valid password. It should be at least 8 characters.".to_string(),
+        ));
+    } else if password != confirm {
+        errors.push(("confirm", "Passwords don't match.".to_string()));
+    }
+
+    let reservation = match reservation {
+        Some(reservation) if errors.is_empty() => reservation,
+        // dropping a reservation frees the name up again
+        _ => {
+            let mut resp = register_page(&expected, name, &errors);
+            *resp.status_mut() = StatusCode::BAD_REQUEST;
+            return Ok(resp);
+        }
+    };
+
+    let person = state.register(reservation, &password).await;
+    let (session, token) = start_session(&state, &http, &person, false).await?;
+    info!(person.id, "registered via HTTP");
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::SEE_OTHER;
+    let location = format!("/#{}={}", CSRFTOKEN, token);
+    let location =
+        HeaderValue::from_str(&location).map_err(|e| HttpError::Internal(e.to_string()))?;
+    resp.headers_mut().insert(LOCATION, location);
+    set_cookie(&mut resp, &session_cookie_for(&session, false))?;
+    Ok(resp)
+}
+
+/// The `Set-Cookie` value for the registration form's CSRF token
+fn register_cookie_for(token: &str) -> String {
+    format!(
+        "{}={}; Path=/register; HttpOnly; SameSite=Strict",
+        REGISTERTOKEN, token
+    )
+}
+
+/// The registration form, filled in with `name` and explaining `errors`
+/// beside the fields they're about
+fn register_page(token: &str, name: &str, errors: &[(&str, String)]) -> Response<Body> {
+    let error = |field: &str| -> String {
+        errors
+            .iter()
+            .filter(|(about, _)| *about == field)
+            .map(|(_, e)| format!("<p class=\"error\">{}</p>", html_escape(e)))
+            .collect()
+    };
+
+    let body = format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Register</title></head>
+<body>
+<h1>Register</h1>
+<form method="post" action="/register">
+<input type="hidden" name="{tok}" value="{token}">
+<p><label>Email address or Twitter handle <input name="name" value="{name}" required></label></p>
+{name_error}
+<p><label>Password <input type="password" name="password" minlength="8" required></label></p>
+{password_error}
+<p><label>Password again <input type="password" name="confirm" required></label></p>
+{confirm_error}
+<p><button type="submit">Register</button></p>
+</form>
+</body>
+</html>
+"#,
+        tok = CSRFTOKEN,
+        token = html_escape(token),
+        name = html_escape(name),
+        name_error = error("name"),
+        password_error = error("password"),
+        confirm_error = error("confirm"),
+    );
+
+    let mut resp = Response::new(Body::from(body));
+    let headers = resp.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html; charset=utf-8"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    resp
+}
+
+/// Make `s` safe to put in HTML text or a quoted attribute
+fn html_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// End the session in the request's cookie, taking them out of the world, and
+/// clear the cookie. There's nothing to end if they weren't logged in, but
+/// the cookie is cleared all the same.
+async fn http_logout(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    if let Some(session) = session_cookie(&req) {
+        let mut http = http.lock().await;
+        if let Some(s) = http.sessions.get(&session) {
+            // dropping their queue ends any poll they have waiting
+            if s.rx.is_some() {
+                let mut state = state.lock().await;
+                if state.is_connected(s.person.id) {
+                    state.logout(&s.person).await;
+                }
+            }
+            info!(id = s.person.id, "logged out via HTTP");
+        }
+        http.end_session(&session);
+    }
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NO_CONTENT;
+    let cookie = format!(
+        "{}=; Path=/; HttpOnly; SameSite=Strict; Max-Age=0; Expires=Thu, 01 Jan 1970 00:00:00 GMT",
+        SESSIONID
+    );
+    set_cookie(&mut resp, &cookie)?;
+    Ok(resp)
+}
+
+/// Long poll: wait for messages for the session's person, answering with
+/// `{"messages": [...], "events": [...]}` as soon as there are any (or with
+/// none, eventually); `messages` are rendered as text, `events` are the same
+/// messages as JSON (see `Message`)
+async fn http_be(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    let session = session_cookie(&req).ok_or(HttpError::Unauthorized)?;
+
+    let (rx, person) = {
+        let mut http = http.lock().await;
+        let s = http.live_session(&state, &session).await?;
+        s.touch();
+
+        if s.rx.is_none() {
+            s.join(&state).await?;
+        }
+
+        match &s.rx {
+            Some(rx) => (rx.clone(), s.person.clone()),
+            None => return Err(HttpError::Internal("joined without a queue".to_string())),
+        }
+    };
+
+    // a second poll on the same session waits its turn
+    let mut msgs = Vec::new();
+    let mut closed = false;
+    {
+        let mut rx = rx.lock().await;
+        let poll = Duration::from_secs(HTTP_POLL_SECS);
+        match tokio::time::timeout(poll, rx.recv()).await {
+            Err(_) => (),
+            Ok(None) => closed = true,
+            Ok(Some(msg)) => {
+                msgs.push(msg);
+                loop {
+                    match rx.try_recv() {
+                        Ok(msg) => msgs.push(msg),
+                        Err(mpsc::error::TryRecvError::Empty) => break,
+                        Err(mpsc::error::TryRecvError::Closed) => {
+                            closed = true;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    {
+        let mut http = http.lock().await;
+        let current = http
+            .sessions
+            .get(&session)
+            .and_then(|s| s.rx.as_ref())
+            .is_some_and(|current| Arc::ptr_eq(current, &rx));
+
+        // their queue closing means they were logged out (not just idle) or
+        // they couldn't keep up; either way, the session is over
+        if closed && current {
+            info!(id = person.id, "HTTP session over");
+            http.end_session(&session);
+
+            let mut state = state.lock().await;
+            if state.is_connected(person.id) {
+                state.unregister_connection(person.id);
+                state.depart(&person, DepartReason::Disconnected).await;
+            }
+        } else if let Some(s) = http.sessions.get_mut(&session) {
+            s.touch();
+        }
+    }
+
+    let mut lines = Vec::with_capacity(msgs.len());
+    let mut events = Vec::with_capacity(msgs.len());
+    for msg in msgs {
+        let line = msg.render_with_opts(person.id, &person.render).await;
+        if !line.is_empty() {
+            lines.push(line);
+        }
+        let mut event =
+            serde_json::to_value(&msg).map_err(|e| HttpError::Internal(e.to_string()))?;
+        if msg.about() == Some(person.id) {
+            event["you"] = serde_json::Value::Bool(true);
+        }
+        events.push(event);
+    }
+
+    Ok(json_response(serde_json::json!({ "messages": lines, "events": events })))
+}
+
+/// Run the command in form field `command`, as though typed at a terminal;
+/// its effects arrive via `/api/be`
+async fn http_do(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    let session = session_cookie(&req).ok_or(HttpError::Unauthorized)?;
+    let form = form_body(&state, req).await?;
+
+    let mut http = http.lock().await;
+    http.live_session(&state, &session).await?;
+    if http.tokens.get(&session) != query_param(&form, CSRFTOKEN).as_ref() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let command = form_field(&form, "command")?;
+    let (max_line_length, require_slash) = {
+        let state = state.lock().await;
+        (state.max_line_length(), state.require_slash())
+    };
+    if command.len() > max_line_length {
+        return Err(HttpError::BadRequest("Message too long.".to_string()));
+    }
+    let line = sanitize(&command);
+    let cmd = Command::parse_with(line.clone(), require_slash)
+        .map_err(|e| HttpError::BadRequest(e.to_string()))?;
+
+    let s = match http.sessions.get_mut(&session) {
+        Some(s) => s,
+        None => return Err(HttpError::Unauthorized),
+    };
+    s.touch();
+
+    if s.rx.is_none() {
+        s.join(&state).await?;
+    }
+    state.mark_active(s.person.id);
+    if cmd.is_recallable() {
+        state.remember(s.person.id, &line);
+    }
+
+    if cmd.is_speech() && !s.flood.allow() {
+        trace!("flood control");
+        return Err(HttpError::TooManyRequests);
+    }
+
+    cmd.run(state, &mut s.person).await;
+
+    let mut resp = Response::new(Body::empty());
+    *resp.status_mut() = StatusCode::NO_CONTENT;
+    Ok(resp)
+}
+
+fn set_cookie(resp: &mut Response<Body>, cookie: &str) -> Result<(), HttpError> {
+    let cookie = HeaderValue::from_str(cookie).map_err(|e| HttpError::Internal(e.to_string()))?;
+    resp.headers_mut().insert(SET_COOKIE, cookie);
+    Ok(())
+}
+
+/// The session ID in the request's cookies, if any
+fn session_cookie(req: &Request<Body>) -> Option<SessionId> {
+    request_cookie(req, SESSIONID)
+}
+
+/// The value of the cookie `name` in the request, if any
+fn request_cookie(req: &Request<Body>, name: &str) -> Option<String> {
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|cookies| cookies.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .find_map(|cookie| {
+            let cookie = cookie.trim();
+            match cookie.find('=') {
+                Some(i) if &cookie[..i] == name => Some(cookie[i + 1..].to_string()),
+                _ => None,
+            }
+        })
+}
+
+/// The body of a form submission, still URL-encoded. Bodies over `--max-body-size`
+/// are refused before we've read them into memory.
+async fn form_body(state: &GameState, req: Request<Body>) -> Result<String, HttpError> {
+    let limit = state.lock().await.max_body_size();
+
+    // most clients say how much is coming, so we can refuse it unread
+    let declared = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+    if matches!(declared, Some(len) if len > limit as u64) {
+        return Err(HttpError::PayloadTooLarge);
+    }
+
+    // ...but they may not tell the truth, or say at all (e.g., when chunked)
+    let mut body = req.into_body();
+    let mut bytes = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if bytes.len() + chunk.len() > limit {
+            return Err(HttpError::PayloadTooLarge);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn form_field(form: &str, key: &str) -> Result<String, HttpError> {
+    query_param(form, key).ok_or_else(|| HttpError::BadRequest(format!("missing field {}", key)))
+}
+
+/// Whether a checkbox-style form field is set, i.e., `on` (as browsers send
+/// it), `true`, or `1`
+fn form_flag(form: &str, key: &str) -> bool {
+    matches!(
+        query_param(form, key).as_deref(),
+        Some("on") | Some("true") | Some("1")
+    )
+}
+
+/// A JSON response that nobody should cache
+fn json_response(body: serde_json::Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(body.to_string()));
+    let headers = resp.headers_mut();
+    headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    headers.insert(CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    resp
+}
+
+/// Account names matching `?q=...`, one per line, a page at a time (see
+/// `page_params`). There are no HTTP sessions to check for admins, so this
+/// only works when `find` is public.
+async fn http_find(state: GameState, req: Request<Body>) -> Result<Response<Body>, HttpError> {
+    let query = match req.uri().query().and_then(|query| query_param(query, "q")) {
+        Some(query) if !query.trim().is_empty() => query,
+        _ => return Err(HttpError::BadRequest("missing query parameter q".to_string())),
+    };
+
+    let state = state.lock().await;
+    if !state.public_find() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let (page, per_page) = page_params(req.uri().query(), state.page_size())?;
+    let (names, paging) = paginate(state.find(query.trim()), page, per_page);
+    let mut body = names.join("\n");
+    let more = paging.total.saturating_sub(paging.page * per_page);
+    if more > 0 {
+        body.push_str(&format!("\n...and {} more", more));
+    }
+
+    let mut resp = Response::new(Body::from(body));
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    Ok(resp)
+}
+
+/// Someone's public profile, as `profile` shows it, and whether they're online.
+/// The user is `/user/NAME` or `/user?name=NAME`. Looking up names tells
+/// strangers who has an account, so this only works when `find` is public.
+async fn http_user(state: GameState, req: Request<Body>) -> Result<Response<Body>, HttpError> {
+    let name = match req.uri().path().strip_prefix("/user/") {
+        Some(name) => Some(percent_decode(name)),
+        None => req.uri().query().and_then(|query| query_param(query, "name")),
+    };
+    let name = match name {
+        Some(name) if !name.trim().is_empty() => name,
+        _ => return Err(HttpError::BadRequest("missing query parameter name".to_string())),
+    };
+
+    let state = state.lock().await;
+    if !state.public_find() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let record = state.person_by_name(name.trim()).ok_or(HttpError::NotFound)?;
+    Ok(json_response(serde_json::json!({
+        "name": record.name,
+        "pronouns": record.pronouns,
+        "bio": record.bio,
+        "online": state.is_connected(record.id),
+    })))
+}
+
+/// A room's name, description, topic, and occupants, for browsing without
+/// logging in. The room is `/room/ID` or `/room?id=ID`.
+async fn http_room(state: GameState, req: Request<Body>) -> Result<Response<Body>, HttpError> {
+    let id = match req.uri().path().strip_prefix("/room/") {
+        Some(id) => Some(id.to_string()),
+        None => req.uri().query().and_then(|query| query_param(query, "id")),
+    };
+    let loc: RoomId = match id {
+        Some(id) => id.trim().parse().map_err(|_| HttpError::NotFound)?,
+        None => return Err(HttpError::BadRequest("missing query parameter id".to_string())),
+    };
+
+    let state = state.lock().await;
+    if !state.has_room(loc) {
+        return Err(HttpError::NotFound);
+    }
+
+    let room = state.room_info(loc);
+    let occupants: Vec<String> = WhoEntry::listing(state.room(loc).iter())
+        .into_iter()
+        .map(|entry| entry.name)
+        .collect();
+    Ok(json_response(serde_json::json!({
+        "id": loc,
+        "name": room.name,
+        "description": room.description,
+        "topic": room.topic,
+        "occupants": occupants,
+    })))
+}
+
+/// The operator's view: server stats, every session (a page at a time; see
+/// `page_params`), and every room, for admins' sessions only
+async fn http_admin(
+    state: GameState,
+    http: Arc<Mutex<HTTPState>>,
+    req: Request<Body>,
+) -> Result<Response<Body>, HttpError> {
+    let session = session_cookie(&req).ok_or(HttpError::Unauthorized)?;
+    let person = {
+        let mut http = http.lock().await;
+        let s = http.live_session(&state, &session).await?;
+        s.touch();
+        s.person.clone()
+    };
+
+    let state = state.lock().await;
+    if !state.is_admin(&person) {
+        info!(id = person.id, "not an admin");
+        return Err(HttpError::Forbidden);
+    }
+
+    let rooms: Vec<serde_json::Value> = state
+        .room_ids()
+        .into_iter()
+        .map(|loc| {
+            let room = state.room_info(loc);
+            serde_json::json!({
+                "id": loc,
+                "name": room.name,
+                "occupants": state.room_occupancy(loc),
+                "capacity": room.capacity,
+            })
+        })
+        .collect();
+    let (page, per_page) = page_params(req.uri().query(), state.page_size())?;
+    let (sessions, paging) = paginate(state.sessions(), page, per_page);
+    Ok(json_response(serde_json::json!({
+        "stats": state.stats(),
+        "sessions": sessions,
+        "sessions_paging": paging,
+        "rooms": rooms,
+    })))
+}
+
+/// Which page of a listing to show, and how long pages are, from `?page=`
+/// (counting from 1) and `?per_page=`; by default, the first page of
+/// `per_page` items
+fn page_params(query: Option<&str>, per_page: usize) -> Result<(usize, usize), HttpError> {
+    let param = |key: &str, default: usize| match query.and_then(|query| query_param(query, key)) {
+        None => Ok(default),
+        Some(n) => n
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&n: &usize| n >= 1)
+            .ok_or_else(|| HttpError::BadRequest(format!("invalid query parameter {}", key))),
+    };
+    Ok((param("page", 1)?, param("per_page", per_page)?))
+}
+
+/// The (percent-decoded) value of `key` in a URL query string
+fn query_param(query: &str, key: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (k, v) = match pair.find('=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, ""),
+        };
+        if k == key {
+            Some(percent_decode(v))
+        } else {
+            None
+        }
+    })
+}
+
+fn percent_decode(s: &str) -> String {
+    let hex = |b: Option<&u8>| b.and_then(|&b| (b as char).to_digit(16)).map(|d| d as u8);
+
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => match (hex(bytes.get(i + 1)), hex(bytes.get(i + 2))) {
+                (Some(hi), Some(lo)) => {
+                    decoded.push(hi << 4 | lo);
+                    i += 2;
+                }
+                _ => decoded.push(b'%'),
+            },
+            b => decoded.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Which build is running, and for how long, so operators can check a deploy
+async fn http_version(state: GameState) -> Result<Response<Body>, HttpError> {
+    let uptime = state.lock().await.uptime();
+    Ok(json_response(serde_json::json!({
+        "version": VERSION,
+        "uptime_secs": uptime.as_secs(),
+    })))
+}
+
+/// Prometheus metrics, only for clients on the loopback interface: operators
+/// who want to scrape remotely should proxy or tunnel to the server.
+fn http_metrics(metrics: Arc<Metrics>, client: SocketAddr) -> Result<Response<Body>, HttpError> {
+    if !client.ip().is_loopback() {
+        return Err(HttpError::Forbidden);
+    }
+
+    let mut resp = Response::new(Body::from(metrics.render()));
+    resp.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    Ok(resp)
 }
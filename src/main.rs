@@ -9,15 +9,18 @@ fn main() -> Result<(), Box<dyn Error>> {
     let config = much::Config::from_args();
 
     // initialize logging
-    tracing_subscriber::fmt()
+    let logs = tracing_subscriber::fmt()
         .with_writer(std::io::stderr) // TODO log to a file?
-        .with_max_level(config.verbosity.clone())
-        .init();
+        .with_max_level(config.verbosity.clone());
+    match config.log_format {
+        much::LogFormat::Text => logs.init(),
+        much::LogFormat::Json => logs.json().init(),
+    }
 
     tracing::info!("much v{}", much::VERSION);
 
-    let state = much::init();
-    tracing::info!("initialized fresh state");
+    let state = much::restore(&config)?;
+    tracing::info!("initialized state");
 
     much::run(&config, state)
 }
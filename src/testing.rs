@@ -0,0 +1,80 @@
+//! Helpers for integration tests that talk to a running server
+
+use std::time::Duration;
+
+use futures::SinkExt;
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::stream::StreamExt;
+use tokio_util::codec::{Framed, LinesCodec};
+
+/// How long to wait for a line before failing the test (rather than hanging)
+const LINE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A line-oriented client, as a telnet user would see the server
+///
+/// Every method panics if anything goes wrong, so tests can read straight
+/// through.
+pub struct TestClient {
+    lines: Framed<TcpStream, LinesCodec>,
+}
+
+impl TestClient {
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Self {
+        let stream = TcpStream::connect(addr).await.expect("connected");
+        TestClient {
+            lines: Framed::new(stream, LinesCodec::new()),
+        }
+    }
+
+    /// Log in to an existing account, reading through the login chatter, any
+    /// scrollback, our own (empty) arrival, and the look at the room
+    pub async fn login(&mut self, name: &str, password: &str) {
+        let _prompt = self.expect_line().await;
+        self.send(name).await;
+        let _prompt = self.expect_line().await;
+        self.send(password).await;
+        let login = self.expect_line().await;
+        assert!(login.contains("Logged in"), "expected login, got '{}'", login);
+        while !self.expect_line().await.is_empty() {}
+        self.expect_containing("Here:").await;
+    }
+
+    pub async fn send(&mut self, line: &str) {
+        self.lines.send(line).await.expect("sent line");
+    }
+
+    /// The next line from the server
+    pub async fn expect_line(&mut self) -> String {
+        match tokio::time::timeout(LINE_TIMEOUT, self.lines.next()).await {
+            Err(_) => panic!("timed out waiting for a line"),
+            Ok(None) => panic!("disconnected while waiting for a line"),
+            Ok(Some(Err(e))) => panic!("error waiting for a line: {}", e),
+            Ok(Some(Ok(line))) => line,
+        }
+    }
+
+    /// Skip lines until one contains `needle` (lines are colored, so exact
+    /// matches are brittle)
+    pub async fn expect_containing(&mut self, needle: &str) -> String {
+        loop {
+            let line = self.expect_line().await;
+            if line.contains(needle) {
+                return line;
+            }
+        }
+    }
+
+    /// Wait for the server to hang up
+    pub async fn expect_disconnect(&mut self) {
+        match tokio::time::timeout(LINE_TIMEOUT, self.lines.next()).await {
+            Err(_) => panic!("timed out waiting for a disconnect"),
+            Ok(Some(Ok(line))) => panic!("expected disconnect, got '{}'", line),
+            Ok(None) | Ok(Some(Err(_))) => (),
+        }
+    }
+
+    /// The underlying framed stream, e.g., to split it
+    pub fn into_inner(self) -> Framed<TcpStream, LinesCodec> {
+        self.lines
+    }
+}
@@ -1,20 +1,93 @@
 use std::error::Error;
 use std::fmt;
-use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use tokio::sync::{Mutex};
-
-use tracing::{info, span, Level};
+use tracing::{error, info, span, Level};
 
+use crate::world::locale::*;
 use crate::world::message::*;
+use crate::world::paging::paginate;
 use crate::world::person::*;
 use crate::world::state::*;
 
 #[derive(Clone, Debug)]
 pub enum Command {
+    /// Mark yourself as away, with an optional message
+    Afk { message: String },
+    /// Keep someone from logging in (and disconnect them), for admins
+    Ban { name: String, reason: Option<String> },
+    /// Disconnect someone, for admins
+    Boot { name: String },
+    /// Show the room's capacity, or (for admins) set it; `Some(None)` removes
+    /// the limit
+    Capacity { capacity: Option<Option<usize>> },
+    /// Speak on a channel you've joined
+    Chan { channel: String, text: String },
+    /// Turn ANSI color on or off (`None` toggles)
+    Color { on: Option<bool> },
+    /// Delete your own account, confirming with your name and password
+    Delete { name: String, password: String },
+    /// Make a new room, for admins
+    Dig { name: String },
+    /// Act something out (e.g., `emote smiles`), maybe at someone in the room
+    Emote {
+        action: String,
+        target: Option<String>,
+    },
+    /// Take a closer look at someone in the room
+    Examine { name: String },
+    /// Search accounts by part of their name, a page of results at a time
+    Find { query: String, page: usize },
+    /// Go to a room by name
+    Goto { room: String },
+    /// Make an account for someone, for admins (e.g., when registration is closed)
+    Invite { name: String, password: String },
+    /// Subscribe to a channel, to hear it wherever you are
+    Join { channel: String },
+    /// Unsubscribe from a channel
+    Leave { channel: String },
     Logout,
+    /// Show your language, or pick another one for your terminal
+    Locale { code: Option<String> },
+    /// Describe the current room
+    Look,
+    /// Set the message of the day, for admins (`None` clears it)
+    Motd { text: Option<String> },
+    /// Show someone's profile (`None` means your own)
+    Profile { name: Option<String> },
+    /// Show the last few lines you typed (`None` means `RECALL_DEFAULT`)
+    Recall { count: Option<usize> },
     Say { text: String },
-    Shutdown,
+    /// Save accounts and rooms to disk now, for admins
+    Save,
+    /// List every connection, a page at a time, for admins
+    Sessions { page: usize },
+    /// Set part of your profile (`None` clears it)
+    Set {
+        field: ProfileField,
+        value: Option<String>,
+    },
+    /// Speak to everyone on the server, regardless of room
+    Shout { text: String },
+    /// Shut the server down, now or after a countdown in seconds, for admins
+    Shutdown { countdown: Option<u64> },
+    /// Report uptime and usage counts
+    Stats,
+    /// Speak privately to someone, wherever they are
+    Tell { name: String, text: String },
+    /// Show the room's topic, or set it (`None` just shows it)
+    Topic { topic: Option<String> },
+    /// Tell the room we're typing (for web clients, so there's no reply)
+    Typing,
+    /// Let a banned person log in again, for admins
+    Unban { name: String },
+    /// Report the server's version and uptime
+    Version,
+    /// Speak privately to someone in the same room (the room sees that you did)
+    Whisper { name: String, text: String },
+    /// List who is online, a page at a time; with `all`, grouped by room
+    /// (just your own room, unless you're an admin) and not paged
+    Who { all: bool, page: usize },
 }
 
 #[derive(Debug)]
@@ -34,52 +107,1363 @@ impl fmt::Display for ParserError {
     }
 }
 
+#[derive(Debug)]
+pub struct AmbiguousCommandError {
+    candidates: Vec<&'static str>,
+}
+
+impl Error for AmbiguousCommandError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for AmbiguousCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Ambiguous command: {}?", self.candidates.join(", "))
+    }
+}
+
+#[derive(Debug)]
+pub struct UnbalancedQuoteError;
+
+impl Error for UnbalancedQuoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for UnbalancedQuoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Parse error: missing a closing quote.")
+    }
+}
+
+/// How many lines `recall` shows by default
+pub const RECALL_DEFAULT: usize = 10;
+
+/// Every verb `Command::parse` knows, in order
+const VERBS: &[&str] = &[
+    "afk", "ban", "boot", "capacity", "chan", "color", "delete", "dig", "emote", "examine", "find",
+    "goto", "invite", "join", "leave", "locale", "logout", "look", "motd", "profile", "recall",
+    "save", "say", "sessions", "set", "shout", "shutdown", "stats", "tell", "topic", "typing",
+    "unban", "version", "whisper", "who",
+];
+
+/// Other words for verbs, from other systems' habits; these are only ever
+/// matched in full, so they don't make abbreviations ambiguous
+const ALIASES: &[(&str, &str)] = &[("exit", "logout"), ("quit", "logout")];
+
+/// Verbs too drastic to abbreviate, or (like `save`, next to `say`) too rare
+/// to be worth making a common abbreviation ambiguous
+const UNABBREVIATED: &[&str] = &["delete", "save", "shutdown"];
+
+/// The shortest abbreviation we'll expand, so chat like "I think so" stays chat
+const MIN_ABBREVIATION: usize = 2;
+
+/// Find the verb that `word` names, either exactly or (if we may
+/// `abbreviate`) as an unambiguous prefix; `None` means it's not a command at
+/// all
+fn resolve_verb(
+    word: &str,
+    abbreviate: bool,
+) -> Result<Option<&'static str>, AmbiguousCommandError> {
+    let word = word.to_lowercase();
+
+    if let Some(verb) = VERBS.iter().find(|verb| **verb == word) {
+        return Ok(Some(verb));
+    }
+
+    if let Some((_, verb)) = ALIASES.iter().find(|(alias, _)| *alias == word) {
+        return Ok(Some(verb));
+    }
+
+    if !abbreviate || word.len() < MIN_ABBREVIATION {
+        return Ok(None);
+    }
+
+    let candidates: Vec<&'static str> = VERBS
+        .iter()
+        .filter(|verb| verb.starts_with(&word) && !UNABBREVIATED.contains(verb))
+        .copied()
+        .collect();
+    match candidates.len() {
+        0 => Ok(None),
+        1 => Ok(Some(candidates[0])),
+        _ => Err(AmbiguousCommandError { candidates }),
+    }
+}
+
+/// Drop control characters (other than tab) from a line of input, so no one
+/// can move the cursor, clear the screen, or recolor text on someone else's
+/// terminal; everything printable, Unicode included, is left alone
+pub fn sanitize(line: &str) -> String {
+    line.chars()
+        .filter(|c| *c == '\t' || !c.is_control())
+        .collect()
+}
+
+/// Split a line into its first word and the rest, trimming surrounding (but
+/// not internal) whitespace from both, so `tell @bob  hi  there` is `@bob` and
+/// `hi  there`; the rest is `""` when there's only one word
+pub fn tokenize(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim()),
+        None => (s, ""),
+    }
+}
+
+/// Split off the first argument of `s`, which may be double-quoted, returning
+/// it and the (trimmed) rest. A backslash takes the next character literally,
+/// quote or not.
+fn split_argument(s: &str) -> Result<(String, &str), UnbalancedQuoteError> {
+    let s = s.trim();
+    let mut arg = String::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            arg.push(c);
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == '"' {
+            quoted = !quoted;
+        } else if c.is_whitespace() && !quoted {
+            return Ok((arg, s[i..].trim()));
+        } else {
+            arg.push(c);
+        }
+    }
+
+    if quoted {
+        return Err(UnbalancedQuoteError);
+    }
+    if escaped {
+        arg.push('\\');
+    }
+    Ok((arg, ""))
+}
+
+/// A page number for a paged listing (e.g., `who 2`): pages count from 1
+fn page_number(s: &str) -> Option<usize> {
+    s.trim().parse().ok().filter(|&page| page >= 1)
+}
+
+/// Split `s` into at most `n` arguments. All but the last may be quoted (see
+/// `split_argument`), e.g., `tell "Bob Smith" hi`; the last is the rest of the
+/// line as typed, so speech and passwords come through untouched.
+pub fn arguments(s: &str, n: usize) -> Result<Vec<String>, UnbalancedQuoteError> {
+    let mut args = Vec::with_capacity(n);
+    let mut rest = s.trim();
+    while !rest.is_empty() {
+        if args.len() + 1 == n {
+            args.push(rest.to_string());
+            break;
+        }
+        let (arg, next) = split_argument(rest)?;
+        args.push(arg);
+        rest = next;
+    }
+    Ok(args)
+}
+
+/// The channel `arg` names, if it's a channel (i.e., `#` and then letters,
+/// numbers, `-`, or `_`); channels are named in lowercase
+fn channel_name(arg: &str) -> Option<String> {
+    let name = arg.strip_prefix('#')?;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return None;
+    }
+    Some(arg.to_ascii_lowercase())
+}
+
+/// Split `smiles at @bob` into the action and its target. Only names (which
+/// all have an `@`) are targets, so `looks at the sky` is just an action.
+fn emote_target(arg: &str) -> (&str, Option<&str>) {
+    if let Some((rest, name)) = arg.rsplit_once(char::is_whitespace) {
+        if let Some((action, at)) = rest.trim_end().rsplit_once(char::is_whitespace) {
+            if at.eq_ignore_ascii_case("at") && name.contains('@') {
+                return (action.trim_end(), Some(name));
+            }
+        }
+    }
+    (arg, None)
+}
+
+/// `who` entries for `people`, sorted by name; how people connect is for
+/// operators, not everyone
+fn who_entries<'a>(
+    state: &State,
+    people: impl Iterator<Item = &'a Person>,
+    admin: bool,
+) -> Vec<WhoEntry> {
+    if !admin {
+        return WhoEntry::listing(people);
+    }
+
+    WhoEntry::sorted(
+        people
+            .map(|q| WhoEntry {
+                transport: state.transport(q.id),
+                ..WhoEntry::new(q)
+            })
+            .collect(),
+    )
+}
+
 impl Command {
+    /// Parse a line of input, where anything that isn't a command is speech
+    /// (see `parse_with`)
     pub fn parse(s: String) -> Result<Command, Box<dyn Error>> {
+        Command::parse_with(s, false)
+    }
+
+    /// Parse a line of input; the first word names the command (in any case,
+    /// or abbreviated). A leading `/` marks a line as a command for sure, so
+    /// `/foo` is an error rather than speech. With `require_slash`, only those
+    /// lines are commands, and everything else is speech.
+    ///
+    /// Without a `/`, abbreviations only count on a line by themselves (e.g.,
+    /// `sta`), so chat like "to be honest" doesn't set the topic.
+    pub fn parse_with(s: String, require_slash: bool) -> Result<Command, Box<dyn Error>> {
         let s = s.trim();
+        let say = || Command::Say {
+            text: s.to_string(),
+        };
 
-        if s == "shutdown" {
-            Ok(Command::Shutdown)
-        } else if s == "logout" {
+        match s.strip_prefix('/') {
+            Some(line) => match Command::parse_command(line, true)? {
+                Some(cmd) => Ok(cmd),
+                None => Err(Box::new(ParserError { msg: s.to_string() })),
+            },
+            None if require_slash => Ok(say()),
+            None => Ok(Command::parse_command(s, false)?.unwrap_or_else(say)),
+        }
+    }
+
+    /// Parse `s` as a command, or `None` if it isn't one (i.e., its first word
+    /// isn't a verb, or what follows doesn't fit the verb). Abbreviated verbs
+    /// only count if the line was `slashed`, or is just the verb; arguments
+    /// that don't fit are only an error if it was `slashed`, so chat like
+    /// "set the table" stays chat.
+    fn parse_command(s: &str, slashed: bool) -> Result<Option<Command>, Box<dyn Error>> {
+        let (verb, arg) = tokenize(s);
+        let verb = match resolve_verb(verb, slashed || arg.is_empty())? {
+            Some(verb) => verb,
+            None => return Ok(None),
+        };
+
+        match Command::parse_arguments(s, verb, arg) {
+            Err(_) if !slashed => Ok(None),
+            parsed => parsed,
+        }
+    }
+
+    /// Parse `arg` as the arguments to `verb` (from the line `s`); `None`
+    /// means the verb doesn't take arguments like these, and an error means
+    /// it does but they're malformed
+    fn parse_arguments(s: &str, verb: &str, arg: &str) -> Result<Option<Command>, Box<dyn Error>> {
+        let cmd: Result<Command, Box<dyn Error>> = if verb == "shutdown" {
+            match arg {
+                "" => Ok(Command::Shutdown { countdown: None }),
+                n => match n.parse() {
+                    Ok(n) => Ok(Command::Shutdown { countdown: Some(n) }),
+                    Err(_) => Err(Box::new(ParserError { msg: s.to_string() })),
+                },
+            }
+        } else if verb == "logout" && arg.is_empty() {
             Ok(Command::Logout)
-        } else {
+        } else if verb == "who" && arg.is_empty() {
+            Ok(Command::Who { all: false, page: 1 })
+        } else if verb == "who" && arg.eq_ignore_ascii_case("all") {
+            Ok(Command::Who { all: true, page: 1 })
+        } else if let (true, Some(page)) = (verb == "who", page_number(arg)) {
+            Ok(Command::Who { all: false, page })
+        } else if verb == "stats" && arg.is_empty() {
+            Ok(Command::Stats)
+        } else if verb == "save" && arg.is_empty() {
+            Ok(Command::Save)
+        } else if verb == "look" && arg.is_empty() {
+            Ok(Command::Look)
+        } else if verb == "typing" && arg.is_empty() {
+            Ok(Command::Typing)
+        } else if verb == "sessions" && arg.is_empty() {
+            Ok(Command::Sessions { page: 1 })
+        } else if let (true, Some(page)) = (verb == "sessions", page_number(arg)) {
+            Ok(Command::Sessions { page })
+        } else if verb == "version" && arg.is_empty() {
+            Ok(Command::Version)
+        } else if verb == "topic" {
+            Ok(Command::Topic {
+                topic: if arg.is_empty() {
+                    None
+                } else {
+                    Some(arg.to_string())
+                },
+            })
+        } else if verb == "ban" && !arg.is_empty() {
+            let mut args = arguments(arg, 2)?.into_iter();
+            Ok(Command::Ban {
+                name: args.next().unwrap_or_default(),
+                reason: args.next(),
+            })
+        } else if verb == "unban" && !arg.is_empty() {
+            Ok(Command::Unban {
+                name: arg.to_string(),
+            })
+        } else if verb == "boot" && !arg.is_empty() {
+            Ok(Command::Boot {
+                name: arg.to_string(),
+            })
+        } else if verb == "delete" {
+            let mut args = arguments(arg, 2)?.into_iter();
+            match (args.next(), args.next()) {
+                (Some(name), Some(password)) => Ok(Command::Delete { name, password }),
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "examine" && !arg.is_empty() {
+            Ok(Command::Examine {
+                name: arg.to_string(),
+            })
+        } else if verb == "find" && !arg.is_empty() {
+            // names have no spaces, so a number after the query is a page
+            let (query, page) = match arg.rfind(char::is_whitespace) {
+                Some(i) => match page_number(&arg[i..]) {
+                    Some(page) => (arg[..i].trim_end(), page),
+                    None => (arg, 1),
+                },
+                None => (arg, 1),
+            };
+            Ok(Command::Find {
+                query: query.to_string(),
+                page,
+            })
+        } else if verb == "capacity" {
+            match arg {
+                "" => Ok(Command::Capacity { capacity: None }),
+                "none" => Ok(Command::Capacity {
+                    capacity: Some(None),
+                }),
+                n => match n.parse() {
+                    Ok(n) => Ok(Command::Capacity {
+                        capacity: Some(Some(n)),
+                    }),
+                    Err(_) => Err(Box::new(ParserError { msg: s.to_string() })),
+                },
+            }
+        } else if verb == "dig" && !arg.is_empty() {
+            Ok(Command::Dig {
+                name: arg.to_string(),
+            })
+        } else if verb == "invite" {
+            let mut args = arguments(arg, 2)?.into_iter();
+            match (args.next(), args.next()) {
+                (Some(name), Some(password)) => Ok(Command::Invite { name, password }),
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "goto" && !arg.is_empty() {
+            Ok(Command::Goto {
+                room: arg.to_string(),
+            })
+        } else if verb == "afk" {
+            Ok(Command::Afk {
+                message: arg.to_string(),
+            })
+        } else if verb == "shout" && !arg.is_empty() {
+            Ok(Command::Shout {
+                text: arg.to_string(),
+            })
+        } else if verb == "tell" {
+            let mut args = arguments(arg, 2)?.into_iter();
+            match (args.next(), args.next()) {
+                (Some(name), Some(text)) => Ok(Command::Tell { name, text }),
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "whisper" {
+            let mut args = arguments(arg, 2)?.into_iter();
+            match (args.next(), args.next()) {
+                (Some(name), Some(text)) => Ok(Command::Whisper { name, text }),
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "emote" {
+            match emote_target(arg) {
+                ("", _) => Err(Box::new(ParserError { msg: s.to_string() })),
+                (action, target) => Ok(Command::Emote {
+                    action: action.to_string(),
+                    target: target.map(str::to_string),
+                }),
+            }
+        } else if verb == "locale" {
+            Ok(Command::Locale {
+                code: if arg.is_empty() {
+                    None
+                } else {
+                    Some(arg.to_lowercase())
+                },
+            })
+        } else if let ("join", Some(channel)) = (verb, channel_name(arg)) {
+            Ok(Command::Join { channel })
+        } else if let ("leave", Some(channel)) = (verb, channel_name(arg)) {
+            Ok(Command::Leave { channel })
+        } else if verb == "chan" {
+            match tokenize(arg) {
+                (channel, text) if !text.is_empty() => match channel_name(channel) {
+                    Some(channel) => Ok(Command::Chan {
+                        channel,
+                        text: text.to_string(),
+                    }),
+                    None => Err(Box::new(ParserError { msg: s.to_string() })),
+                },
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "motd" {
+            Ok(Command::Motd {
+                text: if arg.is_empty() {
+                    None
+                } else {
+                    Some(arg.to_string())
+                },
+            })
+        } else if verb == "recall" {
+            match arg {
+                "" => Ok(Command::Recall { count: None }),
+                n => match n.parse() {
+                    Ok(n) => Ok(Command::Recall { count: Some(n) }),
+                    Err(_) => Err(Box::new(ParserError { msg: s.to_string() })),
+                },
+            }
+        } else if verb == "profile" {
+            Ok(Command::Profile {
+                name: if arg.is_empty() {
+                    None
+                } else {
+                    Some(arg.to_string())
+                },
+            })
+        } else if verb == "set" {
+            let (field, value) = tokenize(arg);
+            let field = match field {
+                "bio" => ProfileField::Bio,
+                "pronouns" => ProfileField::Pronouns,
+                _ => return Err(Box::new(ParserError { msg: s.to_string() })),
+            };
+            Ok(Command::Set {
+                field,
+                value: if value.is_empty() {
+                    None
+                } else {
+                    Some(value.to_string())
+                },
+            })
+        } else if verb == "color" {
+            match arg {
+                "" => Ok(Command::Color { on: None }),
+                "on" => Ok(Command::Color { on: Some(true) }),
+                "off" => Ok(Command::Color { on: Some(false) }),
+                _ => Err(Box::new(ParserError { msg: s.to_string() })),
+            }
+        } else if verb == "say" && !arg.is_empty() {
             Ok(Command::Say {
-                text: s.to_string(),
+                text: arg.to_string(),
             })
+        } else {
+            return Ok(None);
+        };
+        cmd.map(Some)
+    }
+
+    /// Is this user-originated speech (and so subject to flood control)?
+    pub fn is_speech(&self) -> bool {
+        match self {
+            Command::Chan { .. }
+            | Command::Emote { .. }
+            | Command::Say { .. }
+            | Command::Shout { .. }
+            | Command::Tell { .. }
+            | Command::Whisper { .. } => true,
+            Command::Afk { .. }
+            | Command::Ban { .. }
+            | Command::Boot { .. }
+            | Command::Capacity { .. }
+            | Command::Color { .. }
+            | Command::Delete { .. }
+            | Command::Dig { .. }
+            | Command::Examine { .. }
+            | Command::Find { .. }
+            | Command::Goto { .. }
+            | Command::Invite { .. }
+            | Command::Join { .. }
+            | Command::Leave { .. }
+            | Command::Locale { .. }
+            | Command::Logout
+            | Command::Look
+            | Command::Motd { .. }
+            | Command::Profile { .. }
+            | Command::Recall { .. }
+            | Command::Save
+            | Command::Sessions { .. }
+            | Command::Set { .. }
+            | Command::Shutdown { .. }
+            | Command::Stats
+            | Command::Topic { .. }
+            | Command::Typing
+            | Command::Unban { .. }
+            | Command::Version
+            | Command::Who { .. } => false,
+        }
+    }
+
+    /// Should this line be kept for `recall`? Not `recall` itself, and not
+    /// anything with a password in it
+    pub fn is_recallable(&self) -> bool {
+        !matches!(
+            self,
+            Command::Recall { .. } | Command::Delete { .. } | Command::Invite { .. }
+        )
+    }
+
+    /// May only administrators run this command?
+    pub fn is_admin_only(&self) -> bool {
+        match self {
+            Command::Ban { .. }
+            | Command::Boot { .. }
+            | Command::Capacity { capacity: Some(_) }
+            | Command::Dig { .. }
+            | Command::Invite { .. }
+            | Command::Motd { .. }
+            | Command::Save
+            | Command::Sessions { .. }
+            | Command::Shutdown { .. }
+            | Command::Unban { .. } => true,
+            Command::Afk { .. }
+            | Command::Capacity { capacity: None }
+            | Command::Chan { .. }
+            | Command::Color { .. }
+            | Command::Delete { .. }
+            | Command::Emote { .. }
+            | Command::Examine { .. }
+            | Command::Find { .. }
+            | Command::Goto { .. }
+            | Command::Join { .. }
+            | Command::Leave { .. }
+            | Command::Locale { .. }
+            | Command::Logout
+            | Command::Look
+            | Command::Profile { .. }
+            | Command::Recall { .. }
+            | Command::Say { .. }
+            | Command::Set { .. }
+            | Command::Shout { .. }
+            | Command::Stats
+            | Command::Tell { .. }
+            | Command::Topic { .. }
+            | Command::Typing
+            | Command::Version
+            | Command::Whisper { .. }
+            | Command::Who { .. } => false,
+        }
+    }
+
+    /// The free text this command carries, if any
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Command::Afk { message: text }
+            | Command::Chan { text, .. }
+            | Command::Emote { action: text, .. }
+            | Command::Say { text }
+            | Command::Shout { text }
+            | Command::Tell { text, .. }
+            | Command::Whisper { text, .. } => Some(text),
+            Command::Topic { topic } => topic.as_deref(),
+            Command::Set { value, .. } => value.as_deref(),
+            Command::Motd { text } => text.as_deref(),
+            Command::Ban { reason, .. } => reason.as_deref(),
+            Command::Boot { .. }
+            | Command::Capacity { .. }
+            | Command::Color { .. }
+            | Command::Delete { .. }
+            | Command::Dig { .. }
+            | Command::Examine { .. }
+            | Command::Find { .. }
+            | Command::Goto { .. }
+            | Command::Invite { .. }
+            | Command::Join { .. }
+            | Command::Leave { .. }
+            | Command::Locale { .. }
+            | Command::Logout
+            | Command::Look
+            | Command::Profile { .. }
+            | Command::Recall { .. }
+            | Command::Save
+            | Command::Sessions { .. }
+            | Command::Shutdown { .. }
+            | Command::Stats
+            | Command::Typing
+            | Command::Unban { .. }
+            | Command::Version
+            | Command::Who { .. } => None,
         }
     }
 
     pub fn tag(&self) -> &'static str {
         match self {
+            Command::Afk { .. } => "afk",
+            Command::Ban { .. } => "ban",
+            Command::Boot { .. } => "boot",
+            Command::Capacity { .. } => "capacity",
+            Command::Chan { .. } => "chan",
+            Command::Color { .. } => "color",
+            Command::Delete { .. } => "delete",
+            Command::Dig { .. } => "dig",
+            Command::Emote { .. } => "emote",
+            Command::Examine { .. } => "examine",
+            Command::Find { .. } => "find",
+            Command::Goto { .. } => "goto",
+            Command::Invite { .. } => "invite",
+            Command::Join { .. } => "join",
+            Command::Leave { .. } => "leave",
+            Command::Locale { .. } => "locale",
             Command::Logout => "logout",
+            Command::Look => "look",
+            Command::Motd { .. } => "motd",
+            Command::Profile { .. } => "profile",
+            Command::Recall { .. } => "recall",
+            Command::Save => "save",
             Command::Say { .. } => "say",
-            Command::Shutdown => "shutdown",
+            Command::Sessions { .. } => "sessions",
+            Command::Set { .. } => "set",
+            Command::Shout { .. } => "shout",
+            Command::Shutdown { .. } => "shutdown",
+            Command::Stats => "stats",
+            Command::Tell { .. } => "tell",
+            Command::Topic { .. } => "topic",
+            Command::Typing => "typing",
+            Command::Unban { .. } => "unban",
+            Command::Version => "version",
+            Command::Whisper { .. } => "whisper",
+            Command::Who { .. } => "who",
         }
     }
 
-    pub async fn run(self, state: Arc<Mutex<State>>, p: &mut Person) {
+    pub async fn run(self, state: GameState, p: &mut Person) {
         let span = span!(Level::INFO, "command", id = p.id);
         let _guard = span.enter();
         info!(command = self.tag());
 
+        if self.is_admin_only() {
+            let state = state.lock().await;
+            if !state.is_admin(p) {
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: "Only administrators can do that.".to_string(),
+                    },
+                );
+                return;
+            }
+        }
+
+        if let Some(text) = self.text() {
+            let state = state.lock().await;
+            if text.len() > state.max_message_length() {
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: "Message too long.".to_string(),
+                    },
+                );
+                return;
+            }
+        }
+
+        // doing anything at all means you're back
+        if p.away.is_some() && !matches!(self, Command::Afk { .. } | Command::Typing) {
+            let mut state = state.lock().await;
+            state.set_away(p, None);
+            state.send_to(
+                p.id,
+                Message::Notice {
+                    text: "You are no longer away.".to_string(),
+                },
+            );
+        }
+
         match self {
-            Command::Logout => state.lock().await.logout(p).await,
-            Command::Say { text } => {
-                state
-                    .lock()
+            Command::Afk { message } => {
+                let mut state = state.lock().await;
+                state.set_away(p, Some(message));
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: "You are now away.".to_string(),
+                    },
+                );
+            }
+            Command::Ban { name, reason } => {
+                let mut state = state.lock().await;
+
+                let target = match state.person_by_name(&name) {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("There is no one named {}.", name),
+                            },
+                        );
+                        return;
+                    }
+                    Some(target) => target,
+                };
+
+                info!(target = target.id, ?reason, "banned");
+                let why = match &reason {
+                    Some(reason) => format!("You have been banned: {}", reason),
+                    None => "You have been banned.".to_string(),
+                };
+                state.set_banned(target.id, true, reason);
+                state.disconnect(target.id, &why).await;
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: format!("You banned {}.", target.name),
+                    },
+                );
+            }
+            Command::Boot { name } => {
+                let mut state = state.lock().await;
+
+                let target = match state.person_by_name(&name) {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("There is no one named {}.", name),
+                            },
+                        );
+                        return;
+                    }
+                    Some(target) => target,
+                };
+
+                let text = if state
+                    .disconnect(target.id, "You have been disconnected by an administrator.")
                     .await
-                    .roomcast(
-                        p.loc,
-                        Message::Say {
-                            speaker: p.id,
-                            speaker_name: p.name.clone(),
-                            loc: p.loc,
-                            text,
+                {
+                    info!(target = target.id, "booted");
+                    format!("You booted {}.", target.name)
+                } else {
+                    format!("{} is not online.", target.name)
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Capacity { capacity: None } => {
+                let state = state.lock().await;
+                let text = match state.room_info(p.loc).capacity {
+                    Some(capacity) => format!(
+                        "This room holds at most {} people ({} here).",
+                        capacity,
+                        state.room_occupancy(p.loc)
+                    ),
+                    None => "This room has no capacity limit.".to_string(),
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Capacity {
+                capacity: Some(capacity),
+            } => {
+                let mut state = state.lock().await;
+                info!(loc = p.loc, ?capacity, "set capacity");
+                state.room_info_mut(p.loc).capacity = capacity;
+                let text = match capacity {
+                    Some(capacity) => format!("You set this room's capacity to {}.", capacity),
+                    None => "You removed this room's capacity limit.".to_string(),
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Join { channel } => {
+                let mut state = state.lock().await;
+                let text = if state.join_channel(p.id, &channel) {
+                    format!("You join {}.", channel)
+                } else {
+                    format!("You are already in {}.", channel)
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Leave { channel } => {
+                let mut state = state.lock().await;
+                let text = if state.leave_channel(p.id, &channel) {
+                    format!("You leave {}.", channel)
+                } else {
+                    format!("You aren't in {}.", channel)
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Chan { channel, text } => {
+                let (recipients, text) = {
+                    let state = state.lock().await;
+                    if !state.in_channel(p.id, &channel) {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!(
+                                    "You aren't in {}. To join it, type: join {}",
+                                    channel, channel
+                                ),
+                            },
+                        );
+                        return;
+                    }
+                    (state.channel_recipients(&channel), state.filter(&text))
+                };
+                let msg = Message::Channel {
+                    channel,
+                    speaker: p.id,
+                    speaker_name: p.name.clone(),
+                    text,
+                    time: SystemTime::now(),
+                };
+                echo(&state, p, &recipients, &msg);
+                recipients.send(msg);
+            }
+            Command::Locale { code: None } => {
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: format!(
+                            "Your locale is {}. Choose from: {}.",
+                            p.render.locale,
+                            locales().join(", ")
+                        ),
+                    },
+                );
+            }
+            Command::Locale { code: Some(code) } => {
+                if !is_locale(&code) {
+                    state.send_to(
+                        p.id,
+                        Message::Notice {
+                            text: format!(
+                                "There is no locale {}. Choose from: {}.",
+                                code,
+                                locales().join(", ")
+                            ),
                         },
+                    );
+                    return;
+                }
+
+                // HTTP clients get structured messages, so they keep English text
+                if p.conn.is_line_oriented() {
+                    p.render.locale = code.clone();
+                }
+
+                state.lock().await.set_locale(p.id, &code);
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: format!("Your locale is now {}.", code),
+                    },
+                );
+            }
+            Command::Color { on } => {
+                // HTTP clients never get escape codes
+                let on = on.unwrap_or(!p.render.color) && p.conn.is_line_oriented();
+                p.render.color = on;
+
+                let mut state = state.lock().await;
+                state.set_color(p.id, on);
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: format!("Color is now {}.", if on { "on" } else { "off" }),
+                    },
+                );
+            }
+            Command::Delete { name, password } => {
+                let record = state.lock().await.person(&p.id);
+
+                let text = if canonical_name(&name) != canonical_name(&record.name) {
+                    format!(
+                        "To delete your account, type: delete {} <password>",
+                        record.name
                     )
-                    .await
+                } else if state.check_password(&record.name, &password).await.is_none() {
+                    "Password incorrect.".to_string()
+                } else {
+                    state.lock().await.delete_account(p).await;
+                    return;
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Dig { name } => {
+                let mut state = state.lock().await;
+                let text = match state.new_room(&name) {
+                    Ok(loc) => format!("You dig a new room, {}.", state.room_info(loc).name),
+                    Err(e) => e,
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Examine { name } => {
+                let state = state.lock().await;
+
+                let wanted = canonical_name(&name);
+                let target = state
+                    .room(p.loc)
+                    .iter()
+                    .find(|q| canonical_name(&q.name) == wanted);
+                let msg = match target {
+                    None => Message::Notice {
+                        text: "You don't see them here.".to_string(),
+                    },
+                    Some(target) => {
+                        let record = state.person(&target.id);
+                        Message::Examine {
+                            name: target.name.clone(),
+                            pronouns: record.pronouns,
+                            bio: record.bio,
+                            away: target.away.clone(),
+                            here_for: target.arrived_at.elapsed(),
+                        }
+                    }
+                };
+                state.send_to(p.id, msg);
+            }
+            Command::Find { query, page } => {
+                let state = state.lock().await;
+                if !state.may_find(p) {
+                    state.send_to(
+                        p.id,
+                        Message::Notice {
+                            text: "Only administrators can do that.".to_string(),
+                        },
+                    );
+                    return;
+                }
+
+                let (names, paging) = paginate(state.find(&query), page, state.page_size());
+                state.send_to(p.id, Message::Found { query, names, paging });
+            }
+            Command::Goto { room } => {
+                let mut state = state.lock().await;
+
+                let loc = match state.room_by_name(&room) {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("There is no room named {}.", room),
+                            },
+                        );
+                        return;
+                    }
+                    Some(loc) => loc,
+                };
+
+                let name = state.room_info(loc).name.clone();
+                let (text, arrived) = if loc == p.loc {
+                    (format!("You are already in {}.", name), false)
+                } else {
+                    match state.arrive(p, loc).await {
+                        Ok(()) => (format!("You go to {}.", name), true),
+                        Err(e) => (e.to_string(), false),
+                    }
+                };
+                state.send_to(p.id, Message::Notice { text });
+                if arrived {
+                    state.look(p);
+                }
+            }
+            Command::Invite { name, password } => {
+                let text = if let Err(e) = validate_name(&name) {
+                    e
+                } else if password.len() < 8 {
+                    "Passwords should be at least 8 characters.".to_string()
+                } else if let Some(existing) = state.person_by_name(&name) {
+                    format!("There is already someone named {}.", existing.name)
+                } else if let Some(reservation) = state.reserve_name(&name) {
+                    let person = state.register(reservation, &password).await;
+                    info!(target = person.id, "invited");
+                    format!("You invited {}.", person.name)
+                } else {
+                    format!("Someone is registering {} right now.", name)
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Logout => state.lock().await.logout(p).await,
+            Command::Look => state.lock().await.look(p),
+            Command::Motd { text } => {
+                let reply = if text.is_some() {
+                    "You set the message of the day."
+                } else {
+                    "You cleared the message of the day."
+                };
+                let mut state = state.lock().await;
+                state.set_motd(text);
+                state.send_to(
+                    p.id,
+                    Message::Notice {
+                        text: reply.to_string(),
+                    },
+                );
+            }
+            Command::Recall { count } => {
+                let lines = state.recall(p.id, count.unwrap_or(RECALL_DEFAULT));
+                state.send_to(p.id, Message::Recall { lines });
+            }
+            Command::Profile { name } => {
+                let state = state.lock().await;
+
+                let target = match name {
+                    None => state.person(&p.id),
+                    Some(name) => match state.person_by_name(&name) {
+                        None => {
+                            state.send_to(
+                                p.id,
+                                Message::Notice {
+                                    text: format!("There is no one named {}.", name),
+                                },
+                            );
+                            return;
+                        }
+                        Some(target) => target,
+                    },
+                };
+
+                state.send_to(
+                    p.id,
+                    Message::Profile {
+                        name: target.name,
+                        pronouns: target.pronouns,
+                        bio: target.bio,
+                    },
+                );
+            }
+            Command::Say { text } => {
+                // fan out after releasing the lock
+                let (recipients, msg) = {
+                    let mut state = state.lock().await;
+                    let msg = Message::Say {
+                        speaker: p.id,
+                        speaker_name: p.name.clone(),
+                        loc: p.loc,
+                        text: state.filter(&text),
+                        time: SystemTime::now(),
+                    };
+                    state.record(p.loc, msg.clone());
+                    (state.room_recipients(p.loc), msg)
+                };
+                echo(&state, p, &recipients, &msg);
+                recipients.send(msg);
+            }
+            Command::Emote { action, target } => {
+                let (recipients, msg) = {
+                    let mut state = state.lock().await;
+                    let target = match target {
+                        None => None,
+                        Some(name) => {
+                            let wanted = canonical_name(&name);
+                            match state
+                                .room(p.loc)
+                                .iter()
+                                .find(|q| canonical_name(&q.name) == wanted)
+                            {
+                                Some(target) => Some(target.clone()),
+                                None => {
+                                    state.send_to(
+                                        p.id,
+                                        Message::Notice {
+                                            text: format!("You don't see {} here.", name),
+                                        },
+                                    );
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    let msg = Message::Emote {
+                        actor: p.id,
+                        actor_name: p.name.clone(),
+                        loc: p.loc,
+                        action: state.filter(&action),
+                        target: target.as_ref().map(|target| target.id),
+                        target_name: target.as_ref().map(|target| target.name.clone()),
+                        time: SystemTime::now(),
+                    };
+                    state.record(p.loc, msg.clone());
+                    (state.room_recipients(p.loc), msg)
+                };
+                echo(&state, p, &recipients, &msg);
+                recipients.send(msg);
+            }
+            Command::Shout { text } => {
+                let (recipients, text) = {
+                    let state = state.lock().await;
+                    (state.all_recipients(), state.filter(&text))
+                };
+                let msg = Message::Shout {
+                    speaker: p.id,
+                    speaker_name: p.name.clone(),
+                    text,
+                    time: SystemTime::now(),
+                };
+                echo(&state, p, &recipients, &msg);
+                recipients.send(msg);
+            }
+            Command::Sessions { page } => {
+                let state = state.lock().await;
+                let (sessions, paging) = paginate(state.sessions(), page, state.page_size());
+                state.send_to(p.id, Message::Sessions { sessions, paging });
+            }
+            Command::Set { field, value } => {
+                let value = value
+                    .map(|value| ProfileField::clean(&value))
+                    .filter(|value| !value.is_empty());
+
+                let mut state = state.lock().await;
+                let text = match value {
+                    Some(value) if value.chars().count() > field.max_length() => format!(
+                        "Your {} can be at most {} characters.",
+                        field.name(),
+                        field.max_length()
+                    ),
+                    value => {
+                        let text = match &value {
+                            Some(value) => format!("You set your {} to: {}", field.name(), value),
+                            None => format!("You cleared your {}.", field.name()),
+                        };
+                        state.set_profile(p.id, field, value);
+                        text
+                    }
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Shutdown { countdown: None } => state.lock().await.shutdown().await,
+            Command::Shutdown {
+                countdown: Some(secs),
+            } => {
+                if !state.shutdown_after(Duration::from_secs(secs)).await {
+                    state.lock().await.send_to(
+                        p.id,
+                        Message::Notice {
+                            text: "A shutdown is already scheduled.".to_string(),
+                        },
+                    );
+                }
+            }
+            Command::Version => {
+                let state = state.lock().await;
+                state.send_to(
+                    p.id,
+                    Message::Version {
+                        version: crate::VERSION.to_string(),
+                        uptime: state.uptime(),
+                    },
+                );
+            }
+            Command::Save => {
+                let text = if !state.persists() {
+                    "Saving is disabled (there's no --db).".to_string()
+                } else {
+                    match state.save().await {
+                        Ok(people) => {
+                            info!(people, "saved");
+                            if people == 1 {
+                                "Saved 1 account.".to_string()
+                            } else {
+                                format!("Saved {} accounts.", people)
+                            }
+                        }
+                        Err(e) => {
+                            error!(%e, "save failed");
+                            format!("Couldn't save: {}.", e)
+                        }
+                    }
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Stats => {
+                let state = state.lock().await;
+                state.send_to(
+                    p.id,
+                    Message::Stats {
+                        stats: state.stats(),
+                    },
+                );
+            }
+            Command::Tell { name, text } => {
+                let state = state.lock().await;
+
+                let target = match state.person_by_name(&name) {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("There is no one named {}.", name),
+                            },
+                        );
+                        return;
+                    }
+                    Some(target) => target,
+                };
+
+                let away = match state.online_person(target.id) {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("{} is not online.", target.name),
+                            },
+                        );
+                        return;
+                    }
+                    Some(target) => target.away.clone(),
+                };
+
+                let msg = Message::Tell {
+                    from: p.id,
+                    from_name: p.name.clone(),
+                    to: target.id,
+                    to_name: target.name.clone(),
+                    text,
+                    time: SystemTime::now(),
+                };
+                state.send_to(target.id, msg.clone());
+                if target.id != p.id {
+                    state.send_to(p.id, msg);
+                }
+
+                if let Some(away) = away {
+                    let text = if away.is_empty() {
+                        format!("{} is away.", target.name)
+                    } else {
+                        format!("{} is away: {}", target.name, away)
+                    };
+                    state.send_to(p.id, Message::Notice { text });
+                }
+            }
+            Command::Whisper { name, text } => {
+                let (target, recipients) = {
+                    let state = state.lock().await;
+                    let wanted = canonical_name(&name);
+                    let target = state
+                        .room(p.loc)
+                        .iter()
+                        .find(|q| canonical_name(&q.name) == wanted)
+                        .cloned();
+                    (target, state.room_recipients(p.loc))
+                };
+                let target = match target {
+                    None => {
+                        state.send_to(
+                            p.id,
+                            Message::Notice {
+                                text: format!("You don't see {} here.", name),
+                            },
+                        );
+                        return;
+                    }
+                    Some(target) => target,
+                };
+
+                let whisper = |text| Message::Whisper {
+                    from: p.id,
+                    from_name: p.name.clone(),
+                    to: target.id,
+                    to_name: target.name.clone(),
+                    text,
+                    time: SystemTime::now(),
+                };
+                let msg = whisper(Some(text));
+                state.send_to(target.id, msg.clone());
+                if target.id != p.id {
+                    state.send_to(p.id, msg);
+                }
+                recipients.except(p.id).except(target.id).send(whisper(None));
+            }
+            Command::Topic { topic: None } => {
+                let state = state.lock().await;
+                let text = match &state.room_info(p.loc).topic {
+                    Some(topic) => format!("The topic is: {}", topic),
+                    None => "There is no topic.".to_string(),
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Topic { topic: Some(topic) } => {
+                let recipients = {
+                    let mut state = state.lock().await;
+                    state.room_info_mut(p.loc).topic = Some(topic.clone());
+                    state.room_recipients(p.loc)
+                };
+                let msg = Message::Topic {
+                    setter: p.id,
+                    setter_name: p.name.clone(),
+                    loc: p.loc,
+                    topic,
+                };
+                echo(&state, p, &recipients, &msg);
+                recipients.send(msg);
+            }
+            Command::Typing => {
+                if !p.typing_allowed() {
+                    return;
+                }
+
+                let recipients = state.lock().await.room_recipients(p.loc).except(p.id);
+                recipients.send(Message::Typing {
+                    who: p.id,
+                    name: p.name.clone(),
+                    loc: p.loc,
+                });
+            }
+            Command::Unban { name } => {
+                let mut state = state.lock().await;
+
+                let text = match state.person_by_name(&name) {
+                    None => format!("There is no one named {}.", name),
+                    Some(target) if !target.banned => format!("{} isn't banned.", target.name),
+                    Some(target) => {
+                        info!(target = target.id, "unbanned");
+                        state.set_banned(target.id, false, None);
+                        format!("You unbanned {}.", target.name)
+                    }
+                };
+                state.send_to(p.id, Message::Notice { text });
+            }
+            Command::Who { all: false, page } => {
+                let state = state.lock().await;
+                let people = who_entries(&state, state.online(), state.is_admin(p));
+                let (people, paging) = paginate(people, page, state.page_size());
+                state.send_to(p.id, Message::Who { people, paging });
+            }
+            Command::Who { all: true, .. } => {
+                let state = state.lock().await;
+                let admin = state.is_admin(p);
+                let locs = if admin { state.room_ids() } else { vec![p.loc] };
+                let mut rooms: Vec<RoomGroup> = locs
+                    .into_iter()
+                    .filter(|&loc| state.room_occupancy(loc) > 0)
+                    .map(|loc| RoomGroup {
+                        name: state.room_info(loc).name.clone(),
+                        people: who_entries(&state, state.room(loc).iter(), admin),
+                    })
+                    .collect();
+                rooms.sort_by_cached_key(|room| (room.name.to_lowercase(), room.name.clone()));
+                state.send_to(p.id, Message::WhoByRoom { rooms });
             }
-            Command::Shutdown => state.lock().await.shutdown(),
         }
     }
-}
\ No newline at end of file
+}
+
+/// Every command gets a reply: if the speaker won't hear their own message
+/// (e.g., they're not listed in the room), send it to them directly
+fn echo(state: &GameState, p: &Person, recipients: &Recipients, msg: &Message) {
+    if !recipients.contains(p.id) {
+        state.send_to(p.id, msg.clone());
+    }
+}
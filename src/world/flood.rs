@@ -0,0 +1,41 @@
+use std::time::Instant;
+
+/// A token bucket limiting how quickly a single session may speak.
+///
+/// Every user-originated message costs one token. Tokens refill continuously
+/// at `rate` per second, up to a maximum of `burst`.
+#[derive(Clone, Debug)]
+pub struct FloodControl {
+    rate: f64,
+    burst: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl FloodControl {
+    pub fn new(rate: f64, burst: u32) -> Self {
+        FloodControl {
+            rate,
+            burst: burst as f64,
+            tokens: burst as f64,
+            last: Instant::now(),
+        }
+    }
+
+    /// Spend a token, returning `false` if the bucket is empty (i.e., the
+    /// sender is flooding and the message should be dropped).
+    pub fn allow(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
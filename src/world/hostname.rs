@@ -0,0 +1,112 @@
+//! Reverse DNS for connecting addresses, for operators (see
+//! `--resolve-hostnames`)
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, PoisonError, RwLock};
+
+use tokio::task;
+
+use tracing::trace;
+
+/// Most addresses we remember; past this, we forget them all and start over
+const MAX_CACHED_HOSTNAMES: usize = 4096;
+
+/// Hostnames by address, so each address is looked up once. Addresses
+/// without a hostname are remembered, too, so they aren't retried.
+///
+/// Lookups happen off the async workers, and the cache has its own lock, so
+/// nobody waits on DNS while holding the game lock.
+#[derive(Clone, Default)]
+pub struct Hostnames(Arc<RwLock<HashMap<IpAddr, Option<String>>>>);
+
+impl Hostnames {
+    pub fn new() -> Self {
+        Hostnames::default()
+    }
+
+    /// The hostname for `ip`, if we've already looked it up and found one
+    pub fn cached(&self, ip: IpAddr) -> Option<String> {
+        let cache = self.0.read().unwrap_or_else(PoisonError::into_inner);
+        cache.get(&ip).cloned().flatten()
+    }
+
+    /// The hostname for `ip`, looking it up if we haven't yet
+    pub async fn resolve(&self, ip: IpAddr) -> Option<String> {
+        if let Some(host) = self.0.read().unwrap_or_else(PoisonError::into_inner).get(&ip) {
+            return host.clone();
+        }
+
+        let host = task::spawn_blocking(move || reverse_lookup(ip))
+            .await
+            .unwrap_or(None);
+        trace!(%ip, ?host, "reverse lookup");
+
+        let mut cache = self.0.write().unwrap_or_else(PoisonError::into_inner);
+        if cache.len() >= MAX_CACHED_HOSTNAMES {
+            cache.clear();
+        }
+        cache.insert(ip, host.clone());
+        host
+    }
+}
+
+/// Room for the longest hostname `getnameinfo` will give us (`NI_MAXHOST`)
+#[cfg(unix)]
+const MAX_HOSTNAME_LENGTH: usize = 1025;
+
+/// Ask the system resolver for `ip`'s hostname. This blocks!
+#[cfg(unix)]
+fn reverse_lookup(ip: IpAddr) -> Option<String> {
+    use std::ffi::CStr;
+    use std::mem;
+
+    let mut host = [0 as libc::c_char; MAX_HOSTNAME_LENGTH];
+    // SAFETY: the sockaddrs are zeroed before we fill in what we know, their
+    // lengths match their types, and `host` is as long as we say it is
+    let found = unsafe {
+        let mut lookup = |addr: *const libc::sockaddr, len: usize| {
+            libc::getnameinfo(
+                addr,
+                len as libc::socklen_t,
+                host.as_mut_ptr(),
+                host.len() as libc::socklen_t,
+                std::ptr::null_mut(),
+                0,
+                libc::NI_NAMEREQD,
+            )
+        };
+        match ip {
+            IpAddr::V4(ip) => {
+                let mut addr: libc::sockaddr_in = mem::zeroed();
+                addr.sin_family = libc::AF_INET as libc::sa_family_t;
+                addr.sin_addr.s_addr = u32::from_ne_bytes(ip.octets());
+                lookup(
+                    &addr as *const _ as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>(),
+                )
+            }
+            IpAddr::V6(ip) => {
+                let mut addr: libc::sockaddr_in6 = mem::zeroed();
+                addr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                addr.sin6_addr.s6_addr = ip.octets();
+                lookup(
+                    &addr as *const _ as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>(),
+                )
+            }
+        }
+    };
+    if found != 0 {
+        return None;
+    }
+
+    // SAFETY: `getnameinfo` succeeded, so `host` holds a NUL-terminated string
+    let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+    host.to_str().ok().map(str::to_string)
+}
+
+#[cfg(not(unix))]
+fn reverse_lookup(_ip: IpAddr) -> Option<String> {
+    None
+}
@@ -0,0 +1,143 @@
+//! Message text, in each language people can pick with `locale`
+
+use std::fmt::Display;
+
+/// Everyone reads English until they pick something else
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Templates by key, with `{}`s filled in order (see `text`)
+type Catalog = &'static [(&'static str, &'static str)];
+
+/// Every template: other catalogs fall back to these
+const EN: Catalog = &[
+    ("arrived", "{} arrived."),
+    ("arrived_from", "{} arrived from {}."),
+    ("you_arrived", "You arrived in {}."),
+    ("you_arrived_from", "You arrived in {} from {}."),
+    ("left_for", "{} left for {}."),
+    ("logged_off", "{} logged off."),
+    ("disconnected", "{} disconnected."),
+    ("logged_out", "You have logged out."),
+    ("you_say", "You say, '{}'"),
+    ("says", "{} says, '{}'"),
+    ("emote", "{} {}."),
+    ("emote_punctuated", "{} {}"),
+    ("emote_at", "{} {} at {}."),
+    ("emote_at_you", "{} {} at you."),
+    ("channel_you_say", "[{}] You say, '{}'"),
+    ("channel_says", "[{}] {} says, '{}'"),
+    ("you_shout", "You shout, '{}'"),
+    ("shouts", "{} shouts, '{}'"),
+    ("you_tell", "You tell {}, '{}'"),
+    ("tells_you", "{} tells you, '{}'"),
+    ("you_whisper", "You whisper to {}, '{}'"),
+    ("whispers_to_you", "{} whispers to you, '{}'"),
+    ("whispers_something", "{} whispers something to {}."),
+    ("online", "Online ({}):"),
+    ("online_by_room", "Online ({}), by room:"),
+    ("room_people", "{} ({}):"),
+    ("away_name", "{} (away)"),
+    ("via", "{} via {}"),
+    ("topic", "Topic: {}"),
+    ("no_topic", "There is no topic."),
+    ("here", "Here: {}"),
+    ("no_matches", "No one matches '{}'."),
+    ("matching", "Matching '{}' ({}):"),
+    ("more_pages", "(use '{}' for more)"),
+    ("nothing_typed", "You haven't typed anything yet."),
+    ("you_typed", "You typed:"),
+    ("no_bio", "No bio."),
+    ("away", "Away."),
+    ("away_message", "Away: {}"),
+    ("here_for", "Here for {}."),
+    ("sessions", "Sessions ({}):"),
+    ("session", "#{} {} via {}"),
+    ("in_room", " in room {}"),
+    ("not_in_room", " (not in a room)"),
+    ("idle", ", idle {}"),
+    ("stats", "Up for {}. {} connected, {} registered, {} rooms ({} occupied)."),
+    ("version", "much {}, up for {}."),
+    ("you_set_topic", "You set the topic to: {}"),
+    ("set_topic", "{} set the topic to: {}"),
+];
+
+const ES: Catalog = &[
+    ("arrived", "{} llegó."),
+    ("arrived_from", "{} llegó desde {}."),
+    ("you_arrived", "Llegaste a {}."),
+    ("you_arrived_from", "Llegaste a {} desde {}."),
+    ("left_for", "{} se fue a {}."),
+    ("logged_off", "{} cerró la sesión."),
+    ("disconnected", "{} se desconectó."),
+    ("logged_out", "Has cerrado la sesión."),
+    ("you_say", "Dices, '{}'"),
+    ("says", "{} dice, '{}'"),
+    ("channel_you_say", "[{}] Dices, '{}'"),
+    ("channel_says", "[{}] {} dice, '{}'"),
+    ("you_shout", "Gritas, '{}'"),
+    ("shouts", "{} grita, '{}'"),
+    ("you_tell", "Le dices a {}, '{}'"),
+    ("tells_you", "{} te dice, '{}'"),
+    ("you_whisper", "Le susurras a {}, '{}'"),
+    ("whispers_to_you", "{} te susurra, '{}'"),
+    ("whispers_something", "{} le susurra algo a {}."),
+    ("online", "Conectados ({}):"),
+    ("online_by_room", "Conectados ({}), por sala:"),
+    ("away_name", "{} (ausente)"),
+    ("topic", "Tema: {}"),
+    ("no_topic", "No hay tema."),
+    ("here", "Aquí: {}"),
+    ("no_matches", "Nadie coincide con '{}'."),
+    ("matching", "Coinciden con '{}' ({}):"),
+    ("more_pages", "(usa '{}' para ver más)"),
+    ("nothing_typed", "Todavía no has escrito nada."),
+    ("you_typed", "Escribiste:"),
+    ("no_bio", "Sin biografía."),
+    ("away", "Ausente."),
+    ("away_message", "Ausente: {}"),
+    ("you_set_topic", "Cambiaste el tema a: {}"),
+    ("set_topic", "{} cambió el tema a: {}"),
+];
+
+const CATALOGS: &[(&str, Catalog)] = &[("en", EN), ("es", ES)];
+
+/// Codes for every locale we have a catalog for, e.g., for `locale` to list
+pub fn locales() -> Vec<&'static str> {
+    CATALOGS.iter().map(|(code, _)| *code).collect()
+}
+
+pub fn is_locale(code: &str) -> bool {
+    CATALOGS.iter().any(|(known, _)| *known == code)
+}
+
+fn lookup(catalog: Catalog, key: &str) -> Option<&'static str> {
+    catalog
+        .iter()
+        .find(|(known, _)| *known == key)
+        .map(|(_, template)| *template)
+}
+
+/// The template for `key` in `locale`, or in English if `locale` (or its
+/// catalog) doesn't have one
+fn template(locale: &str, key: &str) -> &'static str {
+    CATALOGS
+        .iter()
+        .find(|(code, _)| *code == locale)
+        .and_then(|(_, catalog)| lookup(catalog, key))
+        .or_else(|| lookup(EN, key))
+        .unwrap_or_else(|| panic!("no template for {}", key))
+}
+
+/// The text for `key` in `locale`, filling each `{}` with the next of `args`.
+/// Arguments are never themselves filled in, so people can say `{}` safely.
+pub fn text(locale: &str, key: &str, args: &[&dyn Display]) -> String {
+    let mut pieces = template(locale, key).split("{}");
+    let mut s = pieces.next().unwrap_or("").to_string();
+    for (i, piece) in pieces.enumerate() {
+        if let Some(arg) = args.get(i) {
+            s.push_str(&arg.to_string());
+        }
+        s.push_str(piece);
+    }
+    s
+}
@@ -1,46 +1,693 @@
+use std::fmt::Display;
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Serialize, Serializer};
+
+use crate::world::locale::*;
+use crate::world::paging::Paging;
 use crate::world::person::*;
 use crate::world::room::*;
+use crate::world::state::Connection;
+use crate::world::style::*;
+
+/// Per-connection preferences for turning a `Message` into text
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    /// `strftime`-style format for timestamps on speech, or `None` to omit them
+    pub timestamp_format: Option<String>,
+    /// Whether to emit ANSI color codes
+    pub color: bool,
+    /// Which catalog to take text from (see `locale`); unknown locales,
+    /// including the empty default, get English
+    pub locale: String,
+    /// Whether to tell the receiver about their own arrivals. Terminals see
+    /// the room they arrive in (with `look`) instead.
+    pub own_arrivals: bool,
+}
+
+/// One person in a `who` listing
+#[derive(Clone, Debug, Serialize)]
+pub struct WhoEntry {
+    pub name: String,
+    pub away: bool,
+    /// How they're connected; only filled in for administrators
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transport: Option<&'static str>,
+}
+
+/// One room's people in a `who all` listing
+#[derive(Clone, Debug, Serialize)]
+pub struct RoomGroup {
+    pub name: String,
+    /// Sorted by name (see `WhoEntry::listing`)
+    pub people: Vec<WhoEntry>,
+}
+
+/// One connection in a `sessions` listing
+#[derive(Clone, Debug, Serialize)]
+pub struct SessionEntry {
+    #[serde(skip)]
+    pub id: PersonId,
+    pub name: String,
+    /// As it appears in the logs, so HTTP session IDs stay secret
+    #[serde(serialize_with = "as_display")]
+    pub conn: Connection,
+    /// The TCP peer's hostname, if we've looked it up (see `Hostnames`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    /// The room they're in, if any
+    pub loc: Option<RoomId>,
+    /// How long since they last sent a command
+    #[serde(rename = "idle_secs", serialize_with = "as_secs")]
+    pub idle: Duration,
+}
+
+/// Server statistics, for operators
+#[derive(Clone, Debug, Serialize)]
+pub struct Stats {
+    #[serde(rename = "uptime_secs", serialize_with = "as_secs")]
+    pub uptime: Duration,
+    /// Number of live connections
+    pub connected: usize,
+    /// Number of registered accounts
+    pub accounts: usize,
+    pub rooms: usize,
+    /// Number of rooms with anyone in them
+    pub occupied: usize,
+}
+
+/// Render a duration coarsely, e.g., "2d 3h", "4m 10s"
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, mins)
+    } else if mins > 0 {
+        format!("{}m {}s", mins, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Durations go to clients as whole seconds
+fn as_secs<S: Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_u64(d.as_secs())
+}
+
+/// Times go to clients as RFC 3339 strings, in UTC
+fn as_rfc3339<S: Serializer>(time: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(&DateTime::<Utc>::from(*time).to_rfc3339())
+}
+
+fn as_display<T: Display, S: Serializer>(t: &T, s: S) -> Result<S::Ok, S::Error> {
+    s.collect_str(t)
+}
+
+/// Just the name of the room someone came from
+fn as_room_name<S: Serializer>(from: &Option<(RoomId, String)>, s: S) -> Result<S::Ok, S::Error> {
+    match from {
+        Some((_, name)) => s.serialize_some(name),
+        None => s.serialize_none(),
+    }
+}
+
+/// Why someone left a room
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DepartReason {
+    /// They went to another room
+    Moved { to: RoomId, to_name: String },
+    /// They logged out (or were logged out by an admin)
+    LoggedOff,
+    /// Their connection went away
+    Disconnected,
+}
 
 /// Messages from, e.g., commands
-#[derive(Clone, Debug)]
+///
+/// Terminals get messages rendered as text (see `render`); web clients get
+/// them as JSON, tagged with their `type` (e.g., `{"type": "say", ...}`).
+/// `PersonId`s stay on the server: clients know people by name (names are
+/// unique), and learn their own name when they log in; events about the
+/// receiver themselves (see `about`) are marked `"you": true`. Times are RFC 3339
+/// strings and durations are whole seconds (in fields ending `_secs`).
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
 pub enum Message {
+    /// Someone came in, from another room (`from`, with its name) or by
+    /// logging in (`None`)
     Arrive {
+        #[serde(skip)]
         id: PersonId,
         name: String,
         loc: RoomId,
+        loc_name: String,
+        #[serde(rename = "from_name", serialize_with = "as_room_name")]
+        from: Option<(RoomId, String)>,
     },
     /// Someone left
     Depart {
+        #[serde(skip)]
         id: PersonId,
         name: String,
         loc: RoomId,
+        reason: DepartReason,
     },
     /// Force a logout
     Logout,
+    /// A reply from the server to a single person
+    Notice { text: String },
     /// Someone spoke
     Say {
+        #[serde(skip)]
         speaker: PersonId,
         speaker_name: String,
         loc: RoomId,
         text: String,
+        /// When the message was spoken (not when it was rendered)
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
+    },
+    /// Someone acted something out, maybe at someone else in the room
+    Emote {
+        #[serde(skip)]
+        actor: PersonId,
+        actor_name: String,
+        loc: RoomId,
+        action: String,
+        #[serde(skip)]
+        target: Option<PersonId>,
+        target_name: Option<String>,
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
+    },
+    /// Someone spoke on a channel, to everyone who has joined it
+    Channel {
+        channel: String,
+        #[serde(skip)]
+        speaker: PersonId,
+        speaker_name: String,
+        text: String,
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
+    },
+    /// Someone spoke to everyone on the server
+    Shout {
+        #[serde(skip)]
+        speaker: PersonId,
+        speaker_name: String,
+        text: String,
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
+    },
+    /// Someone spoke privately to someone else
+    Tell {
+        #[serde(skip)]
+        from: PersonId,
+        from_name: String,
+        #[serde(skip)]
+        to: PersonId,
+        to_name: String,
+        text: String,
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
     },
+    /// Someone spoke privately to someone else in the same room; the rest of
+    /// the room only sees that it happened, so they get no `text`
+    Whisper {
+        #[serde(skip)]
+        from: PersonId,
+        from_name: String,
+        #[serde(skip)]
+        to: PersonId,
+        to_name: String,
+        text: Option<String>,
+        #[serde(serialize_with = "as_rfc3339")]
+        time: SystemTime,
+    },
+    /// One page of who is online, sorted by name (see `WhoEntry::listing`)
+    Who {
+        people: Vec<WhoEntry>,
+        #[serde(flatten)]
+        paging: Paging,
+    },
+    /// Who is online, grouped by room, with rooms sorted by name
+    WhoByRoom { rooms: Vec<RoomGroup> },
+    /// A description of the room the receiver is in, people sorted by name
+    Look {
+        name: String,
+        description: Option<String>,
+        topic: Option<String>,
+        people: Vec<WhoEntry>,
+    },
+    /// One page of accounts matching a search, sorted by name
+    Found {
+        query: String,
+        names: Vec<String>,
+        #[serde(flatten)]
+        paging: Paging,
+    },
+    /// What the receiver typed recently, oldest first
+    Recall { lines: Vec<String> },
+    /// Someone's profile
+    Profile {
+        name: String,
+        pronouns: Option<String>,
+        bio: Option<String>,
+    },
+    /// A closer look at someone in the same room
+    Examine {
+        name: String,
+        pronouns: Option<String>,
+        bio: Option<String>,
+        away: Option<String>,
+        /// How long they've been in the room
+        #[serde(rename = "here_for_secs", serialize_with = "as_secs")]
+        here_for: Duration,
+    },
+    /// One page of every connection, for admins
+    Sessions {
+        sessions: Vec<SessionEntry>,
+        #[serde(flatten)]
+        paging: Paging,
+    },
+    /// Server statistics
+    Stats { stats: Stats },
+    /// Which build is running, and for how long
+    Version {
+        version: String,
+        #[serde(rename = "uptime_secs", serialize_with = "as_secs")]
+        uptime: Duration,
+    },
+    /// How many people are online, sent every so often to keep idle
+    /// connections alive, for web clients; terminals never see it
+    Heartbeat { online: usize },
+    /// Someone in the room is typing, for web clients; terminals never see it
+    Typing {
+        #[serde(skip)]
+        who: PersonId,
+        name: String,
+        loc: RoomId,
+    },
+    /// Someone changed the room's topic
+    Topic {
+        #[serde(skip)]
+        setter: PersonId,
+        setter_name: String,
+        loc: RoomId,
+        topic: String,
+    },
+}
+
+/// How `person` appears in `who` listings, e.g., `@bob (away) via TCP`
+fn who_name(person: &WhoEntry, locale: &str) -> String {
+    let name = if person.away {
+        text(locale, "away_name", &[&person.name])
+    } else {
+        person.name.clone()
+    };
+    match person.transport {
+        Some(transport) => text(locale, "via", &[&name, &transport]),
+        None => name,
+    }
+}
+
+impl WhoEntry {
+    pub fn new(p: &Person) -> Self {
+        WhoEntry {
+            name: p.name.clone(),
+            away: p.away.is_some(),
+            transport: None,
+        }
+    }
+
+    /// Entries for `people`, sorted by name ignoring case (rooms are sets, so
+    /// they have no order of their own)
+    pub fn listing<'a, I: Iterator<Item = &'a Person>>(people: I) -> Vec<WhoEntry> {
+        WhoEntry::sorted(people.map(WhoEntry::new).collect())
+    }
+
+    /// Sort entries the way `listing` does
+    pub fn sorted(mut entries: Vec<WhoEntry>) -> Vec<WhoEntry> {
+        entries.sort_by_cached_key(|entry| (canonical_name(&entry.name), entry.name.clone()));
+        entries
+    }
 }
 
 impl Message {
+    /// When the message was created, for messages that carry a timestamp
+    pub fn time(&self) -> Option<SystemTime> {
+        match self {
+            Message::Say { time, .. }
+            | Message::Emote { time, .. }
+            | Message::Channel { time, .. }
+            | Message::Shout { time, .. }
+            | Message::Tell { time, .. }
+            | Message::Whisper { time, .. } => Some(*time),
+            Message::Arrive { .. }
+            | Message::Depart { .. }
+            | Message::Logout
+            | Message::Notice { .. }
+            | Message::Who { .. }
+            | Message::WhoByRoom { .. }
+            | Message::Look { .. }
+            | Message::Found { .. }
+            | Message::Recall { .. }
+            | Message::Profile { .. }
+            | Message::Examine { .. }
+            | Message::Sessions { .. }
+            | Message::Stats { .. }
+            | Message::Version { .. }
+            | Message::Heartbeat { .. }
+            | Message::Typing { .. }
+            | Message::Topic { .. } => None,
+        }
+    }
+
+    /// Should line-oriented (terminal) clients get this message at all?
+    pub fn for_terminals(&self) -> bool {
+        !matches!(self, Message::Heartbeat { .. } | Message::Typing { .. })
+    }
+
+    /// Who this message is about, when it matters whether that's who it's
+    /// going to (e.g., your own arrival)
+    pub fn about(&self) -> Option<PersonId> {
+        match self {
+            Message::Arrive { id, .. } | Message::Depart { id, .. } => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// How to color this message for `receiver` (`None` means plain)
+    fn style(&self, receiver: PersonId) -> Option<Style> {
+        match self {
+            Message::Say { speaker, .. } if *speaker == receiver => None,
+            Message::Say { .. } => Some(Style::Speech),
+            Message::Emote { actor, .. } if *actor == receiver => None,
+            Message::Emote { .. } => Some(Style::Speech),
+            Message::Channel { speaker, .. } if *speaker == receiver => None,
+            Message::Channel { .. } => Some(Style::Speech),
+            Message::Shout { .. } => Some(Style::Shout),
+            Message::Tell { from, .. } if *from == receiver => None,
+            Message::Tell { .. } => Some(Style::Speech),
+            Message::Whisper { from, .. } if *from == receiver => None,
+            Message::Whisper { to, .. } if *to == receiver => Some(Style::Speech),
+            Message::Whisper { .. } => Some(Style::System),
+            Message::Arrive { .. }
+            | Message::Depart { .. }
+            | Message::Logout
+            | Message::Notice { .. }
+            | Message::Who { .. }
+            | Message::WhoByRoom { .. }
+            | Message::Look { .. }
+            | Message::Found { .. }
+            | Message::Recall { .. }
+            | Message::Profile { .. }
+            | Message::Examine { .. }
+            | Message::Sessions { .. }
+            | Message::Stats { .. }
+            | Message::Version { .. }
+            | Message::Heartbeat { .. }
+            | Message::Typing { .. }
+            | Message::Topic { .. } => Some(Style::System),
+        }
+    }
+
     pub async fn render(&self, receiver: PersonId) -> String {
-        // LATER i18n
+        self.render_with_opts(receiver, &RenderOptions::default()).await
+    }
+
+    pub async fn render_with_opts(&self, receiver: PersonId, opts: &RenderOptions) -> String {
+        let line = self.render_line(receiver, opts);
+        let line = match self.style(receiver) {
+            Some(style) => style.paint(&line, opts.color),
+            None => line,
+        };
+
+        match (self.time(), &opts.timestamp_format) {
+            (Some(time), Some(format)) if !line.is_empty() => {
+                let time: DateTime<Local> = time.into();
+                format!("[{}] {}", time.format(format), line)
+            }
+            _ => line,
+        }
+    }
+
+    fn render_line(&self, receiver: PersonId, opts: &RenderOptions) -> String {
+        let locale = opts.locale.as_str();
+        let t = |key, args: &[&dyn Display]| text(locale, key, args);
         match self {
-            Message::Arrive { id, .. } if *id == receiver => "".to_string(),
-            Message::Arrive { name, .. } => format!("{} arrived.", name),
+            Message::Arrive { id, .. } if *id == receiver && !opts.own_arrivals => "".to_string(),
+            Message::Arrive {
+                id,
+                loc_name,
+                from: Some((_, from_name)),
+                ..
+            } if *id == receiver => t("you_arrived_from", &[loc_name, from_name]),
+            Message::Arrive { id, loc_name, .. } if *id == receiver => t("you_arrived", &[loc_name]),
+            Message::Arrive {
+                name,
+                from: Some((_, from_name)),
+                ..
+            } => t("arrived_from", &[name, from_name]),
+            Message::Arrive { name, .. } => t("arrived", &[name]),
             Message::Depart { id, .. } if *id == receiver => "".to_string(),
-            Message::Depart { name, .. } => format!("{} left.", name),
-            Message::Logout => "You have logged out.".to_string(),
-            Message::Say { speaker, text, .. } if *speaker == receiver => {
-                format!("You say, '{}'", text)
-            }
+            Message::Depart { name, reason, .. } => match reason {
+                DepartReason::Moved { to_name, .. } => t("left_for", &[name, to_name]),
+                DepartReason::LoggedOff => t("logged_off", &[name]),
+                DepartReason::Disconnected => t("disconnected", &[name]),
+            },
+            Message::Logout => t("logged_out", &[]),
+            Message::Notice { text } => text.clone(),
+            Message::Say { speaker, text, .. } if *speaker == receiver => t("you_say", &[text]),
             Message::Say {
                 speaker_name, text, ..
-            } => format!("{} says, '{}'", speaker_name, text),
+            } => t("says", &[speaker_name, text]),
+            Message::Emote {
+                actor_name,
+                action,
+                target: Some(target),
+                ..
+            } if *target == receiver => t("emote_at_you", &[actor_name, action]),
+            Message::Emote {
+                actor_name,
+                action,
+                target_name: Some(target_name),
+                ..
+            } => t("emote_at", &[actor_name, action, target_name]),
+            Message::Emote {
+                actor_name, action, ..
+            } if action.ends_with(&['.', '!', '?'][..]) => {
+                t("emote_punctuated", &[actor_name, action])
+            }
+            Message::Emote {
+                actor_name, action, ..
+            } => t("emote", &[actor_name, action]),
+            Message::Channel {
+                channel,
+                speaker,
+                text,
+                ..
+            } if *speaker == receiver => t("channel_you_say", &[channel, text]),
+            Message::Channel {
+                channel,
+                speaker_name,
+                text,
+                ..
+            } => t("channel_says", &[channel, speaker_name, text]),
+            Message::Shout { speaker, text, .. } if *speaker == receiver => {
+                t("you_shout", &[text])
+            }
+            Message::Shout {
+                speaker_name, text, ..
+            } => t("shouts", &[speaker_name, text]),
+            Message::Tell {
+                from, to_name, text, ..
+            } if *from == receiver => t("you_tell", &[to_name, text]),
+            Message::Tell {
+                from_name, text, ..
+            } => t("tells_you", &[from_name, text]),
+            Message::Whisper {
+                from,
+                to_name,
+                text: Some(text),
+                ..
+            } if *from == receiver => t("you_whisper", &[to_name, text]),
+            Message::Whisper {
+                from_name,
+                text: Some(text),
+                ..
+            } => t("whispers_to_you", &[from_name, text]),
+            Message::Whisper {
+                from_name,
+                to_name,
+                text: None,
+                ..
+            } => t("whispers_something", &[from_name, to_name]),
+            Message::Who { people, paging } => {
+                let mut s = t("online", &[&paging.total]);
+                for person in people {
+                    s.push_str("\n  ");
+                    s.push_str(&who_name(person, locale));
+                }
+                if let Some(next) = paging.next() {
+                    s.push_str("\n  ");
+                    s.push_str(&t("more_pages", &[&format!("who {}", next)]));
+                }
+                s
+            }
+            Message::WhoByRoom { rooms } => {
+                let online: usize = rooms.iter().map(|room| room.people.len()).sum();
+                let mut s = t("online_by_room", &[&online]);
+                for room in rooms {
+                    s.push_str("\n  ");
+                    s.push_str(&t("room_people", &[&room.name, &room.people.len()]));
+                    for person in &room.people {
+                        s.push_str("\n    ");
+                        s.push_str(&who_name(person, locale));
+                    }
+                }
+                s
+            }
+            Message::Look {
+                name,
+                description,
+                topic,
+                people,
+            } => {
+                let mut s = format!("{}\n", name);
+                if let Some(description) = description {
+                    s.push_str(description);
+                    s.push('\n');
+                }
+                match topic {
+                    Some(topic) => s.push_str(&t("topic", &[topic])),
+                    None => s.push_str(&t("no_topic", &[])),
+                };
+                let names: Vec<String> = people
+                    .iter()
+                    .map(|person| {
+                        if person.away {
+                            t("away_name", &[&person.name])
+                        } else {
+                            person.name.clone()
+                        }
+                    })
+                    .collect();
+                s.push('\n');
+                s.push_str(&t("here", &[&names.join(", ")]));
+                s
+            }
+            Message::Found { query, names, .. } if names.is_empty() => t("no_matches", &[query]),
+            Message::Found {
+                query,
+                names,
+                paging,
+            } => {
+                let mut s = t("matching", &[query, &paging.total]);
+                for name in names {
+                    s.push_str("\n  ");
+                    s.push_str(name);
+                }
+                if let Some(next) = paging.next() {
+                    s.push_str("\n  ");
+                    s.push_str(&t("more_pages", &[&format!("find {} {}", query, next)]));
+                }
+                s
+            }
+            Message::Recall { lines } if lines.is_empty() => t("nothing_typed", &[]),
+            Message::Recall { lines } => {
+                let mut s = t("you_typed", &[]);
+                for (i, line) in lines.iter().enumerate() {
+                    s.push_str(&format!("\n  {}. {}", i + 1, line));
+                }
+                s
+            }
+            Message::Profile {
+                name,
+                pronouns,
+                bio,
+            } => {
+                let mut s = match pronouns {
+                    Some(pronouns) => format!("{} ({})", name, pronouns),
+                    None => name.clone(),
+                };
+                s.push('\n');
+                match bio {
+                    Some(bio) => s.push_str(bio),
+                    None => s.push_str(&t("no_bio", &[])),
+                }
+                s
+            }
+            Message::Examine {
+                name,
+                pronouns,
+                bio,
+                away,
+                here_for,
+            } => {
+                let mut s = match pronouns {
+                    Some(pronouns) => format!("{} ({})", name, pronouns),
+                    None => name.clone(),
+                };
+                if let Some(bio) = bio {
+                    s.push('\n');
+                    s.push_str(bio);
+                }
+                match away.as_deref() {
+                    Some("") => s.push_str(&format!("\n{}", t("away", &[]))),
+                    Some(away) => s.push_str(&format!("\n{}", t("away_message", &[&away]))),
+                    None => (),
+                }
+                s.push('\n');
+                s.push_str(&t("here_for", &[&format_duration(*here_for)]));
+                s
+            }
+            Message::Sessions { sessions, paging } => {
+                let mut s = t("sessions", &[&paging.total]);
+                for session in sessions {
+                    s.push_str("\n  ");
+                    let via = match &session.host {
+                        Some(host) => format!("{} ({})", host, session.conn),
+                        None => session.conn.to_string(),
+                    };
+                    s.push_str(&t("session", &[&session.id, &session.name, &via]));
+                    match session.loc {
+                        Some(loc) => s.push_str(&t("in_room", &[&loc])),
+                        None => s.push_str(&t("not_in_room", &[])),
+                    }
+                    s.push_str(&t("idle", &[&format_duration(session.idle)]));
+                }
+                if let Some(next) = paging.next() {
+                    s.push_str("\n  ");
+                    s.push_str(&t("more_pages", &[&format!("sessions {}", next)]));
+                }
+                s
+            }
+            Message::Stats { stats } => t(
+                "stats",
+                &[
+                    &format_duration(stats.uptime),
+                    &stats.connected,
+                    &stats.accounts,
+                    &stats.rooms,
+                    &stats.occupied,
+                ],
+            ),
+            Message::Version { version, uptime } => {
+                t("version", &[version, &format_duration(*uptime)])
+            }
+            Message::Heartbeat { .. } | Message::Typing { .. } => "".to_string(),
+            Message::Topic { setter, topic, .. } if *setter == receiver => {
+                t("you_set_topic", &[topic])
+            }
+            Message::Topic {
+                setter_name, topic, ..
+            } => t("set_topic", &[setter_name, topic]),
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,100 @@
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Counters and gauges for monitoring.
+///
+/// `State` keeps these up to date, but they're atomics behind their own `Arc`,
+/// so `/metrics` can read them without taking the `State` lock.
+#[derive(Debug)]
+pub struct Metrics {
+    started_at: Instant,
+    pub connected_users: AtomicUsize,
+    pub registered_accounts: AtomicUsize,
+    pub rooms: AtomicUsize,
+    /// Web sessions we remember, whether or not they're in the world
+    pub http_sessions: AtomicUsize,
+    /// Messages delivered to a peer's queue (a roomcast to five people is five)
+    pub messages_sent: AtomicU64,
+    /// Peers disconnected because their message queue filled up
+    pub slow_peers: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new(started_at: Instant) -> Self {
+        Metrics {
+            started_at,
+            connected_users: AtomicUsize::new(0),
+            registered_accounts: AtomicUsize::new(0),
+            rooms: AtomicUsize::new(0),
+            http_sessions: AtomicUsize::new(0),
+            messages_sent: AtomicU64::new(0),
+            slow_peers: AtomicU64::new(0),
+        }
+    }
+
+    pub fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn slow_peer_disconnected(&self) {
+        self.slow_peers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Render in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        let mut metric = |name: &str, kind: &str, help: &str, value: String| {
+            // writing to a `String` can't fail
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            let _ = writeln!(out, "{} {}", name, value);
+        };
+
+        metric(
+            "much_connected_users",
+            "gauge",
+            "Number of live connections.",
+            self.connected_users.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_registered_accounts",
+            "gauge",
+            "Number of registered accounts.",
+            self.registered_accounts.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_rooms",
+            "gauge",
+            "Number of rooms.",
+            self.rooms.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_http_sessions",
+            "gauge",
+            "Number of web sessions, in the world or not.",
+            self.http_sessions.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_messages_sent_total",
+            "counter",
+            "Messages delivered to connected peers.",
+            self.messages_sent.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_slow_peers_total",
+            "counter",
+            "Peers disconnected for not keeping up with their messages.",
+            self.slow_peers.load(Ordering::Relaxed).to_string(),
+        );
+        metric(
+            "much_uptime_seconds",
+            "gauge",
+            "Seconds since the server started.",
+            self.started_at.elapsed().as_secs().to_string(),
+        );
+
+        out
+    }
+}
@@ -2,4 +2,11 @@ pub mod state;
 pub mod person;
 pub mod room;
 pub mod command;
-pub mod message;
\ No newline at end of file
+pub mod message;
+pub mod flood;
+pub mod style;
+pub mod storage;
+pub mod metrics;
+pub mod locale;
+pub mod hostname;
+pub mod paging;
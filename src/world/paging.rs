@@ -0,0 +1,42 @@
+//! Splitting long listings (e.g., `who`) into pages, so nobody's terminal
+//! gets flooded
+
+use serde::Serialize;
+
+/// Where a page falls in its listing (see `paginate`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Paging {
+    /// Which page this is, counting from 1
+    pub page: usize,
+    /// How many pages there are (an empty listing still has one)
+    pub pages: usize,
+    /// How many items there are on all the pages together
+    pub total: usize,
+}
+
+impl Paging {
+    /// The next page's number, if this isn't the last page
+    pub fn next(&self) -> Option<usize> {
+        if self.page < self.pages {
+            Some(self.page + 1)
+        } else {
+            None
+        }
+    }
+}
+
+/// Page `page` of `items`, `per_page` at a time. Pages count from 1; asking
+/// for a page past the end gets the last one.
+pub fn paginate<T>(items: Vec<T>, page: usize, per_page: usize) -> (Vec<T>, Paging) {
+    let per_page = per_page.max(1);
+    let total = items.len();
+    let pages = items.chunks(per_page).count().max(1);
+    let page = page.max(1).min(pages);
+
+    let items = items
+        .into_iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .collect();
+    (items, Paging { page, pages, total })
+}
@@ -1,3 +1,10 @@
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::world::locale::DEFAULT_LOCALE;
+use crate::world::message::RenderOptions;
 use crate::world::room::*;
 use crate::world::state::Connection;
 
@@ -7,29 +14,188 @@ pub type PersonId = u64;
 // Number of characters to use for the password salt
 pub const PASSWD_SALT_LENGTH: usize = 16; 
 
+/// Longest name (email address or Twitter handle) we'll register
+pub const MAX_NAME_LENGTH: usize = 64;
+
+/// Longest bio someone can set for their profile, in characters
+pub const MAX_BIO_LENGTH: usize = 280;
+
+/// Longest pronouns someone can set for their profile, in characters
+pub const MAX_PRONOUNS_LENGTH: usize = 32;
+
+/// Least time between typing indicators from one session; clients may send
+/// them on every keystroke, and we drop the extras
+pub const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The form of a name used to tell accounts apart, so `@Alice` and `@alice`
+/// are the same person
+pub fn canonical_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Check that `name` looks like an email address or Twitter handle, explaining
+/// what's wrong if it doesn't
+pub fn validate_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err("Please enter an email address or Twitter handle.".to_string());
+    }
+
+    if name.len() > MAX_NAME_LENGTH {
+        return Err(format!(
+            "That name is too long; the limit is {} characters.",
+            MAX_NAME_LENGTH
+        ));
+    }
+
+    if name.chars().any(char::is_control) {
+        return Err("Names can't contain control characters.".to_string());
+    }
+
+    // non-ASCII names invite lookalikes (e.g., a Cyrillic `а` for `a`)
+    if !name.is_ascii() {
+        return Err("Names must be plain ASCII, without accents or symbols.".to_string());
+    }
+
+    if name.contains(char::is_whitespace) {
+        return Err("Names can't contain spaces.".to_string());
+    }
+
+    match name.rfind('@') {
+        None => Err(
+            "Please enter an email address (you@example.com) or Twitter handle (@you)."
+                .to_string(),
+        ),
+        Some(0) => {
+            let handle = &name[1..];
+            if handle.is_empty() {
+                Err("Please enter a Twitter handle after the @.".to_string())
+            } else if !handle.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                Err("Twitter handles may only contain letters, numbers, and underscores.".to_string())
+            } else {
+                Ok(())
+            }
+        }
+        Some(at) => {
+            if name[at + 1..].is_empty() {
+                Err("Email addresses need a domain, like you@example.com.".to_string())
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The parts of a profile people set for themselves
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileField {
+    Bio,
+    Pronouns,
+}
+
+impl ProfileField {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProfileField::Bio => "bio",
+            ProfileField::Pronouns => "pronouns",
+        }
+    }
+
+    pub fn max_length(self) -> usize {
+        match self {
+            ProfileField::Bio => MAX_BIO_LENGTH,
+            ProfileField::Pronouns => MAX_PRONOUNS_LENGTH,
+        }
+    }
+
+    /// Tidy up `text` for storage: control characters (which could, e.g.,
+    /// recolor other people's terminals) become spaces
+    pub fn clean(text: &str) -> String {
+        text.chars()
+            .map(|c| if c.is_control() { ' ' } else { c })
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+}
+
 /// A logged-in connection to the server
-#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+///
+/// People are compared and hashed by `id` alone, so the copy held in a room's
+/// occupant set still matches the session's copy after preferences change.
+#[derive(Clone, Debug)]
 pub struct Person {
     pub id: PersonId,
     pub name: String,
     /// Last known location/default location
     pub loc: RoomId,
     pub conn: Connection,
+    /// How messages are rendered for this connection
+    pub render: RenderOptions,
+    /// Away message, if the person is away
+    pub away: Option<String>,
+    /// When we last passed along a typing indicator for them
+    pub last_typing: Option<Instant>,
+    /// When they arrived in their current room
+    pub arrived_at: Instant,
 }
 
 impl Person {
     pub fn new(p: &PersonRecord, conn: Connection) -> Self {
+        let color = p.color && conn.is_line_oriented();
+        let own_arrivals = !conn.is_line_oriented();
+        // web clients get structured messages, and do their own translating
+        let locale = if conn.is_line_oriented() {
+            p.locale.clone()
+        } else {
+            DEFAULT_LOCALE.to_string()
+        };
+
         Person {
             id: p.id,
             name: p.name.clone(),
             loc: p.loc,
             conn,
+            render: RenderOptions {
+                color,
+                locale,
+                own_arrivals,
+                ..RenderOptions::default()
+            },
+            away: None,
+            last_typing: None,
+            arrived_at: Instant::now(),
+        }
+    }
+
+    /// Should we pass along a typing indicator now? (see `TYPING_INTERVAL`)
+    pub fn typing_allowed(&mut self) -> bool {
+        let now = Instant::now();
+        match self.last_typing {
+            Some(last) if now.duration_since(last) < TYPING_INTERVAL => false,
+            _ => {
+                self.last_typing = Some(now);
+                true
+            }
         }
     }
 }
 
+impl PartialEq for Person {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Person {}
+
+impl Hash for Person {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
 /// A person/user. Not necessarily connected.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct PersonRecord {
     pub id: PersonId,
     pub name: String,
@@ -40,4 +206,23 @@ pub struct PersonRecord {
     pub salt: String,
     /// The hashed password
     pub password: String,
+
+    /// Whether TCP sessions should use ANSI color
+    pub color: bool,
+    /// Which language TCP sessions read messages in (see `locale`)
+    pub locale: String,
+
+    /// Banned accounts can't log in
+    pub banned: bool,
+    /// Why they were banned, shown when they try to log in
+    pub ban_reason: Option<String>,
+
+    /// A few words about themselves, shown by `profile`
+    pub bio: Option<String>,
+    pub pronouns: Option<String>,
+
+    /// When and from where they last logged in (see `Connection::origin`), so
+    /// they can notice logins that weren't theirs
+    pub last_login: Option<SystemTime>,
+    pub last_login_from: Option<String>,
 }
\ No newline at end of file
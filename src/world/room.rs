@@ -1,4 +1,48 @@
+use serde::{Deserialize, Serialize};
+
 /// Unique ID numbers for each room
 pub type RoomId = u64;
 
-pub const INITIAL_LOC: RoomId = 0;
\ No newline at end of file
+pub const INITIAL_LOC: RoomId = 0;
+pub const INITIAL_ROOM_NAME: &str = "Lobby";
+pub const DEFAULT_LOBBY_DESCRIPTION: &str = "Everyone arrives here. Look around, or say hello!";
+
+/// Everything we know about a room besides who is in it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Room {
+    /// What people call the room, e.g., "Main Stage"
+    pub name: String,
+    /// What people see when they `look` around
+    pub description: Option<String>,
+    /// What the room is talking about. Any occupant may change it: rooms don't
+    /// have owners (yet).
+    pub topic: Option<String>,
+    /// Most people allowed in the room at once (`None` means no limit)
+    pub capacity: Option<usize>,
+}
+
+impl Room {
+    pub fn new(name: &str) -> Self {
+        Room {
+            name: name.to_string(),
+            description: None,
+            topic: None,
+            capacity: None,
+        }
+    }
+
+    /// Would one more person be too many?
+    pub fn is_full(&self, occupants: usize) -> bool {
+        matches!(self.capacity, Some(capacity) if occupants >= capacity)
+    }
+}
+
+/// The key rooms are looked up by. Case, spacing, and punctuation don't
+/// matter, so "Main Stage", "main stage", and "main-stage" are the same room.
+pub fn room_slug(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
@@ -1,16 +1,43 @@
 use std::cmp::{Eq, PartialEq};
-use std::collections::{HashMap, HashSet};
-use std::net::SocketAddr;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::{DateTime, Local};
 
 use rand::RngCore;
 
-use tokio::sync::mpsc;
+use serde::{Deserialize, Serialize};
+
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, watch, Mutex, MutexGuard};
+use tokio::task;
 
 use tracing::{error, info, trace, warn};
 
+use crate::world::flood::*;
+use crate::world::hostname::Hostnames;
 use crate::world::message::*;
+use crate::world::metrics::*;
 use crate::world::person::*;
 use crate::world::room::*;
+use crate::world::storage::*;
+use crate::Config;
+
+/// Messages a peer may have waiting (on top of the scrollback replayed when
+/// they arrive somewhere) before we decide they can't keep up and disconnect
+/// them
+pub const MESSAGE_QUEUE_CAPACITY: usize = 256;
+
+/// How many input lines we keep for each connection, for `recall`
+pub const RECALL_LENGTH: usize = 20;
 
 /// The global shared state
 pub struct State {
@@ -18,194 +45,914 @@ pub struct State {
     ///
     /// Password hashing configuration
     password_config: argon2::Config<'static>,
+    /// Sustained messages per second for each user
+    flood_rate: f64,
+    /// Burst size for each user's flood control
+    flood_burst: u32,
+    /// Format for timestamps on speech, if they're enabled
+    timestamp_format: Option<String>,
+    /// Longest text, in bytes, a command may carry
+    max_message_length: usize,
+    /// Largest HTTP request body, in bytes
+    max_body_size: usize,
+    /// How many messages each room's history keeps
+    scrollback: usize,
+    /// How many lines long listings show at a time
+    page_size: usize,
+    /// Most TCP connections at once (`None` means no limit)
+    max_connections: Option<usize>,
+    /// Canonical names of administrators (Unix socket sessions are admins, too)
+    admins: HashSet<String>,
+    /// Whether non-admins may search accounts
+    public_find: bool,
+    /// Whether unknown names may register themselves
+    open_registration: bool,
+    /// Wrong passwords allowed per login
+    max_password_attempts: usize,
+    /// Orientation for people who've just logged in
+    greeting: Option<String>,
+    /// Message of the day, shown to everyone as they log in
+    motd: Option<String>,
+    /// Words masked out of speech (lowercase)
+    filter_words: Vec<String>,
+    /// Whether only `/`-prefixed lines are commands
+    require_slash: bool,
+    /// How long line-oriented sessions may go without input
+    idle_timeout: Option<Duration>,
+    /// How long before an idle disconnect to warn (zero means don't)
+    idle_warning: Duration,
+    /// How long web sessions last without requests (see `HTTPState`)
+    session_timeout: Duration,
+    /// Where to save accounts and rooms (`None` means we don't)
+    db: Option<PathBuf>,
+    /// Whether anything worth saving has changed since the last save. Shared
+    /// with `accounts`, which change without the game lock.
+    dirty: Arc<AtomicBool>,
+
+    /// When the server started
+    started_at: Instant,
+    /// Monitoring counters, shared with the HTTP server
+    metrics: Arc<Metrics>,
 
     /// DATABASE
     ///
-    /// Next `PersonId` to generate
-    next_id: PersonId,
-    /// Each PersonId is associated with Person data
-    people: HashMap<PersonId, PersonRecord>,
-    /// Index of names to PersonId
-    names: HashMap<String, PersonId>,
+    /// Accounts (under their own lock; see `GameState`)
+    accounts: Accounts,
     /// Who is in a room
     rooms: HashMap<RoomId, HashSet<Person>>,
+    /// Room metadata (topic, etc.)
+    room_info: HashMap<RoomId, Room>,
+    /// Index of room slugs (see `room_slug`) to RoomId
+    room_names: HashMap<String, RoomId>,
+    /// Next `RoomId` to generate
+    next_room_id: RoomId,
+    /// Recent speech in each room, oldest first, replayed to arrivals
+    history: HashMap<RoomId, VecDeque<Message>>,
+    /// Who has joined each channel (e.g., `#hallway`), wherever they are.
+    /// Subscriptions outlast sessions, but not the server.
+    channels: HashMap<String, HashSet<PersonId>>,
 
     /// CONNECTION INFO
     ///
-    /// Each `PersonId` has exactly one connection
-    peers: HashMap<PersonId, Connection>, // TODO do we actually need to track this?
-    /// Each `PersonId` has a corresponding message queue
-    queues: HashMap<PersonId, MessageQueueTX>,
+    /// Connections and message queues (under their own lock; see `GameState`)
+    connections: Connections,
+    /// Hostnames of connecting addresses, if we look them up (see `Hostnames`)
+    hostnames: Option<Hostnames>,
+    /// Set to `true` to tell the servers to stop
+    shutdown: watch::Sender<bool>,
+    shutdown_rx: watch::Receiver<bool>,
+    /// When a countdown (see `GameState::shutdown_after`) will end, if one is running
+    shutdown_at: Option<Instant>,
+}
+
+/// How long before a scheduled shutdown we warn everyone (besides when it's
+/// first scheduled)
+pub const SHUTDOWN_WARNINGS: [Duration; 3] = [
+    Duration::from_secs(60),
+    Duration::from_secs(30),
+    Duration::from_secs(10),
+];
+
+/// Everything that outlives the server, as written to `--db`
+#[derive(Serialize, Deserialize)]
+pub struct Saved {
+    accounts: Snapshot,
+    /// Rooms besides the lobby, which comes from the configuration
+    rooms: Vec<(RoomId, Room)>,
+    next_room_id: RoomId,
+    motd: Option<String>,
+}
+
+impl Saved {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        serde_json::from_reader(io::BufReader::new(file))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write to `path` by way of a temporary file, so a crash mid-write leaves
+    /// the last save intact. This blocks!
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_vec(self)?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, json)?;
+        fs::rename(&tmp, path)
+    }
+
+    pub fn num_people(&self) -> usize {
+        self.accounts.people.len()
+    }
+}
+
+/// The warning for a shutdown `left` from now
+pub fn restart_notice(left: Duration) -> String {
+    let secs = left.as_secs();
+    format!(
+        "Server restarting in {} {}...",
+        secs,
+        if secs == 1 { "second" } else { "seconds" }
+    )
+}
+
+/// What we tell someone about their previous login
+pub fn last_login_notice(at: SystemTime, from: &str) -> String {
+    format!(
+        "Last login: {} from {}.",
+        DateTime::<Local>::from(at).format("%a %b %e %H:%M:%S %Y"),
+        from
+    )
 }
 
 impl State {
-    pub fn new() -> Self {
+    pub fn new(config: &Config) -> Self {
+        State::with_storage(config, Box::new(InMemoryStorage::new()))
+    }
+
+    pub fn with_storage(config: &Config, db: Box<dyn Storage>) -> Self {
         let mut rooms = HashMap::new();
         rooms.insert(INITIAL_LOC, HashSet::new());
+        let mut room_info = HashMap::new();
+        let mut lobby = Room::new(&config.lobby_name);
+        lobby.description = config.lobby_description.clone();
+        room_info.insert(INITIAL_LOC, lobby);
+        let mut room_names = HashMap::new();
+        room_names.insert(room_slug(&config.lobby_name), INITIAL_LOC);
+
+        let started_at = Instant::now();
+        let metrics = Arc::new(Metrics::new(started_at));
+        let (shutdown, shutdown_rx) = watch::channel(false);
+        let dirty = Arc::new(AtomicBool::new(false));
 
-        State {
-            next_id: 0,
-            people: HashMap::new(),
-            names: HashMap::new(),
+        let state = State {
+            started_at,
+            metrics: metrics.clone(),
+            accounts: Accounts::new(db, dirty.clone()),
             rooms,
-            peers: HashMap::new(),
-            queues: HashMap::new(),
-            password_config: argon2::Config::default(),
+            room_info,
+            room_names,
+            next_room_id: INITIAL_LOC + 1,
+            history: HashMap::new(),
+            channels: HashMap::new(),
+            connections: Connections::new(metrics),
+            hostnames: if config.resolve_hostnames {
+                Some(Hostnames::new())
+            } else {
+                None
+            },
+            shutdown,
+            shutdown_rx,
+            shutdown_at: None,
+            password_config: config.password_config(),
+            flood_rate: config.flood_rate,
+            flood_burst: config.flood_burst,
+            timestamp_format: config.timestamp_format.clone(),
+            max_message_length: config.max_message_length,
+            max_body_size: config.max_body_size,
+            scrollback: config.scrollback,
+            page_size: config.page_size,
+            max_connections: config.max_connections,
+            open_registration: config.open_registration,
+            max_password_attempts: config.max_password_attempts,
+            greeting: config.greeting.clone(),
+            motd: None,
+            admins: config.admins.iter().map(|name| canonical_name(name)).collect(),
+            public_find: config.public_find,
+            require_slash: config.require_slash,
+            idle_timeout: config.idle_timeout,
+            idle_warning: config.idle_warning,
+            session_timeout: config.session_timeout,
+            db: config.db.clone(),
+            dirty,
+            filter_words: config
+                .filter_words
+                .iter()
+                .map(|word| word.to_ascii_lowercase())
+                .collect(),
+        };
+        state.update_gauges();
+        state
+    }
+
+    /// Pick up where a saved server (see `GameState::save`) left off
+    pub fn restore(config: &Config, saved: Saved) -> Self {
+        let mut state = State::new(config);
+
+        for (loc, room) in saved.rooms {
+            let slug = room_slug(&room.name);
+            if state.rooms.contains_key(&loc) || state.room_names.contains_key(&slug) {
+                warn!(loc, name = room.name.as_str(), "saved room clashes, skipping");
+                continue;
+            }
+            state.rooms.insert(loc, HashSet::new());
+            state.room_info.insert(loc, room);
+            state.room_names.insert(slug, loc);
+            state.next_room_id = state.next_room_id.max(loc + 1);
         }
+        state.next_room_id = state.next_room_id.max(saved.next_room_id);
+        state.motd = saved.motd;
+
+        let mut accounts = saved.accounts;
+        for person in accounts.people.iter_mut() {
+            // a room we couldn't restore can't have them back
+            if !state.rooms.contains_key(&person.loc) {
+                person.loc = INITIAL_LOC;
+            }
+        }
+        let db = Box::new(InMemoryStorage::from_snapshot(accounts));
+        state.accounts = Accounts::new(db, state.dirty.clone());
+
+        state.update_gauges();
+        state
+    }
+
+    /// Everything worth keeping across restarts (see `Saved`)
+    pub fn saved(&self) -> Saved {
+        let mut rooms: Vec<(RoomId, Room)> = self
+            .room_info
+            .iter()
+            .filter(|(loc, _)| **loc != INITIAL_LOC)
+            .map(|(loc, room)| (*loc, room.clone()))
+            .collect();
+        rooms.sort_by_key(|(loc, _)| *loc);
+
+        Saved {
+            accounts: self.accounts.snapshot(),
+            rooms,
+            next_room_id: self.next_room_id,
+            motd: self.motd.clone(),
+        }
+    }
+
+    /// Has anything worth saving changed since the last save?
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    fn mark_dirty(&self) {
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    /// Bring the monitoring gauges in line with our tables
+    fn update_gauges(&self) {
+        let m = &self.metrics;
+        m.connected_users.store(self.connections.read().queues.len(), Ordering::Relaxed);
+        m.registered_accounts.store(self.accounts.num_people(), Ordering::Relaxed);
+        m.rooms.store(self.rooms.len(), Ordering::Relaxed);
+    }
+
+    /// Fresh flood control for a new session
+    pub fn flood_control(&self) -> FloodControl {
+        FloodControl::new(self.flood_rate, self.flood_burst)
+    }
+
+    /// Server-wide timestamp format for new sessions
+    pub fn timestamp_format(&self) -> Option<String> {
+        self.timestamp_format.clone()
+    }
+
+    /// May `p` run admin commands? Anyone on the (local) Unix socket may.
+    pub fn is_admin(&self, p: &Person) -> bool {
+        matches!(p.conn, Connection::Unix { .. }) || self.admins.contains(&canonical_name(&p.name))
+    }
+
+    /// May `p` search accounts? Listing accounts lets people enumerate
+    /// users, so it's up to the operator.
+    pub fn may_find(&self, p: &Person) -> bool {
+        self.public_find || self.is_admin(p)
+    }
+
+    /// May anonymous (i.e., HTTP API) clients search accounts?
+    pub fn public_find(&self) -> bool {
+        self.public_find
+    }
+
+    /// Names of accounts (online or not) containing `query`, ignoring case,
+    /// sorted
+    pub fn find(&self, query: &str) -> Vec<String> {
+        let mut names = self.accounts.names_containing(query);
+        names.sort_by_cached_key(|name| canonical_name(name));
+        names
+    }
+
+    /// Every connection, and where each person is
+    pub fn sessions(&self) -> Vec<SessionEntry> {
+        // copy the table out, so we're not holding its lock during lookups
+        let peers: Vec<(PersonId, Connection, Instant)> = self
+            .connections
+            .read()
+            .peers
+            .iter()
+            .map(|(&id, peer)| (id, peer.conn.clone(), peer.last_active))
+            .collect();
+
+        let mut sessions: Vec<SessionEntry> = peers
+            .into_iter()
+            .map(|(id, conn, last_active)| SessionEntry {
+                id,
+                name: self
+                    .accounts
+                    .person_by_id(id)
+                    .map_or_else(|| "(unknown)".to_string(), |p| p.name),
+                host: match &conn {
+                    Connection::TCP { addr } => {
+                        self.hostnames.as_ref().and_then(|h| h.cached(addr.ip()))
+                    }
+                    Connection::Unix { .. } | Connection::HTTP { .. } => None,
+                },
+                conn,
+                loc: self
+                    .rooms
+                    .iter()
+                    .find(|(_, people)| people.iter().any(|p| p.id == id))
+                    .map(|(&loc, _)| loc),
+                idle: last_active.elapsed(),
+            })
+            .collect();
+        sessions.sort_by_key(|session| session.id);
+        sessions
+    }
+
+    /// How `id` is connected, if they are (see `Connection::transport`)
+    pub fn transport(&self, id: PersonId) -> Option<&'static str> {
+        self.connections
+            .read()
+            .peers
+            .get(&id)
+            .map(|peer| peer.conn.transport())
+    }
+
+    pub fn greeting(&self) -> Option<String> {
+        self.greeting.clone()
+    }
+
+    pub fn motd(&self) -> Option<String> {
+        self.motd.clone()
+    }
+
+    /// Set (or, with `None`, clear) the message of the day
+    pub fn set_motd(&mut self, motd: Option<String>) {
+        info!(?motd, "motd");
+        self.motd = motd;
+        self.mark_dirty();
+    }
+
+    pub fn open_registration(&self) -> bool {
+        self.open_registration
+    }
+
+    pub fn max_password_attempts(&self) -> usize {
+        self.max_password_attempts
+    }
+
+    pub fn page_size(&self) -> usize {
+        self.page_size
+    }
+
+    pub fn max_connections(&self) -> Option<usize> {
+        self.max_connections
+    }
+
+    pub fn max_message_length(&self) -> usize {
+        self.max_message_length
+    }
+
+    pub fn max_body_size(&self) -> usize {
+        self.max_body_size
+    }
+
+    pub fn require_slash(&self) -> bool {
+        self.require_slash
+    }
+
+    /// How long a line-oriented session may go without input, and how long
+    /// before then to warn them (see `IdleClock`)
+    pub fn idle_limits(&self) -> Option<(Duration, Duration)> {
+        self.idle_timeout.map(|timeout| (timeout, self.idle_warning))
+    }
+
+    pub fn session_timeout(&self) -> Duration {
+        self.session_timeout
+    }
+
+    /// Longest line we'll read from a peer: a maximal message, plus room for
+    /// the command and a name (e.g., `tell @someone ...`)
+    pub fn max_line_length(&self) -> usize {
+        self.max_message_length + MAX_NAME_LENGTH + 16
+    }
+
+    /// Mask any filtered words in `text` with asterisks, ignoring case
+    pub fn filter(&self, text: &str) -> String {
+        if self.filter_words.is_empty() {
+            return text.to_string();
+        }
+
+        // ASCII lowercasing keeps byte offsets the same as in `text`
+        let lower = text.to_ascii_lowercase();
+        let mut masked = vec![false; text.len()];
+        for word in self.filter_words.iter().filter(|word| !word.is_empty()) {
+            for (start, _) in lower.match_indices(word.as_str()) {
+                for m in &mut masked[start..start + word.len()] {
+                    *m = true;
+                }
+            }
+        }
+
+        text.char_indices()
+            .map(|(i, c)| if masked[i] { '*' } else { c })
+            .collect()
     }
 
-    pub fn shutdown(&mut self) {
+    pub fn uptime(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    /// A snapshot of how busy the server is
+    pub fn stats(&self) -> Stats {
+        Stats {
+            uptime: self.uptime(),
+            connected: self.connections.read().queues.len(),
+            accounts: self.accounts.num_people(),
+            rooms: self.rooms.len(),
+            occupied: self
+                .rooms
+                .keys()
+                .filter(|&&loc| self.room_occupancy(loc) > 0)
+                .count(),
+        }
+    }
+
+    /// Say goodbye to everyone, disconnect them, and tell the servers to stop
+    pub async fn shutdown(&mut self) {
         warn!("shutdown initiated");
-        // TODO coordinate with top-level tokio runtime via tokio::sync::oneshot
-        std::process::exit(0);
+
+        self.broadcast(Message::Notice {
+            text: "The server is shutting down.".to_string(),
+        })
+        .await;
+
+        {
+            let mut table = self.connections.write();
+            for (_id, mut q) in table.queues.drain() {
+                let _ = q.try_send(Message::Logout);
+            }
+            table.peers.clear();
+        }
+        for people in self.rooms.values_mut() {
+            people.clear();
+        }
+        self.update_gauges();
+
+        if let Err(e) = self.shutdown.broadcast(true) {
+            error!(?e, "couldn't signal shutdown");
+        }
     }
 
-    pub fn fresh_id(&mut self) -> PersonId {
-        let id = self.next_id;
-        self.next_id += 1;
-        id
+    /// How long until a scheduled shutdown, if there is one
+    pub fn shutdown_in(&self) -> Option<Duration> {
+        self.shutdown_at
+            .map(|at| at.saturating_duration_since(Instant::now()))
     }
 
-    pub fn new_person(&mut self, name: &str, password: &str) -> PersonRecord {
-        let id = self.fresh_id();
-        info!(id = id, name = name, "registered");
-
-        // TODO this is a race :(
-        // if someone registers a name while someone else is mid-registration, we'll fail this check :(
-        // best solution: return a result here and handle the race up above
-        assert!(!self.names.contains_key(name));
-        let name = name.to_string();
-        self.names.insert(name.clone(), id);
-
-        let mut salt: [u8; PASSWD_SALT_LENGTH / 4] = [0; PASSWD_SALT_LENGTH / 4];
-        rand::thread_rng().fill_bytes(&mut salt);
-        let salt = base64::encode(salt);
-
-        // TODO handle error case
-        let password =
-            argon2::hash_encoded(password.as_bytes(), salt.as_bytes(), &self.password_config)
-                .unwrap();
-
-        let person = PersonRecord {
-            id,
-            loc: INITIAL_LOC,
-            name,
-            salt,
-            password,
-        };
+    /// A signal that fires when the server shuts down
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        ShutdownSignal(self.shutdown_rx.clone())
+    }
 
-        self.people.insert(id, person.clone());
+    /// Register an account outright, e.g., to set up a test
+    ///
+    /// Panics if `name` is taken; logins go through `GameState::reserve_name`.
+    pub fn new_person(&mut self, name: &str, password: &str) -> PersonRecord {
+        let reservation = self
+            .accounts
+            .reserve(name)
+            .unwrap_or_else(|| panic!("{} is already taken", name));
+        let (salt, password) = hash_password(&self.password_config, password);
+        let person = self.accounts.create(reservation, salt, password);
+        info!(id = person.id, name, "registered");
+        self.update_gauges();
 
         person
     }
 
+    /// Change a person's password
+    pub fn set_password(&mut self, id: PersonId, password: &str) {
+        let (salt, password) = hash_password(&self.password_config, password);
+        self.accounts.set_password(id, salt, password);
+    }
+
+    /// Was `encoded` hashed with different parameters than we use now?
+    pub fn needs_rehash(&self, encoded: &str) -> bool {
+        needs_rehash(&self.password_config, encoded)
+    }
+
     pub fn room(&self, loc: RoomId) -> &HashSet<Person> {
         self.rooms.get(&loc).expect("room should exist")
     }
 
+    /// Every room, in the order they were made
+    pub fn room_ids(&self) -> Vec<RoomId> {
+        let mut locs: Vec<RoomId> = self.rooms.keys().copied().collect();
+        locs.sort_unstable();
+        locs
+    }
+
+    /// How many people are in `loc`, without handing out the room itself
+    pub fn room_occupancy(&self, loc: RoomId) -> usize {
+        self.rooms.get(&loc).map_or(0, HashSet::len)
+    }
+
+    /// Would one more person in `loc` be too many?
+    pub fn is_room_full(&self, loc: RoomId) -> bool {
+        self.room_info(loc).is_full(self.room_occupancy(loc))
+    }
+
     pub fn room_mut(&mut self, loc: RoomId) -> &mut HashSet<Person> {
         self.rooms.get_mut(&loc).expect("room should exist")
     }
 
-    pub fn person(&self, id: &PersonId) -> &PersonRecord {
-        assert!(self.people.contains_key(&id));
-        self.people.get(&id).unwrap()
+    pub fn room_info(&self, loc: RoomId) -> &Room {
+        self.room_info.get(&loc).expect("room should exist")
+    }
+
+    pub fn room_info_mut(&mut self, loc: RoomId) -> &mut Room {
+        self.mark_dirty();
+        self.room_info.get_mut(&loc).expect("room should exist")
+    }
+
+    /// Is there a room `loc`? Room IDs from outside (e.g., URLs) may not be.
+    pub fn has_room(&self, loc: RoomId) -> bool {
+        self.rooms.contains_key(&loc)
+    }
+
+    /// Make a new, empty room, unless the name is taken (or has no letters
+    /// or numbers to look it up by)
+    pub fn new_room(&mut self, name: &str) -> Result<RoomId, String> {
+        let slug = room_slug(name);
+        if slug.is_empty() {
+            return Err("Room names need a letter or number.".to_string());
+        }
+        if let Some(loc) = self.room_names.get(&slug) {
+            return Err(format!(
+                "There is already a room named {}.",
+                self.room_info(*loc).name
+            ));
+        }
+
+        let loc = self.next_room_id;
+        self.next_room_id += 1;
+
+        self.rooms.insert(loc, HashSet::new());
+        self.room_info.insert(loc, Room::new(name));
+        self.room_names.insert(slug, loc);
+        self.mark_dirty();
+        self.update_gauges();
+
+        info!(loc, name, "new room");
+        Ok(loc)
+    }
+
+    /// Look up a room by name, ignoring case, spacing, and punctuation
+    pub fn room_by_name(&self, name: &str) -> Option<RoomId> {
+        self.room_names.get(&room_slug(name)).copied()
+    }
+
+    /// Remember something said in `loc`, for replay to later arrivals
+    pub fn record(&mut self, loc: RoomId, message: Message) {
+        if self.scrollback == 0 {
+            return;
+        }
+
+        let history = self.history.entry(loc).or_default();
+        if history.len() == self.scrollback {
+            history.pop_front();
+        }
+        history.push_back(message);
+    }
+
+    /// Everyone who is currently in a room
+    pub fn online(&self) -> impl Iterator<Item = &Person> {
+        self.rooms.values().flatten()
+    }
+
+    /// The live session for a person, if they're online
+    pub fn online_person(&self, id: PersonId) -> Option<&Person> {
+        self.online().find(|p| p.id == id)
+    }
+
+    /// Mark a person as away (or back), keeping their room's copy up to date
+    pub fn set_away(&mut self, p: &mut Person, away: Option<String>) {
+        p.away = away;
+
+        if let Some(people) = self.rooms.get_mut(&p.loc) {
+            people.replace(p.clone());
+        }
+    }
+
+    pub fn person(&self, id: &PersonId) -> PersonRecord {
+        self.accounts.person_by_id(*id).expect("person should exist")
+    }
+
+    pub fn person_by_id(&self, id: PersonId) -> Option<PersonRecord> {
+        self.accounts.person_by_id(id)
     }
 
     pub fn person_by_name(&self, name: &str) -> Option<PersonRecord> {
-        let id = self.names.get(name)?;
-        self.people.get(id).map(|p| p.clone()).or_else(|| {
-            error!(name, id, "in names but not people");
-            None
-        })
+        self.accounts.person_by_name(name)
+    }
+
+    /// Record a person's color preference for future sessions
+    pub fn set_color(&mut self, id: PersonId, color: bool) {
+        if !self.accounts.modify(id, |person| person.color = color) {
+            error!(id, "no such person to set color");
+        }
+    }
+
+    /// Record a person's language for future sessions
+    pub fn set_locale(&mut self, id: PersonId, locale: &str) {
+        if !self.accounts.modify(id, |person| person.locale = locale.to_string()) {
+            error!(id, "no such person to set locale");
+        }
+    }
+
+    /// Set (or, with `None`, clear) part of someone's profile
+    pub fn set_profile(&mut self, id: PersonId, field: ProfileField, value: Option<String>) {
+        let found = self.accounts.modify(id, |person| match field {
+            ProfileField::Bio => person.bio = value,
+            ProfileField::Pronouns => person.pronouns = value,
+        });
+        if !found {
+            error!(id, "no such person to set profile");
+        }
+    }
+
+    /// Ban (or unban) an account; banning doesn't disconnect them
+    pub fn set_banned(&mut self, id: PersonId, banned: bool, reason: Option<String>) {
+        let found = self.accounts.modify(id, |person| {
+            person.banned = banned;
+            person.ban_reason = if banned { reason } else { None };
+        });
+        if !found {
+            error!(id, "no such person to ban");
+        }
+    }
+
+    /// Send a message to a single person
+    pub fn send_to(&self, id: PersonId, message: Message) {
+        self.connections.send_to(id, message)
+    }
+
+    /// A fresh message queue for a connection, with room for the scrollback
+    pub fn message_queue(&self) -> (MessageQueueTX, MessageQueueRX) {
+        mpsc::channel(MESSAGE_QUEUE_CAPACITY + self.scrollback)
+    }
+
+    /// Whether `id` has a live connection (they may have been disconnected
+    /// for being slow, but not yet left their room)
+    pub fn is_connected(&self, id: PersonId) -> bool {
+        self.connections.read().peers.contains_key(&id)
     }
 
     pub fn register_connection(&mut self, id: PersonId, conn: Connection, tx: MessageQueueTX) {
-        self.peers.insert(id, conn);
-        self.queues.insert(id, tx);
+        {
+            let mut table = self.connections.write();
+            table.peers.insert(
+                id,
+                Peer {
+                    conn,
+                    last_active: Instant::now(),
+                    history: VecDeque::new(),
+                },
+            );
+            table.queues.insert(id, tx);
+        }
+        self.update_gauges();
     }
 
     pub fn unregister_connection(&mut self, id: PersonId) {
-        if let None = self.peers.remove(&id) {
-            warn!(id, "no connection to unregister");
+        {
+            let mut table = self.connections.write();
+            if let None = table.peers.remove(&id) {
+                warn!(id, "no connection to unregister");
+            }
+            // the queue is already gone if they couldn't keep up
+            table.queues.remove(&id);
+        }
+        self.update_gauges();
+    }
+
+    /// Log `p` out and delete their account for good
+    pub async fn delete_account(&mut self, p: &Person) {
+        self.send_to(
+            p.id,
+            Message::Notice {
+                text: "Your account has been deleted.".to_string(),
+            },
+        );
+        self.logout(p).await;
+
+        if self.accounts.remove(p.id).is_none() {
+            error!(p.id, "no such person to delete");
         }
-        if let None = self.queues.remove(&id) {
-            warn!(id, "no queue to unregister");
+        for members in self.channels.values_mut() {
+            members.remove(&p.id);
         }
+        self.channels.retain(|_, members| !members.is_empty());
+        info!(p.id, "account deleted");
+        self.update_gauges();
     }
 
     pub async fn logout(&mut self, p: &Person) {
-        self.depart(p).await;
+        self.depart(p, DepartReason::LoggedOff).await;
+
+        let (conn, q) = {
+            let mut table = self.connections.write();
+            (table.peers.remove(&p.id), table.queues.remove(&p.id))
+        };
 
-        let conn = match self.peers.remove(&p.id) {
+        let conn = match conn {
             None => {
                 warn!(p.id, "no connection to terminate on logout");
                 return ();
             },
-            Some(conn) => conn,
+            Some(peer) => peer.conn,
         };
 
-        let q = match self.queues.remove(&p.id) {
+        let mut q = match q {
             None => {
                 warn!(p.id, "no connection to terminate on logout");
                 return ();
             },
             Some(q) => q,
         };
+        self.update_gauges();
 
-        if let Connection::TCP { .. } = conn {
-            let _ = q.send(Message::Logout);
+        // HTTP sessions end when their queue closes, i.e., when `q` drops
+        if conn.is_line_oriented() {
+            let _ = q.try_send(Message::Logout);
         }
+    }
+
+    /// Tell someone why, then log them out, whoever is asking. Returns `false`
+    /// if they weren't online.
+    pub async fn disconnect(&mut self, id: PersonId, why: &str) -> bool {
+        // the room's copy knows where they are
+        let target = match self.online_person(id) {
+            None => return false,
+            Some(target) => target.clone(),
+        };
 
-        // TODO force end of HTTP session?
+        self.send_to(
+            id,
+            Message::Notice {
+                text: why.to_string(),
+            },
+        );
+        self.logout(&target).await;
+        true
     }
 
-    /// Send a message to _all_ peers.
-    pub async fn broadcast(&mut self, message: Message) {
-        trace!(message = ?message, "broadcast");
+    /// Everyone connected, to send to once the lock is released
+    pub fn all_recipients(&self) -> Recipients {
+        Recipients {
+            queues: self
+                .connections
+                .read()
+                .queues
+                .iter()
+                .map(|(&id, q)| (id, q.clone()))
+                .collect(),
+            connections: self.connections.clone(),
+        }
+    }
+
+    /// Subscribe `id` to `channel`, unless they already are
+    pub fn join_channel(&mut self, id: PersonId, channel: &str) -> bool {
+        self.channels.entry(channel.to_string()).or_default().insert(id)
+    }
 
-        for p in self.queues.iter_mut() {
-            let _ = p.1.send(message.clone());
+    /// Unsubscribe `id` from `channel`, if they were subscribed
+    pub fn leave_channel(&mut self, id: PersonId, channel: &str) -> bool {
+        let left = match self.channels.get_mut(channel) {
+            Some(members) => members.remove(&id),
+            None => false,
+        };
+        if matches!(self.channels.get(channel), Some(members) if members.is_empty()) {
+            self.channels.remove(channel);
         }
+        left
     }
 
-    /// Send a message to everyone in a given location
-    pub async fn roomcast(&mut self, loc: RoomId, message: Message) {
-        trace!(loc, message = ?message, "roomcast");
+    pub fn in_channel(&self, id: PersonId, channel: &str) -> bool {
+        matches!(self.channels.get(channel), Some(members) if members.contains(&id))
+    }
+
+    /// Everyone connected who has joined `channel`, to send to once the lock
+    /// is released
+    pub fn channel_recipients(&self, channel: &str) -> Recipients {
+        let members = match self.channels.get(channel) {
+            None => return Recipients::none(self.connections.clone()),
+            Some(members) => members,
+        };
+
+        let table = self.connections.read();
+        Recipients {
+            queues: members
+                .iter()
+                .filter_map(|id| table.queues.get(id).map(|q| (*id, q.clone())))
+                .collect(),
+            connections: self.connections.clone(),
+        }
+    }
 
+    /// Everyone in a given location, to send to once the lock is released
+    pub fn room_recipients(&self, loc: RoomId) -> Recipients {
         // find out who's there
         let people = match self.rooms.get(&loc) {
             None => {
-                error!(loc, ?message, "room not found in rooms table");
-                return ();
-            },
+                error!(loc, "room not found in rooms table");
+                return Recipients::none(self.connections.clone());
+            }
             Some(people) => people,
         };
 
-        // let 'em hear about it
+        let table = self.connections.read();
+        let mut queues = Vec::with_capacity(people.len());
         for p in people {
-            let q = self.queues.get(&p.id);
-
-            match q {
-                None => warn!(
-                    loc,
-                    ?p,
-                    "listed in room, but no message queue... disconnected?"
-                ),
-                Some(q) => match q.send(message.clone()) {
-                    Err(e) => warn!(loc, ?p, ?e, "bad message queue"),
-                    Ok(()) => (),
-                },
+            match table.queues.get(&p.id) {
+                // slow peers lose their queue, then leave once their session drains
+                None if table.peers.contains_key(&p.id) => trace!(loc, ?p, "no queue yet"),
+                None => warn!(loc, ?p, "listed in room, but not connected (a ghost)"),
+                Some(q) => queues.push((p.id, q.clone())),
             }
         }
+
+        Recipients {
+            queues,
+            connections: self.connections.clone(),
+        }
     }
 
-    pub async fn depart(&mut self, p: &Person) {
-        // TODO extra parameter indicating where we're going:
-        //  - other room (visible)
-        //  - logoff (visible)
-        //  - private room (invisible)
-        info!(?p, "depart");
+    /// Send a message to _all_ peers.
+    ///
+    /// Prefer `all_recipients` when you don't otherwise need the lock.
+    pub async fn broadcast(&mut self, message: Message) {
+        trace!(message = ?message, "broadcast");
+        self.all_recipients().send(message);
+    }
+
+    /// Take anyone out of `loc` who isn't connected at all; they're left over
+    /// from a session that ended without departing, and would otherwise show
+    /// up in `look` forever
+    fn reap_ghosts(&mut self, loc: RoomId) {
+        let table = self.connections.read();
+        if let Some(people) = self.rooms.get_mut(&loc) {
+            people.retain(|p| {
+                let connected = table.peers.contains_key(&p.id);
+                if !connected {
+                    warn!(loc, ?p, "removing ghost from room");
+                }
+                connected
+            });
+        }
+    }
+
+    /// Send a message to everyone in a given location
+    ///
+    /// Prefer `room_recipients` when you don't otherwise need the lock.
+    pub async fn roomcast(&mut self, loc: RoomId, message: Message) {
+        trace!(loc, message = ?message, "roomcast");
+        self.reap_ghosts(loc);
+        self.room_recipients(loc).send(message);
+    }
+
+    /// Take `p` out of their room, telling the people left behind why
+    pub async fn depart(&mut self, p: &Person, reason: DepartReason) {
+        info!(?p, ?reason, "depart");
 
         let people = match self.rooms.get_mut(&p.loc) {
             None => {
@@ -221,44 +968,556 @@ impl State {
             id: p.id,
             name: p.name.clone(),
             loc: p.loc,
+            reason,
         };
 
         self.roomcast(p.loc, msg).await;
     }
 
-    pub async fn arrive(&mut self, p: &mut Person, loc: RoomId) {
+    /// Describe `p`'s room to them; every arrival should end with one
+    pub fn look(&self, p: &Person) {
+        let room = self.room_info(p.loc);
+        self.send_to(
+            p.id,
+            Message::Look {
+                name: room.name.clone(),
+                description: room.description.clone(),
+                topic: room.topic.clone(),
+                people: WhoEntry::listing(self.room(p.loc).iter()),
+            },
+        );
+    }
+
+    /// Bring someone who's just connected back to the room they were last in,
+    /// or to the lobby if that room has filled up in the meantime
+    pub async fn rejoin(&mut self, p: &mut Person) -> Result<(), RoomFullError> {
+        let loc = p.loc;
+        match self.arrive(p, loc).await {
+            Err(_) if loc != INITIAL_LOC => {
+                // they were never in the old room, so there's nothing to depart
+                p.loc = INITIAL_LOC;
+                self.arrive(p, INITIAL_LOC).await
+            }
+            arrived => arrived,
+        }
+    }
+
+    /// Move `p` into `loc` (departing their old room) or, on login, put them
+    /// there. If `loc` is full, they stay where they were.
+    pub async fn arrive(&mut self, p: &mut Person, loc: RoomId) -> Result<(), RoomFullError> {
         info!(?p, "arrive");
 
-        if p.loc != loc {
-            let old_room = self.room_mut(p.loc);
-            old_room.remove(p);
+        // someone already in the room (e.g., logging in again) takes no more space
+        if !self.room(loc).contains(p) && self.is_room_full(loc) {
+            info!(?p, loc, "room full");
+            return Err(RoomFullError { loc });
+        }
 
+        let from = if p.loc != loc {
+            let from = (p.loc, self.room_info(p.loc).name.clone());
+            let reason = DepartReason::Moved {
+                to: loc,
+                to_name: self.room_info(loc).name.clone(),
+            };
+            self.depart(p, reason).await;
             p.loc = loc;
+            Some(from)
+        } else {
+            None
+        };
+
+        // the record is authoritative for offline people (and where they'll
+        // come back to), so keep it in step even when `p.loc` already matches
+        if !self.accounts.modify(p.id, |record| record.loc = loc) {
+            error!(id = p.id, "no such person to move");
         }
 
+        p.arrived_at = Instant::now();
         let new_room = self.room_mut(loc);
         new_room.insert(p.clone());
 
+        // catch them up; each message renders from their point of view
+        if let Some(history) = self.history.get(&loc) {
+            for message in history {
+                self.send_to(p.id, message.clone());
+            }
+        }
+
         let msg = Message::Arrive {
             id: p.id,
             name: p.name.clone(),
-            loc: loc,
+            loc,
+            loc_name: self.room_info(loc).name.clone(),
+            from,
         };
         self.roomcast(loc, msg).await;
+
+        Ok(())
+    }
+}
+
+/// Someone tried to enter a room at capacity
+#[derive(Debug)]
+pub struct RoomFullError {
+    pub loc: RoomId,
+}
+
+impl Error for RoomFullError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        None
+    }
+}
+
+impl fmt::Display for RoomFullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "That room is full.")
+    }
+}
+
+/// A handle on the game, cheap to clone and share between sessions
+///
+/// Rooms and the rest of the game live behind `lock`. The account database
+/// and the connection table each have their own lock, so that logins (which
+/// hash passwords) and direct messages don't wait on the whole game. When
+/// holding more than one, take `lock` first.
+#[derive(Clone)]
+pub struct GameState {
+    state: Arc<Mutex<State>>,
+    accounts: Accounts,
+    connections: Connections,
+    password_config: argon2::Config<'static>,
+    metrics: Arc<Metrics>,
+    hostnames: Option<Hostnames>,
+    /// A hash of nothing in particular, under the current policy, to check
+    /// passwords for unknown names against (see `check_password`)
+    dummy_hash: Arc<String>,
+    /// Where to save (see `save`), and a lock so only one save writes at once
+    db: Option<PathBuf>,
+    saving: Arc<std::sync::Mutex<()>>,
+}
+
+impl GameState {
+    pub fn new(state: State) -> Self {
+        GameState {
+            accounts: state.accounts.clone(),
+            connections: state.connections.clone(),
+            hostnames: state.hostnames.clone(),
+            password_config: state.password_config.clone(),
+            metrics: state.metrics.clone(),
+            dummy_hash: Arc::new(hash_password(&state.password_config, "").1),
+            db: state.db.clone(),
+            saving: Arc::new(std::sync::Mutex::new(())),
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Lock the game state
+    pub async fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().await
+    }
+
+    /// Shut down after `delay`, warning everyone along the way; returns
+    /// `false` (and does nothing) if a countdown is already running
+    pub async fn shutdown_after(&self, delay: Duration) -> bool {
+        let at = Instant::now() + delay;
+        {
+            let mut state = self.lock().await;
+            if state.shutdown_at.is_some() {
+                return false;
+            }
+            warn!(?delay, "shutdown scheduled");
+            state.shutdown_at = Some(at);
+            let text = restart_notice(delay);
+            state.broadcast(Message::Notice { text }).await;
+        }
+
+        let state = self.clone();
+        tokio::spawn(async move {
+            for warning in SHUTDOWN_WARNINGS.iter().filter(|warning| **warning < delay) {
+                tokio::time::delay_until((at - *warning).into()).await;
+                let text = restart_notice(*warning);
+                state.lock().await.broadcast(Message::Notice { text }).await;
+            }
+            tokio::time::delay_until(at.into()).await;
+            state.lock().await.shutdown().await;
+        });
+        true
+    }
+
+    /// Are we saving to disk (see `--db`)?
+    pub fn persists(&self) -> bool {
+        self.db.is_some()
+    }
+
+    /// Save accounts and rooms to `--db`, returning how many accounts there
+    /// were. The game is only locked long enough to copy them: the write
+    /// happens off the async workers.
+    pub async fn save(&self) -> io::Result<usize> {
+        let path = match &self.db {
+            None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "saving is disabled")),
+            Some(path) => path.clone(),
+        };
+        let saved = {
+            let state = self.lock().await;
+            // anything that changes from here on will be saved next time
+            state.dirty.store(false, Ordering::Relaxed);
+            state.saved()
+        };
+        let people = saved.num_people();
+
+        let saving = self.saving.clone();
+        let written = task::spawn_blocking(move || {
+            let _saving = saving.lock().unwrap_or_else(PoisonError::into_inner);
+            saved.write(&path)
+        })
+        .await
+        .expect("saving panicked");
+        if written.is_err() {
+            self.lock().await.mark_dirty();
+        }
+        written?;
+
+        Ok(people)
+    }
+
+    /// Save (see `save`) if anything has changed since the last save,
+    /// returning how many accounts were saved, if any
+    pub async fn autosave(&self) -> io::Result<Option<usize>> {
+        if !self.persists() || !self.lock().await.is_dirty() {
+            return Ok(None);
+        }
+        self.save().await.map(Some)
+    }
+
+    pub fn person_by_name(&self, name: &str) -> Option<PersonRecord> {
+        self.accounts.person_by_name(name)
+    }
+
+    /// The account named `name`, if it exists and `password` is theirs.
+    /// Unknown names take as long to fail as wrong passwords, so timing
+    /// doesn't tell who has an account.
+    pub async fn check_password(&self, name: &str, password: &str) -> Option<PersonRecord> {
+        let person = self.accounts.person_by_name(name);
+        let encoded = match &person {
+            Some(person) => person.password.clone(),
+            None => self.dummy_hash.to_string(),
+        };
+
+        // as slow as hashing, so off the async workers for the same reason
+        let password = password.to_string();
+        let verified = task::spawn_blocking(move || {
+            argon2::verify_encoded(&encoded, password.as_bytes()).unwrap_or(false)
+        })
+        .await
+        .expect("password verification panicked");
+        person.filter(|_| verified)
+    }
+
+    /// Was `encoded` hashed with different parameters than we use now?
+    pub fn needs_rehash(&self, encoded: &str) -> bool {
+        needs_rehash(&self.password_config, encoded)
+    }
+
+    /// Claim a free name for registration (see `Reservation`), or `None` if
+    /// it's taken or someone else is registering it
+    pub fn reserve_name(&self, name: &str) -> Option<Reservation> {
+        self.accounts.reserve(name)
+    }
+
+    /// Register a new account without taking the game lock
+    pub async fn register(&self, reservation: Reservation, password: &str) -> PersonRecord {
+        let (salt, password) = self.hash_password(password).await;
+        let person = self.accounts.create(reservation, salt, password);
+        info!(id = person.id, name = person.name.as_str(), "registered");
+        self.metrics
+            .registered_accounts
+            .store(self.accounts.num_people(), Ordering::Relaxed);
+
+        person
+    }
+
+    /// Change a person's password without taking the game lock
+    pub async fn set_password(&self, id: PersonId, password: &str) {
+        let (salt, password) = self.hash_password(password).await;
+        self.accounts.set_password(id, salt, password);
+    }
+
+    /// Hashing is slow on purpose, so keep it off the async workers
+    async fn hash_password(&self, password: &str) -> (String, String) {
+        let config = self.password_config.clone();
+        let password = password.to_string();
+        task::spawn_blocking(move || hash_password(&config, &password))
+            .await
+            .expect("password hashing panicked")
+    }
+
+    /// Send a message to a single person without taking the game lock
+    pub fn send_to(&self, id: PersonId, message: Message) {
+        self.connections.send_to(id, message)
+    }
+
+    /// Are we looking up hostnames (see `--resolve-hostnames`)?
+    pub fn resolves_hostnames(&self) -> bool {
+        self.hostnames.is_some()
+    }
+
+    /// The hostname for `ip`, if we look them up and it has one. This may
+    /// wait on DNS, but never holds the game lock.
+    pub async fn hostname(&self, ip: IpAddr) -> Option<String> {
+        self.hostnames.as_ref()?.resolve(ip).await
+    }
+
+    /// Note that `id` just logged in over `conn`, returning when and from where
+    /// they last did (if ever)
+    pub fn record_login(&self, id: PersonId, conn: &Connection) -> Option<(SystemTime, String)> {
+        let mut previous = None;
+        self.accounts.modify(id, |person| {
+            if let (Some(at), Some(from)) = (person.last_login, person.last_login_from.take()) {
+                previous = Some((at, from));
+            }
+            person.last_login = Some(SystemTime::now());
+            person.last_login_from = Some(conn.origin());
+        });
+        previous
+    }
+
+    /// Note that we've just heard from `id`, without taking the game lock
+    pub fn mark_active(&self, id: PersonId) {
+        if let Some(peer) = self.connections.write().peers.get_mut(&id) {
+            peer.last_active = Instant::now();
+        }
+    }
+
+    /// Keep a line `id` typed, for `recall`
+    pub fn remember(&self, id: PersonId, line: &str) {
+        if let Some(peer) = self.connections.write().peers.get_mut(&id) {
+            if peer.history.len() == RECALL_LENGTH {
+                peer.history.pop_front();
+            }
+            peer.history.push_back(line.to_string());
+        }
+    }
+
+    /// The last `n` lines `id` typed on this connection, oldest first
+    pub fn recall(&self, id: PersonId, n: usize) -> Vec<String> {
+        match self.connections.read().peers.get(&id) {
+            None => Vec::new(),
+            Some(peer) => {
+                let skip = peer.history.len().saturating_sub(n);
+                peer.history.iter().skip(skip).cloned().collect()
+            }
+        }
+    }
+}
+
+/// Salt and hash a password, returning the salt and the encoded hash
+fn hash_password(config: &argon2::Config, password: &str) -> (String, String) {
+    let mut salt: [u8; PASSWD_SALT_LENGTH / 4] = [0; PASSWD_SALT_LENGTH / 4];
+    rand::thread_rng().fill_bytes(&mut salt);
+    let salt = base64::encode(salt);
+
+    // TODO handle error case
+    let password = argon2::hash_encoded(password.as_bytes(), salt.as_bytes(), config).unwrap();
+
+    (salt, password)
+}
+
+fn needs_rehash(c: &argon2::Config, encoded: &str) -> bool {
+    // same layout as argon2's encoding, up to the salt
+    let params = format!(
+        "${}$v={}$m={},t={},p={}$",
+        c.variant, c.version, c.mem_cost, c.time_cost, c.lanes
+    );
+
+    !encoded.starts_with(&params)
+}
+
+/// Who's connected and how to reach them, with its own lock: sending someone
+/// a message only needs to read this table
+#[derive(Clone)]
+struct Connections {
+    table: Arc<RwLock<ConnectionTable>>,
+    metrics: Arc<Metrics>,
+}
+
+/// A live connection, and when we last heard from it
+struct Peer {
+    conn: Connection,
+    /// The last command they sent (or when they connected)
+    last_active: Instant,
+    /// What they've typed, oldest first, up to `RECALL_LENGTH` lines
+    history: VecDeque<String>,
+}
+
+#[derive(Default)]
+struct ConnectionTable {
+    /// Each `PersonId` has exactly one connection
+    peers: HashMap<PersonId, Peer>,
+    /// Each `PersonId` has a corresponding message queue
+    queues: HashMap<PersonId, MessageQueueTX>,
+}
+
+impl Connections {
+    fn new(metrics: Arc<Metrics>) -> Self {
+        Connections {
+            table: Arc::new(RwLock::new(ConnectionTable::default())),
+            metrics,
+        }
+    }
+
+    // guards are never held across an `.await`, and a panicking session
+    // shouldn't cut everyone else off
+    fn read(&self) -> RwLockReadGuard<'_, ConnectionTable> {
+        self.table.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, ConnectionTable> {
+        self.table.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn send_to(&self, id: PersonId, message: Message) {
+        trace!(id, ?message, "send_to");
+
+        let q = self.read().queues.get(&id).cloned();
+        match q {
+            None => warn!(id, "no message queue... disconnected?"),
+            Some(mut q) => self.deliver(id, &mut q, message),
+        }
+    }
+
+    /// Queue up a message for `id`, disconnecting them if they're too far
+    /// behind. Don't call this while holding the table's lock.
+    fn deliver(&self, id: PersonId, q: &mut MessageQueueTX, message: Message) {
+        match q.try_send(message) {
+            Ok(()) => self.metrics.message_sent(),
+            Err(TrySendError::Full(_)) => {
+                // without a queue, their session ends once it drains what's
+                // already waiting (several snapshots may notice; only act once)
+                if self.write().queues.remove(&id).is_some() {
+                    warn!(id, "message queue full, disconnecting slow peer");
+                    self.metrics.slow_peer_disconnected();
+                }
+            }
+            // they've disconnected since the snapshot
+            Err(e @ TrySendError::Closed(_)) => warn!(id, ?e, "bad message queue"),
+        }
     }
 }
 
-/// A connection to the server, either directly over TCP (e.g., telnet or a MUD client)
-/// or statelessly via an HTTP session.
+/// Resolves when the server is shutting down (see `State::shutdown`)
+#[derive(Clone)]
+pub struct ShutdownSignal(watch::Receiver<bool>);
+
+impl ShutdownSignal {
+    pub async fn recv(&mut self) {
+        // the first `recv` yields the current value, so wait for a `true`
+        while let Some(down) = self.0.recv().await {
+            if down {
+                return;
+            }
+        }
+    }
+}
+
+/// A connection to the server, either directly over TCP (e.g., telnet or a MUD client),
+/// over a local Unix domain socket, or statelessly via an HTTP session.
 ///
 /// Each such connection will have its own message queue.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Connection {
     /// TCP sessions merely need to track the peer
     TCP { addr: SocketAddr },
+    /// Unix socket peers are anonymous, so we track the socket they came in on
+    Unix { path: PathBuf },
     /// HTTP sessions track the session ID
     HTTP { session: String },
 }
 
-pub type MessageQueueTX = mpsc::UnboundedSender<Message>;
-pub type MessageQueueRX = mpsc::UnboundedReceiver<Message>;
+impl Connection {
+    /// Line-oriented connections (TCP and Unix sockets) are terminals, which
+    /// see prompts, colors, and the like
+    pub fn is_line_oriented(&self) -> bool {
+        match self {
+            Connection::TCP { .. } | Connection::Unix { .. } => true,
+            Connection::HTTP { .. } => false,
+        }
+    }
+
+    /// Where this connection came from, for people to read: ports change with
+    /// every connection, so they're left off
+    pub fn origin(&self) -> String {
+        match self {
+            Connection::TCP { addr } => addr.ip().to_string(),
+            Connection::Unix { .. } => "a local socket".to_string(),
+            Connection::HTTP { .. } => "the web".to_string(),
+        }
+    }
+
+    /// How this connection reached us, without saying from where
+    pub fn transport(&self) -> &'static str {
+        match self {
+            Connection::TCP { .. } => "TCP",
+            Connection::Unix { .. } => "Unix",
+            Connection::HTTP { .. } => "HTTP",
+        }
+    }
+}
+
+impl fmt::Display for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Connection::TCP { addr } => write!(f, "{}", addr),
+            Connection::Unix { path } => write!(f, "{}", path.display()),
+            // session IDs are secrets, so keep them out of the logs
+            Connection::HTTP { .. } => write!(f, "an HTTP session"),
+        }
+    }
+}
+
+/// A snapshot of some message queues, so the fan-out of a roomcast or
+/// broadcast can happen without holding the `State` lock
+///
+/// Each session's own messages still arrive in order, but two people speaking
+/// at once may be heard in different orders by different listeners.
+pub struct Recipients {
+    queues: Vec<(PersonId, MessageQueueTX)>,
+    connections: Connections,
+}
+
+impl Recipients {
+    fn none(connections: Connections) -> Self {
+        Recipients {
+            queues: Vec::new(),
+            connections,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.is_empty()
+    }
+
+    /// Leave someone out, e.g., the person a message is about
+    pub fn except(mut self, id: PersonId) -> Self {
+        self.queues.retain(|(q_id, _)| *q_id != id);
+        self
+    }
+
+    pub fn contains(&self, id: PersonId) -> bool {
+        self.queues.iter().any(|(q_id, _)| *q_id == id)
+    }
+
+    /// Let 'em hear about it
+    pub fn send(&self, message: Message) {
+        for (id, q) in &self.queues {
+            // `try_send` needs its own sender
+            self.connections.deliver(*id, &mut q.clone(), message.clone());
+        }
+    }
+}
+
+pub type MessageQueueTX = mpsc::Sender<Message>;
+pub type MessageQueueRX = mpsc::Receiver<Message>;
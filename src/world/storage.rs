@@ -0,0 +1,343 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use serde::{Deserialize, Serialize};
+
+use tracing::error;
+
+use crate::world::locale::DEFAULT_LOCALE;
+use crate::world::person::*;
+use crate::world::room::*;
+
+/// The account database: everything about people that outlives a session.
+///
+/// `State` only talks to accounts through this trait, so the live game logic
+/// doesn't care whether records are kept in memory or on disk. Lookups return
+/// owned records, since a disk-backed store can't lend out references.
+pub trait Storage: Send + Sync {
+    /// Allocate a `PersonId` that has never been used
+    fn fresh_id(&mut self) -> PersonId;
+
+    /// Add a new account. The caller must ensure the name is free.
+    fn insert_person(&mut self, person: PersonRecord);
+
+    fn person_by_id(&self, id: PersonId) -> Option<PersonRecord>;
+
+    /// Look up an account by name, ignoring case (see `canonical_name`)
+    fn person_by_name(&self, name: &str) -> Option<PersonRecord>;
+
+    /// Names of every account whose name contains `needle`, ignoring case
+    fn names_containing(&self, needle: &str) -> Vec<String>;
+
+    /// How many accounts there are
+    fn num_people(&self) -> usize;
+
+    /// Overwrite an existing account's record (its id and name must not change)
+    fn update_person(&mut self, person: PersonRecord);
+
+    /// Change an account's name, returning `false` if the new name is taken
+    fn rename(&mut self, id: PersonId, name: &str) -> bool;
+
+    /// Replace an account's (already hashed) password
+    fn set_password(&mut self, id: PersonId, salt: String, password: String);
+
+    /// Delete an account, returning its record if it existed. Its id is never
+    /// reused, but its name is free for someone else to register.
+    fn remove_person(&mut self, id: PersonId) -> Option<PersonRecord>;
+
+    /// Every account, e.g., to write to disk (see `State::save`)
+    fn snapshot(&self) -> Snapshot;
+}
+
+/// A copy of the whole account database, as saved to disk
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Next `PersonId` to generate, so ids of deleted accounts stay retired
+    pub next_id: PersonId,
+    pub people: Vec<PersonRecord>,
+}
+
+/// Accounts kept in memory, lost on restart
+#[derive(Default)]
+pub struct InMemoryStorage {
+    /// Next `PersonId` to generate
+    next_id: PersonId,
+    /// Each PersonId is associated with Person data
+    people: HashMap<PersonId, PersonRecord>,
+    /// Index of canonical names to PersonId
+    names: HashMap<String, PersonId>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        InMemoryStorage::default()
+    }
+
+    /// Accounts as they were when `snapshot` was taken
+    pub fn from_snapshot(snapshot: Snapshot) -> Self {
+        let mut db = InMemoryStorage {
+            next_id: snapshot.next_id,
+            ..InMemoryStorage::default()
+        };
+        for person in snapshot.people {
+            if db.names.contains_key(&canonical_name(&person.name)) {
+                error!(id = person.id, name = person.name.as_str(), "name taken twice");
+                continue;
+            }
+            db.next_id = db.next_id.max(person.id + 1);
+            db.insert_person(person);
+        }
+        db
+    }
+}
+
+impl Storage for InMemoryStorage {
+    fn fresh_id(&mut self) -> PersonId {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    fn insert_person(&mut self, person: PersonRecord) {
+        let name = canonical_name(&person.name);
+        assert!(!self.names.contains_key(&name));
+
+        self.names.insert(name, person.id);
+        self.people.insert(person.id, person);
+    }
+
+    fn person_by_id(&self, id: PersonId) -> Option<PersonRecord> {
+        self.people.get(&id).cloned()
+    }
+
+    fn person_by_name(&self, name: &str) -> Option<PersonRecord> {
+        let id = self.names.get(&canonical_name(name))?;
+        self.people.get(id).cloned().or_else(|| {
+            error!(name, id, "in names but not people");
+            None
+        })
+    }
+
+    fn names_containing(&self, needle: &str) -> Vec<String> {
+        let needle = canonical_name(needle);
+        self.names
+            .iter()
+            .filter(|(name, _)| name.contains(&needle))
+            .filter_map(|(_, id)| self.people.get(id))
+            .map(|person| person.name.clone())
+            .collect()
+    }
+
+    fn num_people(&self) -> usize {
+        self.people.len()
+    }
+
+    fn update_person(&mut self, person: PersonRecord) {
+        match self.people.get_mut(&person.id) {
+            None => error!(id = person.id, "no such person to update"),
+            Some(old) => {
+                debug_assert_eq!(old.name, person.name, "use rename to change names");
+                *old = person;
+            }
+        }
+    }
+
+    fn rename(&mut self, id: PersonId, name: &str) -> bool {
+        let canonical = canonical_name(name);
+        if let Some(&owner) = self.names.get(&canonical) {
+            // changing case alone is fine
+            if owner != id {
+                return false;
+            }
+        }
+
+        let person = match self.people.get_mut(&id) {
+            None => {
+                error!(id, "no such person to rename");
+                return false;
+            }
+            Some(person) => person,
+        };
+
+        self.names.remove(&canonical_name(&person.name));
+        person.name = name.to_string();
+        self.names.insert(canonical, id);
+
+        true
+    }
+
+    fn set_password(&mut self, id: PersonId, salt: String, password: String) {
+        match self.people.get_mut(&id) {
+            None => error!(id, "no such person to set password"),
+            Some(person) => {
+                person.salt = salt;
+                person.password = password;
+            }
+        }
+    }
+
+    fn remove_person(&mut self, id: PersonId) -> Option<PersonRecord> {
+        let person = self.people.remove(&id)?;
+        self.names.remove(&canonical_name(&person.name));
+        Some(person)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        let mut people: Vec<PersonRecord> = self.people.values().cloned().collect();
+        people.sort_by_key(|person| person.id);
+        Snapshot {
+            next_id: self.next_id,
+            people,
+        }
+    }
+}
+
+/// A shared handle on the account database, with its own lock so that logins
+/// and lookups don't wait on the rest of the game. Reads far outnumber writes.
+///
+/// Guards are never held across an `.await`, so a plain `std` lock will do.
+/// When both locks are needed, take `db` before `reserved`.
+#[derive(Clone)]
+pub struct Accounts {
+    db: Arc<RwLock<Box<dyn Storage>>>,
+    /// Canonical names someone is partway through registering
+    reserved: Arc<Mutex<HashSet<String>>>,
+    /// Set on every change, so we know there's something to save (see
+    /// `State::is_dirty`)
+    dirty: Arc<AtomicBool>,
+}
+
+/// A claim on a free name, held while someone registers it
+///
+/// Nobody else can reserve or register the name until this is consumed by
+/// `Accounts::create` or dropped (e.g., when registration is abandoned).
+pub struct Reservation {
+    name: String,
+    reserved: Arc<Mutex<HashSet<String>>>,
+}
+
+impl Reservation {
+    /// The name as it was typed (not canonicalized)
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.reserved
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&canonical_name(&self.name));
+    }
+}
+
+impl Accounts {
+    pub fn new(db: Box<dyn Storage>, dirty: Arc<AtomicBool>) -> Self {
+        Accounts {
+            db: Arc::new(RwLock::new(db)),
+            reserved: Arc::new(Mutex::new(HashSet::new())),
+            dirty,
+        }
+    }
+
+    // a panicking session shouldn't lock everyone else out of their accounts
+    fn read(&self) -> RwLockReadGuard<'_, Box<dyn Storage>> {
+        self.db.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Box<dyn Storage>> {
+        self.dirty.store(true, Ordering::Relaxed);
+        self.db.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Claim `name` for registration, unless it's already someone's account or
+    /// someone else is registering it
+    pub fn reserve(&self, name: &str) -> Option<Reservation> {
+        let db = self.read();
+        if db.person_by_name(name).is_some() {
+            return None;
+        }
+
+        // holding `db` means nobody can register the name while we claim it
+        let mut reserved = self.reserved.lock().unwrap_or_else(PoisonError::into_inner);
+        if !reserved.insert(canonical_name(name)) {
+            return None;
+        }
+
+        Some(Reservation {
+            name: name.to_string(),
+            reserved: self.reserved.clone(),
+        })
+    }
+
+    /// Add an account with an already hashed password, releasing the
+    /// reservation on its name
+    pub fn create(&self, reservation: Reservation, salt: String, password: String) -> PersonRecord {
+        let mut db = self.write();
+
+        // nobody else could have taken the name while we held the reservation
+        debug_assert!(db.person_by_name(reservation.name()).is_none());
+
+        let person = PersonRecord {
+            id: db.fresh_id(),
+            loc: INITIAL_LOC,
+            name: reservation.name().to_string(),
+            salt,
+            password,
+            color: true,
+            locale: DEFAULT_LOCALE.to_string(),
+            banned: false,
+            ban_reason: None,
+            bio: None,
+            pronouns: None,
+            last_login: None,
+            last_login_from: None,
+        };
+        db.insert_person(person.clone());
+
+        person
+    }
+
+    pub fn person_by_id(&self, id: PersonId) -> Option<PersonRecord> {
+        self.read().person_by_id(id)
+    }
+
+    pub fn person_by_name(&self, name: &str) -> Option<PersonRecord> {
+        self.read().person_by_name(name)
+    }
+
+    pub fn names_containing(&self, needle: &str) -> Vec<String> {
+        self.read().names_containing(needle)
+    }
+
+    pub fn num_people(&self) -> usize {
+        self.read().num_people()
+    }
+
+    /// Read-modify-write an account's record under a single lock
+    pub fn modify<F: FnOnce(&mut PersonRecord)>(&self, id: PersonId, f: F) -> bool {
+        let mut db = self.write();
+        match db.person_by_id(id) {
+            None => false,
+            Some(mut person) => {
+                f(&mut person);
+                db.update_person(person);
+                true
+            }
+        }
+    }
+
+    pub fn set_password(&self, id: PersonId, salt: String, password: String) {
+        self.write().set_password(id, salt, password)
+    }
+
+    pub fn remove(&self, id: PersonId) -> Option<PersonRecord> {
+        self.write().remove_person(id)
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        self.read().snapshot()
+    }
+}
@@ -0,0 +1,36 @@
+//! ANSI styling for rendered messages.
+//!
+//! Every escape sequence the server emits lives here, so retheming means
+//! editing this file and nothing else.
+
+const RESET: &str = "\x1b[0m";
+
+/// The kinds of text we style differently
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// Other people talking
+    Speech,
+    /// Someone talking to the whole server
+    Shout,
+    /// Notices from the server: arrivals, departures, replies to commands
+    System,
+}
+
+impl Style {
+    fn code(self) -> &'static str {
+        match self {
+            Style::Speech => "\x1b[36m", // cyan
+            Style::Shout => "\x1b[1;35m", // bold magenta
+            Style::System => "\x1b[33m", // yellow
+        }
+    }
+
+    /// Wrap `text` in this style's escape codes, if `color` is enabled
+    pub fn paint(self, text: &str, color: bool) -> String {
+        if color && !text.is_empty() {
+            format!("{}{}{}", self.code(), text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
@@ -0,0 +1,233 @@
+extern crate much;
+
+use std::time::Duration;
+
+use much::{parse_duration, Config, LogFormat};
+
+#[test]
+fn durations() {
+    assert_eq!(parse_duration("forever"), Ok(None));
+    assert_eq!(parse_duration("60"), Ok(Some(Duration::from_secs(60))));
+    assert_eq!(parse_duration("30s"), Ok(Some(Duration::from_secs(30))));
+    assert_eq!(parse_duration("10m"), Ok(Some(Duration::from_secs(600))));
+    assert_eq!(parse_duration("1h30m"), Ok(Some(Duration::from_secs(5400))));
+    assert_eq!(parse_duration("2d"), Ok(Some(Duration::from_secs(172_800))));
+}
+
+#[test]
+fn bad_durations() {
+    for bad in &["", "soon", "1h30", "h", "-5", "10x", "99999999999999999999d"] {
+        assert!(parse_duration(bad).is_err(), "'{}' should be rejected", bad);
+    }
+}
+
+#[test]
+fn default_argon2_params_unchanged() {
+    let config = Config::default();
+    assert!(config.validate().is_ok());
+
+    let ours = config.password_config();
+    let theirs = argon2::Config::default();
+    assert_eq!(ours.mem_cost, theirs.mem_cost);
+    assert_eq!(ours.time_cost, theirs.time_cost);
+    assert_eq!(ours.lanes, theirs.lanes);
+}
+
+#[test]
+fn bad_argon2_params() {
+    let config = Config {
+        argon2_iterations: 0,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = Config {
+        argon2_lanes: 4,
+        argon2_mem_kib: 16,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn max_connections_at_least_one() {
+    let config = Config {
+        max_connections: Some(0),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn heartbeat_not_zero() {
+    let config = Config {
+        heartbeat: Some(std::time::Duration::from_secs(0)),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn autosave_not_zero() {
+    let config = Config {
+        autosave: std::time::Duration::from_secs(0),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn idle_warning_before_timeout() {
+    let config = Config {
+        idle_timeout: Some(std::time::Duration::from_secs(60)),
+        idle_warning: std::time::Duration::from_secs(60),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+
+    let config = Config {
+        idle_timeout: Some(std::time::Duration::from_secs(60)),
+        idle_warning: std::time::Duration::from_secs(0),
+        ..Config::default()
+    };
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn body_size_not_zero() {
+    let config = Config {
+        max_body_size: 0,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn password_attempts_at_least_one() {
+    let config = Config {
+        max_password_attempts: 0,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn flood_rate_positive() {
+    for rate in [0.0, -1.0, f64::NAN].iter() {
+        let config = Config {
+            flood_rate: *rate,
+            ..Config::default()
+        };
+        assert!(config.validate().is_err(), "flood rate {} should be rejected", rate);
+    }
+}
+
+#[test]
+fn flood_burst_not_zero() {
+    let config = Config {
+        flood_burst: 0,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn lobby_needs_a_name() {
+    let config = Config {
+        lobby_name: "???".to_string(),
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
+
+#[tokio::test]
+async fn rehash_when_params_change() {
+    let old = much::init(&Config::default());
+    let hash = old.lock().await.new_person("@a", "aaaaaaaa").password;
+    assert!(!old.lock().await.needs_rehash(&hash));
+
+    let new = much::init(&Config {
+        argon2_iterations: 4,
+        ..Config::default()
+    });
+    assert!(new.lock().await.needs_rehash(&hash));
+}
+
+#[tokio::test]
+async fn filter_masks_words() {
+    let state = much::init(&Config {
+        filter_words: vec!["darn".to_string(), "HECK".to_string()],
+        ..Config::default()
+    });
+    let state = state.lock().await;
+
+    assert_eq!(state.filter("Darn it, what the heck?"), "**** it, what the ****?");
+    assert_eq!(state.filter("darnit café"), "****it café");
+    assert_eq!(state.filter("nothing to see"), "nothing to see");
+
+    let unfiltered = much::init(&Config::default());
+    assert_eq!(unfiltered.lock().await.filter("darn"), "darn");
+}
+
+#[test]
+fn log_formats() {
+    assert_eq!("text".parse(), Ok(LogFormat::Text));
+    assert_eq!("json".parse(), Ok(LogFormat::Json));
+    assert!("xml".parse::<LogFormat>().is_err());
+}
+
+#[test]
+fn bind_addresses_and_ports() {
+    for addr in &["0.0.0.0", "127.0.0.1", "::1", "[::1]", "localhost", "much.example.com"] {
+        let config = Config {
+            addr: addr.to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok(), "'{}' should be allowed", addr);
+    }
+
+    for addr in &["", "foo:bar", "[::1", "two words", "-bad.example.com", "a..b"] {
+        let config = Config {
+            addr: addr.to_string(),
+            ..Config::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(format!("invalid bind address '{}'", addr))
+        );
+    }
+
+    for port in &["", "http", "70000", "-1"] {
+        let config = Config {
+            tcp_port: port.to_string(),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err(), "TCP port '{}' should be rejected", port);
+
+        let config = Config {
+            unified_port: Some(port.to_string()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err(), "unified port '{}' should be rejected", port);
+    }
+}
+
+#[test]
+fn run_refuses_bad_config() {
+    let config = Config {
+        addr: "foo:bar".to_string(),
+        ..Config::default()
+    };
+    let state = much::init(&config);
+    let err = much::run(&config, state).expect_err("bad address");
+    assert_eq!(err.to_string(), "invalid bind address 'foo:bar'");
+}
+
+#[test]
+fn page_size_not_zero() {
+    let config = Config {
+        page_size: 0,
+        ..Config::default()
+    };
+    assert!(config.validate().is_err());
+}
@@ -0,0 +1,773 @@
+extern crate much;
+
+use hyper::header::{CONTENT_TYPE, COOKIE, SET_COOKIE};
+use hyper::{Body, Client, Method, Request, StatusCode};
+use much::testing::TestClient;
+use much::*;
+
+async fn get(url: &str) -> (StatusCode, String) {
+    let resp = Client::new()
+        .get(url.parse().expect("valid URL"))
+        .await
+        .expect("response");
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .expect("body");
+    (status, String::from_utf8(body.to_vec()).expect("UTF-8 body"))
+}
+
+/// POST a form (already URL-encoded), with the session cookie if there is one
+async fn post(url: &str, cookie: Option<&str>, form: &str) -> (StatusCode, Option<String>, String) {
+    let mut req = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded");
+    if let Some(cookie) = cookie {
+        req = req.header(COOKIE, cookie);
+    }
+    let req = req.body(Body::from(form.to_string())).expect("request");
+
+    let resp = Client::new().request(req).await.expect("response");
+    let status = resp.status();
+    let set_cookie = resp
+        .headers()
+        .get(SET_COOKIE)
+        .map(|cookie| cookie.to_str().expect("ASCII cookie").to_string());
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .expect("body");
+    let body = String::from_utf8(body.to_vec()).expect("UTF-8 body");
+    (status, set_cookie, body)
+}
+
+async fn get_with_cookie(url: &str, cookie: &str) -> (StatusCode, String) {
+    let req = Request::builder()
+        .uri(url)
+        .header(COOKIE, cookie)
+        .body(Body::empty())
+        .expect("request");
+    let resp = Client::new().request(req).await.expect("response");
+    let status = resp.status();
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .expect("body");
+    (status, String::from_utf8(body.to_vec()).expect("UTF-8 body"))
+}
+
+async fn serve(config: &Config) -> GameState {
+    let state = much::init(config);
+    {
+        let mut state = state.lock().await;
+        for name in &["@a", "@bob", "@Bobby"] {
+            let _ = state.new_person(name, "password");
+        }
+    }
+    tokio::spawn(http_serve(state.clone(), config.http_addr()));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+    state
+}
+
+#[tokio::test]
+async fn find_forbidden_unless_public() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4180".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, _) = get(&format!("http://{}/api/find?q=bob", config.http_addr())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn find_when_public() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4181".to_string(),
+        public_find: true,
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, body) = get(&format!("http://{}/api/find?q=%40BOB", config.http_addr())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "@bob\n@Bobby");
+
+    let (status, _) = get(&format!("http://{}/api/find", config.http_addr())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn errors_are_json() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4182".to_string(),
+        public_find: true,
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, body) = get(&format!("http://{}/nowhere", config.http_addr())).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body, r#"{"error":"Not Found"}"#);
+
+    let (status, body) = get(&format!("http://{}/who", config.http_addr())).await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    assert_eq!(body, r#"{"error":"Not Implemented"}"#);
+
+    let (status, body) = get(&format!("http://{}/api/find", config.http_addr())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body.contains("missing query parameter q"), "got '{}'", body);
+}
+
+#[tokio::test]
+async fn web_users_share_rooms_with_terminals() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4020".to_string(),
+        http_port: "4183".to_string(),
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let base = format!("http://{}", config.http_addr());
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "password").await;
+
+    let (status, _, _) = post(
+        &format!("{}/api/login", base),
+        None,
+        "name=%40bob&password=wrong",
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, cookie, body) = post(
+        &format!("{}/api/login", base),
+        None,
+        "name=%40bob&password=password",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+    let login: serde_json::Value = serde_json::from_str(&body).expect("JSON login");
+    let tok = login["tok"].as_str().expect("CSRF token").to_string();
+
+    a.expect_containing("@bob arrived").await;
+    a.send("who").await;
+    a.expect_containing("@bob").await;
+
+    a.send("say hi").await;
+    a.expect_containing("hi").await;
+    let (status, body) = get_with_cookie(&format!("{}/api/be", base), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("@a says"), "got '{}'", body);
+    let be: serde_json::Value = serde_json::from_str(&body).expect("JSON messages");
+    let events = be["events"].as_array().expect("events");
+    let said = events
+        .iter()
+        .find(|event| event["type"] == "say")
+        .expect("say event");
+    assert_eq!(said["speaker_name"], "@a");
+    assert_eq!(said["text"], "hi");
+    assert!(said["time"].is_string());
+    assert!(said.get("speaker").is_none(), "leaked a PersonId: {}", said);
+
+    let (status, _, _) = post(
+        &format!("{}/api/do", base),
+        Some(&cookie),
+        "tok=bogus&command=say+hello",
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let form = format!("tok={}&command=say+hello", tok);
+    let (status, _, _) = post(&format!("{}/api/do", base), Some(&cookie), &form).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    a.expect_containing("@bob says").await;
+
+    let (status, _) = get(&format!("{}/api/be", base)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn version_reports_the_build() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4184".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, body) = get(&format!("http://{}/version", config.http_addr())).await;
+    assert_eq!(status, StatusCode::OK);
+    let json: serde_json::Value = serde_json::from_str(&body).expect("json");
+    assert_eq!(json["version"], much::VERSION);
+    assert!(json["uptime_secs"].is_u64(), "got '{}'", body);
+}
+
+#[tokio::test]
+async fn rooms_can_be_browsed() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4038".to_string(),
+        http_port: "4185".to_string(),
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let base = format!("http://{}", config.http_addr());
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "password").await;
+    a.send("topic cats").await;
+    a.expect_containing("cats").await;
+
+    let (status, body) = get(&format!("{}/room/0", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    let room: serde_json::Value = serde_json::from_str(&body).expect("JSON room");
+    assert_eq!(room["name"], "Lobby");
+    assert_eq!(room["topic"], "cats");
+    assert_eq!(room["occupants"], serde_json::json!(["@a"]));
+    assert!(!body.contains("password"), "got '{}'", body);
+
+    let (status, same) = get(&format!("{}/room?id=0", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(same, body);
+
+    let (status, _) = get(&format!("{}/room/99", base)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get(&format!("{}/room/lobby", base)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = get(&format!("{}/room", base)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn profiles_when_public() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4039".to_string(),
+        http_port: "4186".to_string(),
+        public_find: true,
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let base = format!("http://{}", config.http_addr());
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "password").await;
+    a.send("set bio likes cats").await;
+    a.expect_containing("bio").await;
+
+    let (status, body) = get(&format!("{}/user/%40a", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    let profile: serde_json::Value = serde_json::from_str(&body).expect("JSON profile");
+    assert_eq!(profile["name"], "@a");
+    assert_eq!(profile["bio"], "likes cats");
+    assert_eq!(profile["online"], true);
+    assert!(!body.contains("salt"), "got '{}'", body);
+    assert!(!body.contains("password"), "got '{}'", body);
+
+    let (status, body) = get(&format!("{}/user?name=%40bob", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    let profile: serde_json::Value = serde_json::from_str(&body).expect("JSON profile");
+    assert_eq!(profile["online"], false);
+    assert!(profile["pronouns"].is_null());
+
+    let (status, _) = get(&format!("{}/user/%40nobody", base)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn profiles_forbidden_unless_public() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4187".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, _) = get(&format!("http://{}/user/%40a", config.http_addr())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn unified_port_speaks_both() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        unified_port: Some("4188".to_string()),
+        ..Config::default()
+    };
+    let addr = config.unified_addr().expect("unified address");
+    let state = much::init(&config);
+    {
+        let mut state = state.lock().await;
+        let _ = state.new_person("@a", "password");
+    }
+    tokio::spawn(unified_serve(state.clone(), addr.clone()));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut a = TestClient::connect(&addr).await;
+    a.login("@a", "password").await;
+
+    let (status, body) = get(&format!("http://{}/version", addr)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains(much::VERSION), "got '{}'", body);
+
+    a.send("who").await;
+    a.expect_containing("@a").await;
+}
+
+#[tokio::test]
+async fn heartbeats_reach_web_clients_only() {
+    let state = much::init(&Config::default());
+    {
+        let mut state = state.lock().await;
+        for name in &["@a", "@bob"] {
+            let _ = state.new_person(name, "password");
+        }
+    }
+    let _server = ServerBuilder::new()
+        .tcp_addr("127.0.0.1:4042")
+        .http_addr("127.0.0.1:4189")
+        .heartbeat(std::time::Duration::from_millis(300))
+        .with_state(state)
+        .spawn();
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut a = TestClient::connect("127.0.0.1:4042").await;
+    a.login("@a", "password").await;
+
+    let (status, cookie, _) = post(
+        "http://127.0.0.1:4189/api/login",
+        None,
+        "name=%40bob&password=password",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+
+    let mut heartbeat = None;
+    for _ in 0..10 {
+        let (status, body) = get_with_cookie("http://127.0.0.1:4189/api/be", &cookie).await;
+        assert_eq!(status, StatusCode::OK);
+        let be: serde_json::Value = serde_json::from_str(&body).expect("JSON messages");
+        heartbeat = be["events"]
+            .as_array()
+            .expect("events")
+            .iter()
+            .find(|event| event["type"] == "heartbeat")
+            .cloned();
+        if heartbeat.is_some() {
+            break;
+        }
+    }
+    let heartbeat = heartbeat.expect("a heartbeat");
+    assert_eq!(heartbeat["online"], 2);
+
+    // the terminal saw @bob arrive, and nothing since
+    a.expect_containing("@bob arrived").await;
+    a.send("version").await;
+    let line = a.expect_line().await;
+    assert!(line.contains(much::VERSION), "got '{}'", line);
+}
+
+#[tokio::test]
+async fn oversized_bodies_refused() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4190".to_string(),
+        max_body_size: 64,
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let url = format!("http://{}/api/login", config.http_addr());
+
+    let padding = "x".repeat(100);
+    let (status, cookie, body) = post(
+        &url,
+        None,
+        &format!("name=%40a&password=password&padding={}", padding),
+    )
+    .await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert!(cookie.is_none());
+    assert!(body.contains("Payload Too Large"), "got '{}'", body);
+
+    // no Content-Length to go on, so it's caught while reading
+    let chunks = (0..3).map(|_| Ok::<_, std::io::Error>("x".repeat(40)));
+    let req = Request::builder()
+        .method(Method::POST)
+        .uri(&url)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::wrap_stream(futures::stream::iter(chunks)))
+        .expect("request");
+    let resp = Client::new().request(req).await.expect("response");
+    assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+    let (status, _, _) = post(&url, None, "name=%40a&password=password").await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn remembered_sessions_outlive_the_browser() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4191".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let url = format!("http://{}/api/login", config.http_addr());
+
+    let (status, cookie, _) = post(&url, None, "name=%40a&password=password").await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    assert!(!cookie.contains("Max-Age"), "got '{}'", cookie);
+
+    let (status, cookie, _) = post(&url, None, "name=%40bob&password=password&remember=on").await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    assert!(cookie.contains("; Max-Age=2592000"), "got '{}'", cookie);
+
+    let session = cookie.split(';').next().expect("cookie value");
+    let (status, _) = get_with_cookie(&format!("http://{}/api/be", config.http_addr()), session).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn expired_sessions_forgotten() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4192".to_string(),
+        session_timeout: std::time::Duration::from_secs(2),
+        // cheap hashes, so logging in over and over doesn't outlast the sessions
+        argon2_mem_kib: 8,
+        argon2_iterations: 1,
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    let base = format!("http://{}", config.http_addr());
+    let metrics = state.lock().await.metrics();
+
+    let mut cookies = Vec::new();
+    for _ in 0..10 {
+        let (status, cookie, _) =
+            post(&format!("{}/api/login", base), None, "name=%40a&password=password").await;
+        assert_eq!(status, StatusCode::OK);
+        let cookie = cookie.expect("session cookie");
+        cookies.push(cookie.split(';').next().expect("cookie value").to_string());
+    }
+    assert!(metrics.render().contains("much_http_sessions 10"));
+
+    tokio::time::delay_for(tokio::time::Duration::from_millis(2500)).await;
+    for cookie in &cookies {
+        let (status, _) = get_with_cookie(&format!("{}/api/be", base), cookie).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    assert!(metrics.render().contains("much_http_sessions 0"));
+    assert_eq!(state.lock().await.stats().connected, 0);
+}
+
+#[tokio::test]
+async fn logout_ends_the_session() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4056".to_string(),
+        http_port: "4193".to_string(),
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+    let base = format!("http://{}", config.http_addr());
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "password").await;
+
+    let (status, cookie, _) = post(
+        &format!("{}/api/login", base),
+        None,
+        "name=%40bob&password=password",
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+    a.expect_containing("@bob arrived").await;
+
+    let (status, cleared, _) = post(&format!("{}/api/logout", base), Some(&cookie), "").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let cleared = cleared.expect("cleared cookie");
+    assert!(cleared.starts_with("id=;"), "got '{}'", cleared);
+    assert!(cleared.contains("Max-Age=0"), "got '{}'", cleared);
+    a.expect_containing("@bob logged off.").await;
+    assert_eq!(state.lock().await.stats().connected, 1);
+
+    let (status, _) = get_with_cookie(&format!("{}/api/be", base), &cookie).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    // logging out again, or without ever logging in, is harmless
+    let (status, cleared, _) = post(&format!("{}/api/logout", base), Some(&cookie), "").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    assert!(cleared.is_some());
+    let (status, _, _) = post(&format!("{}/api/logout", base), None, "").await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn register_on_the_web() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4194".to_string(),
+        ..Config::default()
+    };
+    let state = serve(&config).await;
+    let url = format!("http://{}/register", config.http_addr());
+
+    let resp = Client::new()
+        .get(url.parse().expect("valid URL"))
+        .await
+        .expect("response");
+    assert_eq!(resp.status(), StatusCode::OK);
+    let cookie = resp
+        .headers()
+        .get(SET_COOKIE)
+        .expect("token cookie")
+        .to_str()
+        .expect("ASCII cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+    let tok = cookie.strip_prefix("regtok=").expect("token").to_string();
+    let body = hyper::body::to_bytes(resp.into_body())
+        .await
+        .expect("body");
+    let body = String::from_utf8(body.to_vec()).expect("UTF-8 body");
+    assert!(body.contains(&format!("value=\"{}\"", tok)), "got '{}'", body);
+
+    // no token, no registration
+    let form = "name=%40new&password=password&confirm=password";
+    let (status, _, _) = post(&url, Some(&cookie), &format!("tok=bogus&{}", form)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _, _) = post(&url, None, &format!("tok={}&{}", tok, form)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // the same rules as at a terminal, explained beside each field
+    for (form, complaint) in &[
+        ("name=%40a&password=password&confirm=password", "There is already someone named @a."),
+        ("name=new&password=password&confirm=password", "email address"),
+        ("name=%40new&password=short&confirm=short", "at least 8 characters"),
+        ("name=%40new&password=password&confirm=passw0rd", "Passwords don&#39;t match."),
+    ] {
+        let (status, set_cookie, body) =
+            post(&url, Some(&cookie), &format!("tok={}&{}", tok, form)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "for {}", form);
+        assert!(set_cookie.is_none());
+        assert!(body.contains(complaint), "expected '{}', got '{}'", complaint, body);
+    }
+    let (_, _, body) = post(
+        &url,
+        Some(&cookie),
+        &format!("tok={}&name=%3Cb%3E&password=x&confirm=x", tok),
+    )
+    .await;
+    assert!(body.contains("value=\"&lt;b&gt;\""), "got '{}'", body);
+    assert_eq!(state.lock().await.stats().accounts, 3);
+
+    let (status, session, _) = post(&url, Some(&cookie), &format!("tok={}&{}", tok, form)).await;
+    assert_eq!(status, StatusCode::SEE_OTHER);
+    let session = session.expect("session cookie");
+    let session = session.split(';').next().expect("cookie value").to_string();
+    assert!(session.starts_with("id="), "got '{}'", session);
+    assert!(state.lock().await.person_by_name("@new").is_some());
+
+    let (status, body) = get_with_cookie(&format!("http://{}/api/be", config.http_addr()), &session).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("Here: @new"), "got '{}'", body);
+}
+
+#[tokio::test]
+async fn register_forbidden_when_closed() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4195".to_string(),
+        open_registration: false,
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let url = format!("http://{}/register", config.http_addr());
+
+    let (status, _) = get(&url).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _, _) = post(&url, Some("regtok=x"), "tok=x&name=%40new&password=password&confirm=password").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn admin_dashboard_for_admins_only() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4196".to_string(),
+        admins: vec!["@a".to_string()],
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let base = format!("http://{}", config.http_addr());
+
+    let mut cookies = Vec::new();
+    for name in &["%40a", "%40bob"] {
+        let form = format!("name={}&password=password", name);
+        let (status, cookie, _) = post(&format!("{}/api/login", base), None, &form).await;
+        assert_eq!(status, StatusCode::OK);
+        let cookie = cookie.expect("session cookie");
+        cookies.push(cookie.split(';').next().expect("cookie value").to_string());
+    }
+
+    let (status, _) = get(&format!("{}/admin", base)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = get_with_cookie(&format!("{}/admin", base), &cookies[1]).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = get_with_cookie(&format!("{}/admin", base), &cookies[0]).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.contains("argon2"), "leaked a password hash: {}", body);
+    let admin: serde_json::Value = serde_json::from_str(&body).expect("JSON dashboard");
+    assert_eq!(admin["stats"]["connected"], 2);
+    assert_eq!(admin["stats"]["accounts"], 3);
+    assert!(admin["stats"]["uptime_secs"].is_number());
+    let sessions = admin["sessions"].as_array().expect("sessions");
+    let names: Vec<&str> = sessions.iter().filter_map(|s| s["name"].as_str()).collect();
+    assert_eq!(names, vec!["@a", "@bob"]);
+    assert_eq!(admin["rooms"][0]["occupants"], 2);
+}
+
+#[tokio::test]
+async fn hostnames_bind_every_address() {
+    let config = Config {
+        addr: "localhost".to_string(),
+        http_port: "4197".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+
+    let (status, _) = get("http://127.0.0.1:4197/version").await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a name that doesn't resolve is an error, not a panic
+    let state = much::init(&config);
+    assert!(http_serve(state, "nowhere.invalid:4198").await.is_err());
+}
+
+#[tokio::test]
+async fn listings_are_paged() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4200".to_string(),
+        public_find: true,
+        page_size: 1,
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let base = format!("http://{}", config.http_addr());
+
+    let (status, body) = get(&format!("{}/api/find?q=bob", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "@bob\n...and 1 more");
+    let (status, body) = get(&format!("{}/api/find?q=bob&page=2", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "@Bobby");
+    let (status, body) = get(&format!("{}/api/find?q=bob&per_page=5", base)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "@bob\n@Bobby");
+    for bad in &["page=0", "per_page=0", "page=next"] {
+        let (status, _) = get(&format!("{}/api/find?q=bob&{}", base, bad)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "for {}", bad);
+    }
+
+    let (status, cookie, body) =
+        post(&format!("{}/api/login", base), None, "name=%40bob&password=password").await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+    let login: serde_json::Value = serde_json::from_str(&body).expect("JSON login");
+    let tok = login["tok"].as_str().expect("CSRF token");
+
+    let form = format!("tok={}&command=who", tok);
+    let (status, _, _) = post(&format!("{}/api/do", base), Some(&cookie), &form).await;
+    assert!(status.is_success(), "got {}", status);
+    let (_, body) = get_with_cookie(&format!("{}/api/be", base), &cookie).await;
+    let be: serde_json::Value = serde_json::from_str(&body).expect("JSON messages");
+    let who = be["events"]
+        .as_array()
+        .expect("events")
+        .iter()
+        .find(|event| event["type"] == "who")
+        .expect("who event");
+    assert_eq!(who["people"].as_array().map(Vec::len), Some(1));
+    assert_eq!(who["page"], 1);
+    assert_eq!(who["pages"], 1);
+    assert_eq!(who["total"], 1);
+}
+
+#[tokio::test]
+async fn own_arrival_on_the_web() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4201".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let base = format!("http://{}", config.http_addr());
+
+    let (status, cookie, _) =
+        post(&format!("{}/api/login", base), None, "name=%40bob&password=password").await;
+    assert_eq!(status, StatusCode::OK);
+    let cookie = cookie.expect("session cookie");
+    let cookie = cookie.split(';').next().expect("cookie value").to_string();
+
+    let (status, body) = get_with_cookie(&format!("{}/api/be", base), &cookie).await;
+    assert_eq!(status, StatusCode::OK);
+    let be: serde_json::Value = serde_json::from_str(&body).expect("JSON messages");
+    let messages = be["messages"].as_array().expect("messages");
+    assert!(
+        messages.iter().any(|m| m == "You arrived in Lobby."),
+        "got {:?}",
+        messages
+    );
+    let arrival = be["events"]
+        .as_array()
+        .expect("events")
+        .iter()
+        .find(|event| event["type"] == "arrive")
+        .expect("arrive event");
+    assert_eq!(arrival["name"], "@bob");
+    assert_eq!(arrival["loc_name"], "Lobby");
+    assert_eq!(arrival["you"], true);
+}
+
+#[tokio::test]
+async fn unknown_names_fail_like_wrong_passwords() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        http_port: "4202".to_string(),
+        ..Config::default()
+    };
+    let _state = serve(&config).await;
+    let url = format!("http://{}/api/login", config.http_addr());
+
+    let wrong = post(&url, None, "name=%40bob&password=wrong").await;
+    let unknown = post(&url, None, "name=%40nobody&password=wrong").await;
+    assert_eq!(wrong.0, StatusCode::UNAUTHORIZED);
+    assert_eq!(unknown, wrong);
+}
@@ -1,38 +1,114 @@
 extern crate much;
 
 use futures_util::sink::SinkExt;
+use much::testing::TestClient;
 use much::*;
 use tokio::stream::StreamExt;
 use tokio_util::codec::{Framed, LinesCodec};
 
 fn config_timeout(timeout: u64) -> Config {
     let mut config = Config::default();
-    config.timeout = Some(timeout);
+    config.timeout = Some(std::time::Duration::from_secs(timeout));
     config
 }
 
+/// The accounts most tests log in as
+const ACCOUNTS: &[(&str, &str)] = &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb"), ("@c", "cccccccc")];
+
 async fn simple_state() -> GameState {
-    let state = much::init();
+    let state = much::init(&Config::default());
 
     {
         let mut state = state.lock().await;
 
-        let _ = state.new_person("@a", "aaaaaaaa");
-        let _ = state.new_person("@b", "bbbbbbbb");
-        let _ = state.new_person("@c", "cccccccc");
+        for (name, password) in ACCOUNTS {
+            let _ = state.new_person(name, password);
+        }
     }
     state
 }
 
+/// Start a TCP server for `config`, with `accounts` (names and passwords)
+/// already registered
+async fn serve(config: &Config, accounts: &[(&str, &str)]) -> GameState {
+    let state = much::init(config);
+    {
+        let mut state = state.lock().await;
+        for (name, password) in accounts {
+            let _ = state.new_person(name, password);
+        }
+    }
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+    state
+}
+
 #[tokio::test]
 async fn successful_login_and_shutdown() {
-    let config = config_timeout(1);
-    let state = simple_state().await;
+    let config = Config {
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
 
-    let tcp_server = tcp_serve(state.clone(), config.tcp_addr());
+    let mut client = TestClient::connect(config.tcp_addr()).await;
+    client.login("@a", "aaaaaaaa").await;
+    client.send("shutdown").await;
 
-    tokio::spawn(tcp_server);
-    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+    client.expect_containing("shutting down").await;
+    client.expect_containing("You have logged out.").await;
+    client.expect_disconnect().await;
+}
+
+#[test]
+fn run_returns_after_shutdown() {
+    let mut config = config_timeout(60);
+    config.tcp_port = "4001".to_string();
+    config.http_port = "4081".to_string();
+    let state = much::init(&config);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    let server_state = state.clone();
+    std::thread::spawn(move || {
+        let result = much::run(&config, server_state);
+        tx.send(result.is_ok()).expect("report result");
+    });
+    std::thread::sleep(std::time::Duration::from_millis(100));
+
+    futures::executor::block_on(async { state.lock().await.shutdown().await });
+
+    assert_eq!(
+        rx.recv_timeout(std::time::Duration::from_secs(5)),
+        Ok(true),
+        "run should return once the server shuts down"
+    );
+}
+
+#[tokio::test]
+async fn ipv6_loopback() {
+    let mut config = config_timeout(1);
+    config.addr = "::1".to_string();
+    config.tcp_port = "4002".to_string();
+    assert_eq!(config.tcp_addr(), "[::1]:4002");
+
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let stream = tokio::net::TcpStream::connect(config.tcp_addr())
+        .await
+        .expect("connected over IPv6");
+    let mut lines = Framed::new(stream, LinesCodec::new());
+
+    let prompt = lines.next().await.expect("username prompt");
+    assert!(prompt.is_ok(), "expected a prompt, got {:?}", prompt);
+}
+
+#[tokio::test]
+async fn bad_names_reprompt() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4004".to_string();
+
+    let _state = serve(&config, ACCOUNTS).await;
 
     let stream = tokio::net::TcpStream::connect(config.tcp_addr())
         .await
@@ -40,28 +116,2100 @@ async fn successful_login_and_shutdown() {
     let mut lines = Framed::new(stream, LinesCodec::new());
 
     let _prompt = lines.next().await.expect("username prompt");
-    lines.send("@a").await.expect("send username");
+    for (bad, complaint) in &[
+        ("@", "Twitter handle after the @"),
+        ("a@", "need a domain"),
+        ("alice", "email address"),
+        ("a b@example.com", "spaces"),
+        ("@\u{430}lice", "plain ASCII"),
+    ] {
+        lines.send(*bad).await.expect("sent bad name");
+        let reprompt = lines.next().await.expect("reprompt").expect("reprompt");
+        assert!(
+            reprompt.contains(complaint),
+            "expected '{}' for '{}', got '{}'",
+            complaint,
+            bad,
+            reprompt
+        );
+        let _prompt = lines.next().await.expect("username prompt");
+    }
+
+    // names are case-insensitive, but keep their registered case
+    lines.send("@A").await.expect("sent username");
     let _prompt = lines.next().await.expect("password prompt");
-    lines.send("aaaaaaaa").await.expect("send login");
-    let _prompt = lines.next().await.expect("logged in message");
-    lines.send("shutdown").await.expect("send shutdown comand");
+    lines.send("aaaaaaaa").await.expect("sent password");
+    assert_eq!(
+        lines.next().await.expect("login").expect("login"),
+        "Logged in as @a..."
+    );
+}
+
+#[tokio::test]
+async fn message_length_limit() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4005".to_string();
+    config.max_message_length = 10;
+
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let stream = tokio::net::TcpStream::connect(config.tcp_addr())
+        .await
+        .expect("connected");
+    let mut lines = Framed::new(stream, LinesCodec::new());
+
+    let _prompt = lines.next().await.expect("username prompt");
+    lines.send("@a").await.expect("sent username");
+    let _prompt = lines.next().await.expect("password prompt");
+    lines.send("aaaaaaaa").await.expect("sent password");
+    let _login = lines.next().await.expect("logged in message");
+    let _arrive = lines.next().await.expect("arrival");
+    loop {
+        let line = lines.next().await.expect("look").expect("look");
+        if line.contains("Here:") {
+            break;
+        }
+    }
+
+    // right at the limit
+    lines.send("0123456789").await.expect("sent message");
+    let said = lines.next().await.expect("reply").expect("reply");
+    assert!(said.contains("You say, '0123456789'"), "got '{}'", said);
+
+    // just over it
+    lines.send("0123456789X").await.expect("sent message");
+    let said = lines.next().await.expect("reply").expect("reply");
+    assert!(said.contains("Message too long."), "got '{}'", said);
+
+    // so far over it that the line itself is too long
+    lines.send("X".repeat(500)).await.expect("sent message");
+    let said = lines.next().await.expect("reply").expect("reply");
+    assert!(said.contains("Message too long."), "got '{}'", said);
+
+    // and we're still connected
+    lines.send("hi").await.expect("sent message");
+    let said = lines.next().await.expect("reply").expect("reply");
+    assert!(said.contains("You say, 'hi'"), "got '{}'", said);
+}
+
+#[tokio::test]
+async fn tls_login() {
+    use std::sync::Arc;
+    use tokio_rustls::{rustls, webpki, TlsConnector};
+
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).expect("cert");
+    let dir = std::env::temp_dir();
+    let cert_path = dir.join(format!("much-test-{}.crt", std::process::id()));
+    let key_path = dir.join(format!("much-test-{}.key", std::process::id()));
+    std::fs::write(&cert_path, cert.serialize_pem().expect("cert PEM")).expect("wrote cert");
+    std::fs::write(&key_path, cert.serialize_private_key_pem()).expect("wrote key");
+
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4003".to_string();
+    config.tls_cert = Some(cert_path.clone());
+    config.tls_key = Some(key_path.clone());
+    let tls = config.tls_acceptor().expect("loaded cert and key");
+    assert!(tls.is_some());
+
+    let state = simple_state().await;
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), tls));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut client = rustls::ClientConfig::new();
+    client
+        .root_store
+        .add(&rustls::Certificate(cert.serialize_der().expect("cert DER")))
+        .expect("trusted our cert");
+    let connector = TlsConnector::from(Arc::new(client));
+
+    let stream = tokio::net::TcpStream::connect(config.tcp_addr())
+        .await
+        .expect("connected");
+    let domain = webpki::DNSNameRef::try_from_ascii_str("localhost").expect("domain");
+    let stream = connector.connect(domain, stream).await.expect("handshake");
+    let mut lines = Framed::new(stream, LinesCodec::new());
+
+    let _ = lines.next().await.expect("username prompt");
+    lines.send("@a").await.expect("sent username");
+    let _ = lines.next().await.expect("password prompt");
+    lines.send("aaaaaaaa").await.expect("sent password");
+
+    assert_eq!(
+        lines.next().await.expect("login").expect("login"),
+        "Logged in as @a..."
+    );
+
+    let _ = std::fs::remove_file(cert_path);
+    let _ = std::fs::remove_file(key_path);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_cleaned_up_on_shutdown() {
+    let path = std::env::temp_dir().join(format!("much-test-{}.sock", std::process::id()));
+
+    let state = simple_state().await;
+    let server = tokio::spawn(uds_serve(state.clone(), path.clone()));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let stream = tokio::net::UnixStream::connect(&path)
+        .await
+        .expect("connected over Unix socket");
+    let mut lines = Framed::new(stream, LinesCodec::new());
+
+    let prompt = lines.next().await.expect("username prompt");
+    assert!(prompt.is_ok(), "expected a prompt, got {:?}", prompt);
+
+    state.lock().await.shutdown().await;
+    server
+        .await
+        .expect("server task")
+        .expect("server stopped cleanly");
+    assert!(!path.exists(), "socket file {} left behind", path.display());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn unix_socket_wont_replace_a_file() {
+    let path = std::env::temp_dir().join(format!("much-test-{}.notasock", std::process::id()));
+    std::fs::write(&path, "precious").expect("wrote file");
+
+    let state = simple_state().await;
+    let result = uds_serve(state, path.clone()).await;
+    let contents = std::fs::read_to_string(&path);
+    let _ = std::fs::remove_file(&path);
+
+    assert!(result.is_err(), "served over a regular file");
+    assert_eq!(contents.expect("file kept"), "precious");
+}
+
+#[tokio::test]
+async fn sessions_admin_only() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4006".to_string();
+    config.admins = vec!["@A".to_string()];
+
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let arrived = a.expect_line().await;
+    assert!(arrived.contains("@b arrived."), "got '{}'", arrived);
+
+    b.send("sessions").await;
+    let refused = b.expect_line().await;
+    assert!(refused.contains("Only administrators"), "got '{}'", refused);
+
+    a.send("sessions").await;
+    let mut listing = Vec::new();
+    for _ in 0..3 {
+        listing.push(a.expect_line().await);
+    }
+    assert!(listing[0].contains("Sessions (2):"), "got {:?}", listing);
+    assert!(listing[1].contains("#0 @a via 127.0.0.1:"), "got {:?}", listing);
+    assert!(listing[1].ends_with("in room 0, idle 0s"), "got {:?}", listing);
+    assert!(listing[2].contains("#1 @b via 127.0.0.1:"), "got {:?}", listing);
+}
+
+#[tokio::test]
+async fn admin_boots_another_user() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4007".to_string();
+    config.admins = vec!["@a".to_string()];
+
+    let state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    // only admins may boot
+    b.send("boot @a").await;
+    let refused = b.expect_line().await;
+    assert!(refused.contains("Only administrators"), "got '{}'", refused);
+
+    a.send("boot @B").await;
+
+    let notice = b.expect_line().await;
+    assert!(
+        notice.contains("disconnected by an administrator"),
+        "got '{}'",
+        notice
+    );
+    let logout = b.expect_line().await;
+    assert!(logout.contains("logged out"), "got '{}'", logout);
+    b.expect_disconnect().await;
+
+    let left = a.expect_line().await;
+    assert!(left.contains("@b logged off."), "got '{}'", left);
+    let booted = a.expect_line().await;
+    assert!(booted.contains("You booted @b."), "got '{}'", booted);
+
+    {
+        let state = state.lock().await;
+        assert_eq!(state.stats().connected, 1);
+        assert_eq!(state.online().count(), 1);
+    }
+
+    a.send("boot @b").await;
+    let offline = a.expect_line().await;
+    assert!(offline.contains("@b is not online."), "got '{}'", offline);
+}
+
+#[tokio::test]
+async fn banned_users_cant_log_in() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4008".to_string();
+    config.admins = vec!["@a".to_string()];
+
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    // banning someone online disconnects them
+    a.send("ban @b spamming the hall").await;
+    let notice = b.expect_line().await;
+    assert!(
+        notice.contains("You have been banned: spamming the hall"),
+        "got '{}'",
+        notice
+    );
+    let _logout = b.expect_line().await;
+    b.expect_disconnect().await;
+    let _left = a.expect_line().await;
+    let banned = a.expect_line().await;
+    assert!(banned.contains("You banned @b."), "got '{}'", banned);
+
+    // and they can't come back, not even as far as a password prompt
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let refused = b.expect_line().await;
+    assert_eq!(refused, "You are banned: spamming the hall");
+    b.expect_disconnect().await;
+
+    // until they're unbanned
+    a.send("unban @b").await;
+    let unbanned = a.expect_line().await;
+    assert!(unbanned.contains("You unbanned @b."), "got '{}'", unbanned);
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let arrived = a.expect_line().await;
+    assert!(arrived.contains("@b arrived."), "got '{}'", arrived);
+}
+
+#[tokio::test]
+async fn roomcast_without_holding_the_lock() {
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4009".to_string();
+
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    let recipients = state.lock().await.room_recipients(0);
+    assert_eq!(recipients.len(), 1);
+
+    // the fan-out doesn't need the lock, so it goes through even while
+    // someone else is holding it
+    let held = state.lock().await;
+    recipients.send(much::Message::Notice {
+        text: "delivered".to_string(),
+    });
+    let line = tokio::time::timeout(tokio::time::Duration::from_secs(1), a.expect_line())
+        .await
+        .expect("delivered while the lock was held");
+    assert!(line.contains("delivered"), "got '{}'", line);
+    drop(held);
+}
+
+#[tokio::test]
+async fn input_not_starved_by_output() {
+    // fewer than a message queue holds, so @a isn't dropped for being slow
+    const FLOOD: usize = 200;
+
+    let mut config = config_timeout(1);
+    config.addr = "127.0.0.1".to_string();
+    config.tcp_port = "4010".to_string();
+
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let just_a = state.lock().await.room_recipients(0);
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    // bury @a in messages, and don't read any of them yet
+    for i in 0..FLOOD {
+        just_a.send(Message::Notice {
+            text: format!("flood {}", i),
+        });
+    }
+
+    // @a can still get a word in edgewise
+    a.send("hello").await;
+    tokio::time::timeout(
+        tokio::time::Duration::from_secs(5),
+        b.expect_containing("@a says, 'hello'"),
+    )
+    .await
+    .expect("@a's command waited on their output");
+
+    // and nothing queued for them was lost along the way
+    for i in 0..FLOOD {
+        let line = a.expect_line().await;
+        assert!(line.contains(&format!("flood {}", i)), "expected flood {}, got '{}'", i, line);
+    }
+    let said = a.expect_line().await;
+    assert!(said.contains("You say, 'hello'"), "got '{}'", said);
+}
+
+#[tokio::test]
+async fn every_command_gets_a_reply() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4011".to_string(),
+        admins: vec!["@a".to_string()],
+        flood_burst: 100,
+        ..config_timeout(1)
+    };
+
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    // alone in the room, offline targets, no-op toggles: there's always a reply
+    let replies = [
+        ("look", "Here: @a"),
+        ("topic", "There is no topic."),
+        ("topic cats", "You set the topic to: cats"),
+        ("hello", "You say, 'hello'"),
+        ("shout hey", "You shout, 'hey'"),
+        ("tell @a me", "You tell @a, 'me'"),
+        ("tell @b hi", "@b is not online."),
+        ("tell @nobody hi", "There is no one named @nobody."),
+        ("tell @b", "You say, 'tell @b'"),
+        ("whisper @a me", "You whisper to @a, 'me'"),
+        ("whisper @b hi", "You don't see @b here."),
+        ("chan #hallway hi", "You aren't in #hallway."),
+        ("join #Hallway", "You join #hallway."),
+        ("join #hallway", "You are already in #hallway."),
+        ("chan #hallway hi", "[#hallway] You say, 'hi'"),
+        ("chan #hallway", "You say, 'chan #hallway'"),
+        ("leave #hallway", "You leave #hallway."),
+        ("leave #hallway", "You aren't in #hallway."),
+        ("locale", "Your locale is en."),
+        ("locale xx", "There is no locale xx."),
+        ("emote waves", "@a waves."),
+        ("emote looks at the sky!", "@a looks at the sky!"),
+        ("emote smiles at @A", "@a smiles at you."),
+        ("emote smiles at @b", "You don't see @b here."),
+        ("emote", "You say, 'emote'"),
+        ("who", "Online (1):"),
+        ("stats", "Up for"),
+        ("version", "much "),
+        ("color off", "Color is now off."),
+        ("color", "Color is now on."),
+        ("color purple", "You say, 'color purple'"),
+        ("find zz", "No one matches 'zz'."),
+        ("capacity", "This room has no capacity limit."),
+        ("capacity 5", "You set this room's capacity to 5."),
+        ("capacity none", "You removed this room's capacity limit."),
+        ("goto nowhere", "There is no room named nowhere."),
+        ("goto LOBBY", "You are already in Lobby."),
+        ("dig ???", "Room names need a letter or number."),
+        ("dig lobby", "There is already a room named Lobby."),
+        ("afk", "You are now away."),
+        ("afk still", "You are now away."),
+        ("look", "You are no longer away."),
+        ("profile", "No bio."),
+        ("set pronouns they/them", "You set your pronouns to: they/them"),
+        ("set bio likes\x1b[31m cats", "You set your bio to: likes[31m cats"),
+        ("profile @A", "@a (they/them)"),
+        ("examine @A", "Here for"),
+        ("examine @b", "You don't see them here."),
+        ("set bio", "You cleared your bio."),
+        ("set mood happy", "You say, 'set mood happy'"),
+        ("profile @nobody", "There is no one named @nobody."),
+        ("motd be kind", "You set the message of the day."),
+        ("motd", "You cleared the message of the day."),
+        ("sessions", "Sessions (1):"),
+        ("save", "Saving is disabled (there's no --db)."),
+        ("shutdown soon", "You say, 'shutdown soon'"),
+        ("LOOK", "Here: @a"),
+        ("sta", "Up for"),
+        ("lo", "Ambiguous command: locale, logout, look?"),
+        ("i think so", "You say, 'i think so'"),
+        ("say who", "You say, 'who'"),
+        ("say", "You say, 'say'"),
+        ("/who", "Online (1):"),
+        ("who all", "Online (1), by room:"),
+        ("/STA", "Up for"),
+        ("/sa hi", "You say, 'hi'"),
+        ("/frob", "Parse error: /frob is not a valid command."),
+        ("/tell @b", "not a valid command"),
+        ("/color purple", "not a valid command"),
+        ("/set mood happy", "not a valid command"),
+        ("boot @b", "@b is not online."),
+        ("boot @nobody", "There is no one named @nobody."),
+        ("ban @c spam", "You banned @c."),
+        ("unban @c", "You unbanned @c."),
+        ("unban @c", "@c isn't banned."),
+        ("logout", "You have logged out."),
+    ];
+
+    for (command, reply) in replies.iter() {
+        a.send(command).await;
+        let heard = tokio::time::timeout(
+            tokio::time::Duration::from_secs(1),
+            a.expect_containing(reply),
+        )
+        .await;
+        assert!(heard.is_ok(), "no reply to '{}'", command);
+    }
+
+    // only admins can stop the server, right away or otherwise
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("shutdown").await;
+    b.expect_containing("Only administrators can do that.").await;
+}
+
+#[tokio::test]
+async fn who_and_look_sorted_by_name() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4012".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut clients = Vec::new();
+    for (name, password) in &[("@c", "cccccccc"), ("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")] {
+        let mut client = TestClient::connect(config.tcp_addr()).await;
+        client.login(name, password).await;
+        clients.push(client);
+    }
+    let c = &mut clients[0];
+
+    c.send("who").await;
+    c.expect_containing("Online (3):").await;
+    for name in &["@a", "@b", "@c"] {
+        let line = c.expect_line().await;
+        assert!(line.contains(&format!("  {}", name)), "expected {}, got '{}'", name, line);
+    }
+
+    c.send("look").await;
+    let here = c.expect_containing("Here:").await;
+    assert!(here.contains("Here: @a, @b, @c"), "got '{}'", here);
+}
+
+#[tokio::test]
+async fn find_accounts() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4013".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let accounts: Vec<_> = ["@a", "@b", "@Bobby", "@bob", "bob@example.com"]
+        .iter()
+        .map(|name| (*name, "password"))
+        .collect();
+    let _state = serve(&config, &accounts).await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "password").await;
+    b.send("find bob").await;
+    b.expect_containing("Only administrators can do that.").await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "password").await;
+    a.send("find BOB").await;
+    a.expect_containing("Matching 'BOB' (3):").await;
+    for name in &["@bob", "@Bobby", "bob@example.com"] {
+        let line = a.expect_line().await;
+        assert!(line.contains(&format!("  {}", name)), "expected {}, got '{}'", name, line);
+    }
+}
+
+#[tokio::test]
+async fn full_rooms_turn_people_away() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4014".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("capacity 1").await;
+    a.expect_containing("You set this room's capacity to 1.").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Logged in").await;
+    b.expect_containing("That room is full.").await;
+    b.expect_disconnect().await;
+
+    a.send("capacity").await;
+    a.expect_containing("This room holds at most 1 people (1 here).").await;
+    assert_eq!(state.lock().await.stats().occupied, 1);
+    a.send("capacity none").await;
+    a.expect_containing("You removed this room's capacity limit.").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+}
+
+#[tokio::test]
+async fn dig_and_goto_rooms() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4015".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    b.send("dig Hallway Track").await;
+    b.expect_containing("Only administrators can do that.").await;
+
+    a.send("dig Main Stage").await;
+    a.expect_containing("You dig a new room, Main Stage.").await;
+    a.send("dig main-stage").await;
+    a.expect_containing("There is already a room named Main Stage.").await;
+
+    a.send("goto MAIN STAGE").await;
+    a.expect_containing("You go to Main Stage.").await;
+    b.expect_containing("@a left for Main Stage.").await;
+
+    a.send("look").await;
+    a.expect_containing("Main Stage").await;
+    a.expect_containing("Here: @a").await;
+
+    b.send("goto main stage").await;
+    b.expect_containing("You go to Main Stage.").await;
+    a.expect_containing("@b arrived from Lobby.").await;
+}
+
+#[tokio::test]
+async fn scrollback_replayed_on_arrival() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4016".to_string(),
+        scrollback: 2,
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    for text in &["one", "two", "three"] {
+        a.send(text).await;
+        a.expect_containing(&format!("You say, '{}'", text)).await;
+    }
+
+    // the last two, from @b's point of view, before @b's arrival
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Logged in").await;
+    let _greeting = b.expect_line().await;
+    assert!(b.expect_line().await.contains("@a says, 'two'"));
+    assert!(b.expect_line().await.contains("@a says, 'three'"));
+    assert_eq!(b.expect_line().await, "");
+
+    // and from @a's, when they come back
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+    a.expect_disconnect().await;
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("aaaaaaaa").await;
+    a.expect_containing("Logged in").await;
+    assert!(a.expect_line().await.starts_with("Last login: "));
+    let _greeting = a.expect_line().await;
+    assert!(a.expect_line().await.contains("You say, 'two'"));
+    assert!(a.expect_line().await.contains("You say, 'three'"));
+}
+
+#[tokio::test]
+async fn departures_say_why() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4017".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("logout").await;
+    a.expect_containing("@b logged off.").await;
+
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+    drop(c);
+    a.expect_containing("@c disconnected.").await;
+}
+
+#[tokio::test]
+async fn typing_never_reaches_terminals() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4018".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    b.expect_containing("@a arrived.").await;
+
+    a.send("typing").await;
+    a.send("typing").await;
+    a.send("hi").await;
+    a.expect_containing("You say, 'hi'").await;
+
+    let line = b.expect_line().await;
+    assert!(line.contains("@a says, 'hi'"), "got '{}'", line);
+}
+
+#[tokio::test]
+async fn embedded_server_shuts_down() {
+    let state = simple_state().await;
+    let server = ServerBuilder::new()
+        .tcp_addr("127.0.0.1:4019")
+        .with_state(state)
+        .spawn();
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut client = TestClient::connect("127.0.0.1:4019").await;
+    client.login("@a", "aaaaaaaa").await;
+
+    tokio::time::timeout(tokio::time::Duration::from_secs(5), server.shutdown())
+        .await
+        .expect("server stopped");
+    client.expect_containing("shutting down").await;
+    client.expect_containing("You have logged out.").await;
+    client.expect_disconnect().await;
+}
+
+#[tokio::test]
+async fn slow_readers_get_disconnected() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4021".to_string(),
+        flood_rate: 1_000_000.0,
+        flood_burst: 1_000_000,
+        max_message_length: 4096,
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    // @b never reads, so the socket buffers fill, and then their queue
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    let metrics = state.lock().await.metrics();
+    let text = "x".repeat(4000);
+    let mut said = 0;
+    while !metrics.render().contains("much_slow_peers_total 1") {
+        assert!(said < 20_000, "@b never disconnected");
+        a.send(&format!("say {}", text)).await;
+        a.expect_containing(&text).await;
+        said += 1;
+    }
+
+    // once @b catches up on what was queued, they're gone
+    let mut b = b.into_inner();
+    while let Some(Ok(_)) = b.next().await {}
+    a.expect_containing("@b disconnected.").await;
+}
+
+#[tokio::test]
+async fn delete_own_account() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4022".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    b.send("delete @a bbbbbbbb").await;
+    b.expect_containing("To delete your account, type: delete @b <password>").await;
+    b.send("delete @b aaaaaaaa").await;
+    b.expect_containing("Password incorrect.").await;
+
+    b.send("delete @B bbbbbbbb").await;
+    b.expect_containing("Your account has been deleted.").await;
+    b.expect_containing("You have logged out.").await;
+    b.expect_disconnect().await;
+    a.expect_containing("@b logged off.").await;
+
+    a.send("who").await;
+    a.expect_containing("Online (1):").await;
+    assert!(state.person_by_name("@b").is_none());
+
+    // the name is free again
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    b.expect_containing("You must be new here!").await;
+}
+
+#[tokio::test]
+async fn configured_lobby() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4023".to_string(),
+        lobby_name: "Main Hall".to_string(),
+        lobby_description: Some("Mind the step.".to_string()),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    a.send("look").await;
+    a.expect_containing("Main Hall").await;
+    a.expect_containing("Mind the step.").await;
+    a.expect_containing("Here: @a").await;
+
+    a.send("goto main-hall").await;
+    a.expect_containing("You are already in Main Hall.").await;
+}
+
+#[tokio::test]
+async fn full_server_turns_connections_away() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4024".to_string(),
+        max_connections: Some(1),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    // abandoning a login gives the slot back
+    let quitter = TestClient::connect(config.tcp_addr()).await;
+    drop(quitter);
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    let mut refused = TestClient::connect(config.tcp_addr()).await;
+    refused.expect_containing("Server is full, please try again later.").await;
+    refused.expect_disconnect().await;
+
+    // so does logging out
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+    a.expect_disconnect().await;
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+}
+
+#[tokio::test]
+async fn open_registration_welcomes_new_names() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4025".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut known = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = known.expect_line().await;
+    known.send("@a").await;
+    let _prompt = known.expect_line().await;
+    known.send("wrong password").await;
+    known.expect_containing("Password incorrect.").await;
+
+    let mut unknown = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = unknown.expect_line().await;
+    unknown.send("@newcomer").await;
+    unknown.expect_containing("You must be new here!").await;
+}
+
+#[tokio::test]
+async fn closed_registration_hides_accounts() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4026".to_string(),
+        open_registration: false,
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    // unknown names and wrong passwords look the same, three strikes and out
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = c.expect_line().await;
+    c.send("@nobody").await;
+    c.expect_containing("Password:").await;
+    c.send("whatever1").await;
+    c.expect_containing("Login failed.").await;
+    c.expect_containing("What is your email address").await;
+    c.send("@a").await;
+    c.expect_containing("Password:").await;
+    c.send("wrong password").await;
+    c.expect_containing("Login failed.").await;
+    c.expect_containing("What is your email address").await;
+    c.send("@a").await;
+    c.expect_containing("Password:").await;
+    c.send("still wrong").await;
+    c.expect_containing("Login failed.").await;
+    c.expect_disconnect().await;
 
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("invite @c short").await;
+    a.expect_containing("at least 8 characters").await;
+    a.send("invite @A cccccccc").await;
+    a.expect_containing("There is already someone named @a.").await;
+    a.send("invite @c cccccccc").await;
+    a.expect_containing("You invited @c.").await;
+
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+    a.expect_containing("@c arrived.").await;
+}
+
+#[tokio::test]
+async fn motd_follows_login() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4027".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("motd Be kind to each other.").await;
+    a.expect_containing("You set the message of the day.").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Logged in").await;
+    let _greeting = b.expect_line().await;
+    let motd = b.expect_line().await;
+    assert!(motd.contains("Be kind to each other."), "got '{}'", motd);
+
+    a.send("motd").await;
+    a.expect_containing("You cleared the message of the day.").await;
+
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = c.expect_line().await;
+    c.send("@c").await;
+    let _prompt = c.expect_line().await;
+    c.send("cccccccc").await;
+    c.expect_containing("Logged in").await;
+    let _greeting = c.expect_line().await;
+    let next = c.expect_line().await;
+    assert!(!next.contains("Be kind"), "got '{}'", next);
+}
+
+#[tokio::test]
+async fn look_follows_arrival() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4028".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Logged in").await;
+    let _greeting = b.expect_line().await;
+    assert_eq!(b.expect_line().await, "");
+    assert!(b.expect_line().await.contains("Lobby"));
+    assert!(b.expect_line().await.contains("Everyone arrives here."));
+    assert!(b.expect_line().await.contains("There is no topic."));
+    assert!(b.expect_line().await.contains("Here: @a, @b"));
+}
+
+#[tokio::test]
+async fn reconnect_to_last_room() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4029".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("dig Den").await;
+    a.expect_containing("Den").await;
+    a.send("goto den").await;
+    a.expect_containing("You go to Den.").await;
+    a.send("capacity 1").await;
+    a.expect_containing("You set this room's capacity to 1.").await;
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("look").await;
+    a.expect_containing("Den").await;
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+
+    // someone took the last spot while they were gone
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("goto den").await;
+    b.expect_containing("You go to Den.").await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("look").await;
+    a.expect_containing("Lobby").await;
+}
+
+#[tokio::test]
+async fn records_follow_moves() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4030".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("dig Den").await;
+    a.expect_containing("Den").await;
+    a.send("goto den").await;
+    a.expect_containing("You go to Den.").await;
+
+    let den = state.lock().await.room_by_name("den").expect("dug");
+    let record = state.lock().await.person_by_name("@a").expect("account");
+    assert_eq!(record.loc, den);
+
+    a.send("capacity 1").await;
+    a.expect_containing("You set this room's capacity to 1.").await;
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+    let record = state.lock().await.person_by_name("@a").expect("account");
+    assert_eq!(record.loc, den);
+
+    // bumped to the lobby on the way back in, and the record says so
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("goto den").await;
+    b.expect_containing("You go to Den.").await;
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let record = state.lock().await.person_by_name("@a").expect("account");
+    assert_eq!(Some(record.loc), state.lock().await.room_by_name("lobby"));
+}
+
+#[tokio::test]
+async fn control_characters_stripped() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4031".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+    a.expect_containing("@b arrived.").await;
+
+    // clear the screen, home the cursor, and back over the speaker's name
+    a.send("hi \x1b[2J\x1b[H\u{9b}1A th\tere\x08\x08\x08\x08\x08\x08\x7f ✓").await;
+    let heard = b.expect_containing("@a says").await;
+    assert_eq!(heard, "@a says, 'hi [2J[H1A th\tere ✓'");
+}
+
+#[tokio::test]
+async fn shutdown_countdown() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4032".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("shutdown 2").await;
+    a.expect_containing("Server restarting in 2 seconds...").await;
+    a.send("shutdown 5").await;
+    a.expect_containing("A shutdown is already scheduled.").await;
+
+    // latecomers hear about it too
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Logged in").await;
+    let _greeting = b.expect_line().await;
+    let warning = b.expect_line().await;
+    assert!(warning.contains("Server restarting in"), "got '{}'", warning);
+    b.send("shutdown 1").await;
+    b.expect_containing("Only administrators can do that.").await;
+    b.send("shutdown").await;
+    b.expect_containing("Only administrators can do that.").await;
+
+    a.expect_containing("The server is shutting down.").await;
+    b.expect_containing("The server is shutting down.").await;
+    b.expect_containing("You have logged out.").await;
+    b.expect_disconnect().await;
+}
+
+#[tokio::test]
+async fn recall_recent_input() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4033".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("recall").await;
+    a.expect_containing("You haven't typed anything yet.").await;
+
+    for text in &["one", "two", "look", "delete @a secret", "three"] {
+        a.send(text).await;
+    }
+    a.expect_containing("You say, 'three'").await;
+
+    // neither `recall` nor passwords are kept
+    a.send("recall 3").await;
+    a.expect_containing("You typed:").await;
+    assert!(a.expect_line().await.contains("1. two"));
+    assert!(a.expect_line().await.contains("2. look"));
+    assert!(a.expect_line().await.contains("3. three"));
+    a.send("recall 1").await;
+    a.expect_containing("You typed:").await;
+    assert!(a.expect_line().await.contains("1. three"));
+}
+
+#[tokio::test]
+async fn whispers_stay_in_the_room() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4034".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+    a.expect_containing("@c arrived.").await;
+    b.expect_containing("@c arrived.").await;
+
+    a.send("whisper @B psst").await;
+    a.expect_containing("You whisper to @b, 'psst'").await;
+    b.expect_containing("@a whispers to you, 'psst'").await;
+    let overheard = c.expect_line().await;
+    assert!(overheard.contains("@a whispers something to @b."), "got '{}'", overheard);
+    assert!(!overheard.contains("psst"));
+
+    a.send("whisper @nobody hi").await;
+    a.expect_containing("You don't see @nobody here.").await;
+}
+
+#[tokio::test]
+async fn registration_gives_up_on_mismatched_passwords() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4035".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = c.expect_line().await;
+    c.send("@newcomer").await;
+    for _ in 0..5 {
+        c.expect_containing("Please enter a password").await;
+        c.send("password one").await;
+        c.expect_containing("Please re-enter your password").await;
+        c.send("password two").await;
+        c.expect_containing("Passwords don't match.").await;
+    }
+    c.expect_disconnect().await;
+    assert!(state.person_by_name("@newcomer").is_none());
+}
+
+#[tokio::test]
+async fn abrupt_disconnects_leave_the_room() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4036".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+
+    // reset the connection rather than closing it politely
+    let b = b.into_inner();
+    b.get_ref()
+        .set_linger(Some(std::time::Duration::from_secs(0)))
+        .expect("linger");
+    drop(b);
+
+    a.send("anyone there?").await;
+    a.expect_containing("@b disconnected.").await;
+    a.send("look").await;
+    let here = a.expect_containing("Here:").await;
+    assert!(!here.contains("@b"), "got '{}'", here);
+}
+
+#[tokio::test]
+async fn greeting_after_login() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4037".to_string(),
+        greeting: Some("Hello!\nType 'who' to see who's here.".to_string()),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("aaaaaaaa").await;
+    a.expect_containing("Logged in").await;
+    assert_eq!(a.expect_line().await, "Hello!");
+    assert_eq!(a.expect_line().await, "Type 'who' to see who's here.");
+    assert_eq!(a.expect_line().await, "");
+}
+
+#[tokio::test]
+async fn arguments_keep_internal_spacing() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4040".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+    a.expect_containing("@b arrived.").await;
+
+    a.send("   say   hello   world   ").await;
+    let heard = b.expect_containing("@a says").await;
+    assert_eq!(heard, "@a says, 'hello   world'");
+
+    a.send("tell   @b   hi   there  ").await;
+    let heard = b.expect_containing("@a tells you").await;
+    assert!(heard.ends_with("'hi   there'"), "got '{}'", heard);
+}
+
+#[tokio::test]
+async fn quoted_arguments() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4041".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+
+    a.send("tell \"@b\" \"hi\"  there").await;
+    let heard = b.expect_containing("@a tells you").await;
+    assert!(heard.ends_with("'\"hi\"  there'"), "got '{}'", heard);
+
+    a.send("/tell \"@b hi").await;
+    a.expect_containing("missing a closing quote").await;
+
+    a.send("tell \"@b\\\" the builder\" hi").await;
+    a.expect_containing("There is no one named @b\" the builder.").await;
+}
+
+#[tokio::test]
+async fn one_password_attempt() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4043".to_string(),
+        max_password_attempts: 1,
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("wrong password").await;
+    a.expect_disconnect().await;
+}
+
+#[tokio::test]
+async fn emotes_at_someone() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4044".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+    for client in [&mut b, &mut c].iter_mut() {
+        client.send("color off").await;
+        client.expect_containing("Color is now off.").await;
+    }
+    a.expect_containing("@c arrived.").await;
+
+    a.send("emote smiles at @B").await;
+    assert_eq!(b.expect_containing("smiles").await, "@a smiles at you.");
+    assert_eq!(c.expect_containing("smiles").await, "@a smiles at @b.");
+    a.expect_containing("@a smiles at @b.").await;
+}
+
+#[tokio::test]
+async fn locale_changes_terminal_text() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4045".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+    b.send("locale ES").await;
+    b.expect_containing("Your locale is now es.").await;
+    a.expect_containing("@b arrived.").await;
+
+    a.send("hi").await;
+    assert_eq!(b.expect_containing("hi").await, "@a dice, 'hi'");
+    // no translation yet, so it's in English
+    a.send("emote waves").await;
+    assert_eq!(b.expect_containing("waves").await, "@a waves.");
+
+    // it sticks across sessions
+    b.send("logout").await;
+    b.expect_containing("Has cerrado la sesión.").await;
+    a.expect_containing("@b logged off.").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = b.expect_line().await;
+    b.send("@b").await;
+    let _prompt = b.expect_line().await;
+    b.send("bbbbbbbb").await;
+    b.expect_containing("Aquí: @a, @b").await;
+}
+
+#[tokio::test]
+async fn channels_reach_only_members() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4046".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("dig Stage").await;
+    a.expect_containing("You dig a new room").await;
+    a.send("goto stage").await;
+    a.expect_containing("Here: @a").await;
+    a.send("join #hallway").await;
+    a.expect_containing("You join #hallway.").await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+    b.send("join #hallway").await;
+    b.expect_containing("You join #hallway.").await;
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+
+    // across rooms, but only to members
+    a.send("chan #hallway hi").await;
+    assert_eq!(
+        b.expect_containing("#hallway").await,
+        "[#hallway] @a says, 'hi'"
+    );
+    c.send("stats").await;
+    let line = c.expect_line().await;
+    assert!(line.contains("Up for"), "got '{}'", line);
+
+    b.send("leave #hallway").await;
+    b.expect_containing("You leave #hallway.").await;
+    a.send("chan #hallway anyone?").await;
+    a.expect_containing("anyone?").await;
+    b.send("stats").await;
+    let line = b.expect_line().await;
+    assert!(line.contains("Up for"), "got '{}'", line);
+}
+
+#[tokio::test]
+async fn who_shows_transport_to_admins() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4047".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("color off").await;
+    a.expect_containing("Color is now off.").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    b.send("color off").await;
+    b.expect_containing("Color is now off.").await;
+
+    b.send("who").await;
+    b.expect_containing("Online (2):").await;
+    assert_eq!(b.expect_line().await, "  @a");
+    assert_eq!(b.expect_line().await, "  @b");
+
+    a.send("who").await;
+    a.expect_containing("Online (2):").await;
+    assert_eq!(a.expect_line().await, "  @a via TCP");
+    assert_eq!(a.expect_line().await, "  @b via TCP");
+}
+
+#[tokio::test]
+async fn one_registration_per_name() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4048".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[]).await;
+
+    let mut clients = Vec::new();
+    for _ in 0..16 {
+        let mut client = TestClient::connect(config.tcp_addr()).await;
+        let _prompt = client.expect_line().await;
+        clients.push(client);
+    }
+
+    // everyone asks for the same name at once
+    let replies = futures::future::join_all(clients.iter_mut().map(|client| async move {
+        client.send("@new").await;
+        client.expect_line().await
+    }))
+    .await;
+    let registering: Vec<usize> = (0..replies.len())
+        .filter(|&i| replies[i] == "You must be new here!")
+        .collect();
+    assert_eq!(registering.len(), 1, "got {:?}", replies);
+    for (i, reply) in replies.iter().enumerate() {
+        if i != registering[0] {
+            assert_eq!(reply, "Someone else is registering that name right now.");
+        }
+    }
+
+    let winner = registering[0];
+    let _prompt = clients[winner].expect_line().await;
+    clients[winner].send("password").await;
+    let _prompt = clients[winner].expect_line().await;
+    clients[winner].send("password").await;
+    clients[winner].expect_containing("Here:").await;
+
+    // once it's registered, the name is just taken
+    let loser = (winner + 1) % clients.len();
+    let _prompt = clients[loser].expect_line().await;
+    clients[loser].send("@new").await;
+    assert_eq!(clients[loser].expect_line().await, "Password: ");
+    assert_eq!(state.lock().await.stats().accounts, 1);
+}
+
+#[tokio::test]
+async fn sessions_end_with_matchable_errors() {
+    let config = Config {
+        max_password_attempts: 1,
+        ..config_timeout(1)
+    };
+    let state = much::init(&config);
+    let _ = state.lock().await.new_person("@a", "aaaaaaaa");
+    let mut listener = tokio::net::TcpListener::bind("127.0.0.1:4049")
+        .await
+        .expect("bound");
+
+    let mut client = TestClient::connect("127.0.0.1:4049").await;
+    let (stream, _) = listener.accept().await.expect("accepted");
+    let session = tokio::spawn(much::process(state.clone(), stream));
+    let _prompt = client.expect_line().await;
+    client.send("@a").await;
+    let _prompt = client.expect_line().await;
+    client.send("wrong password").await;
+    let result = session.await.expect("session ran");
+    match result {
+        Err(SessionError::TooManyPasswordAttempts(_)) => (),
+        result => panic!("expected too many attempts, got {:?}", result),
+    }
+
+    let mut client = TestClient::connect("127.0.0.1:4049").await;
+    let (stream, _) = listener.accept().await.expect("accepted");
+    let session = tokio::spawn(much::process(state.clone(), stream));
+    let _prompt = client.expect_line().await;
+    drop(client);
+    match session.await.expect("session ran") {
+        Err(e @ SessionError::LoginAborted(_)) => assert!(e.is_routine()),
+        result => panic!("expected an aborted login, got {:?}", result),
+    }
+}
+
+#[tokio::test]
+async fn last_login_shown_on_return() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4050".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("aaaaaaaa").await;
+    a.expect_containing("Logged in as @a").await;
+    let line = a.expect_line().await;
+    assert!(!line.starts_with("Last login"), "got '{}'", line);
+    a.send("logout").await;
+    a.expect_containing("You have logged out.").await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("aaaaaaaa").await;
+    a.expect_containing("Logged in as @a").await;
+    let line = a.expect_line().await;
+    assert!(line.starts_with("Last login: "), "got '{}'", line);
+    assert!(line.ends_with(" from 127.0.0.1."), "got '{}'", line);
+}
+
+#[tokio::test]
+async fn sessions_show_hostnames() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4051".to_string(),
+        admins: vec!["@a".to_string()],
+        resolve_hostnames: true,
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    // the lookup on connect may still be running, but this one waits
+    let host = state
+        .hostname("127.0.0.1".parse().expect("an address"))
+        .await
+        .expect("a hostname for 127.0.0.1");
+    assert!(host.contains("localhost"), "got '{}'", host);
+
+    a.send("sessions").await;
+    a.expect_containing("Sessions (1):").await;
+    let line = a.expect_line().await;
+    assert!(
+        line.contains(&format!("#0 @a via {} (127.0.0.1:", host)),
+        "got '{}'",
+        line
+    );
+}
+
+#[tokio::test]
+async fn require_slash_for_commands() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4052".to_string(),
+        require_slash: true,
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("/color off").await;
+    a.expect_containing("Color is now off.").await;
+
+    a.send("who").await;
+    assert_eq!(a.expect_line().await, "You say, 'who'");
+    a.send("/who").await;
+    assert_eq!(a.expect_line().await, "Online (1):");
+    assert_eq!(a.expect_line().await, "  @a");
+    a.send("/say /who").await;
+    assert_eq!(a.expect_line().await, "You say, '/who'");
+}
+
+#[tokio::test]
+async fn idle_sessions_warned_then_disconnected() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4053".to_string(),
+        idle_timeout: Some(std::time::Duration::from_secs(2)),
+        idle_warning: std::time::Duration::from_secs(1),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("color off").await;
+    a.expect_containing("Color is now off.").await;
+    assert_eq!(
+        a.expect_line().await,
+        "You will be disconnected in 1s due to inactivity."
+    );
+
+    // typing anything winds the clock back...
+    a.send("look").await;
+    a.expect_containing("Here: @a").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+    a.expect_containing("You will be disconnected in 1s").await;
+
+    // ...but hearing things doesn't
+    b.send("anyone there?").await;
+    a.expect_containing("@b says, 'anyone there?'").await;
+    a.expect_containing("You have been disconnected for inactivity.").await;
+    a.expect_disconnect().await;
+    b.expect_containing("@a disconnected.").await;
+}
+
+#[tokio::test]
+async fn quit_and_exit_log_out() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4054".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    for alias in &["quit", "EXIT", "logout"] {
+        let mut b = TestClient::connect(config.tcp_addr()).await;
+        b.login("@b", "bbbbbbbb").await;
+        a.expect_containing("@b arrived.").await;
+
+        // only on their own, so this is just chat
+        b.send(&format!("{} smoking", alias)).await;
+        b.expect_containing(&format!("You say, '{} smoking'", alias)).await;
+
+        b.send(alias).await;
+        b.expect_containing("You have logged out.").await;
+        b.expect_disconnect().await;
+        a.expect_containing("@b logged off.").await;
+        assert_eq!(state.lock().await.stats().connected, 1, "after '{}'", alias);
+    }
+}
+
+#[tokio::test]
+async fn names_ignore_case() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4055".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, &[]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@Carol").await;
+    a.expect_containing("You must be new here!").await;
+    let _prompt = a.expect_line().await;
+    a.send("password").await;
+    let _prompt = a.expect_line().await;
+    a.send("password").await;
+    a.expect_containing("Here:").await;
+
+    // another case is the same account, not a new one
+    for name in &["@CAROL", "@carol"] {
+        let mut b = TestClient::connect(config.tcp_addr()).await;
+        let _prompt = b.expect_line().await;
+        b.send(name).await;
+        assert_eq!(b.expect_line().await, "Password: ");
+        b.send("password").await;
+        assert_eq!(b.expect_line().await, "Logged in as @Carol...");
+    }
+
+    let state = state.lock().await;
+    assert_eq!(state.stats().accounts, 1);
+    let carol = state.person_by_name("@cArOl").expect("found by any case");
+    assert_eq!(carol.name, "@Carol");
+}
+
+#[test]
+fn run_fails_when_a_port_is_taken() {
+    let _taken = std::net::TcpListener::bind("127.0.0.1:4057").expect("bound");
+
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4057".to_string(),
+        http_port: "4199".to_string(),
+        ..config_timeout(10)
+    };
+    let state = much::init(&config);
+    let started = std::time::Instant::now();
+    let err = much::run(&config, state).expect_err("port in use");
+    assert!(err.to_string().starts_with("TCP server failed"), "got '{}'", err);
+    assert!(started.elapsed() < std::time::Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn who_all_groups_by_room() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4058".to_string(),
+        admins: vec!["@a".to_string()],
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+    {
+        let mut state = state.lock().await;
+        let _ = state.new_room("Attic").expect("new room");
+        let _ = state.new_room("Cellar").expect("new room");
+    }
+
+    let mut clients = Vec::new();
+    for (name, password) in &[("@a", "aaaaaaaa"), ("@c", "cccccccc"), ("@b", "bbbbbbbb")] {
+        let mut client = TestClient::connect(config.tcp_addr()).await;
+        client.login(name, password).await;
+        client.send("color off").await;
+        client.expect_containing("Color is now off.").await;
+        clients.push(client);
+    }
+    clients[2].send("goto attic").await;
+    clients[2].expect_containing("Here: @b").await;
+
+    // admins see every (occupied) room, sorted, and how people connect
+    let a = &mut clients[0];
+    a.send("who all").await;
+    a.expect_containing("Online (3), by room:").await;
+    for expected in &["  Attic (1):", "    @b via TCP", "  Lobby (2):", "    @a via TCP", "    @c via TCP"] {
+        assert_eq!(a.expect_line().await, *expected);
+    }
+
+    // everyone else sees just their own room
+    let c = &mut clients[1];
+    c.send("who all").await;
+    c.expect_containing("Online (2), by room:").await;
+    for expected in &["  Lobby (2):", "    @a", "    @c"] {
+        assert_eq!(c.expect_line().await, *expected);
+    }
+}
+
+#[tokio::test]
+async fn save_and_restore() {
+    let db = std::env::temp_dir().join(format!("much-test-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4059".to_string(),
+        admins: vec!["@a".to_string()],
+        db: Some(db.clone()),
+        ..config_timeout(1)
+    };
+
+    let state = much::restore(&config).expect("fresh state");
+    {
+        let mut state = state.lock().await;
+        let _ = state.new_person("@a", "aaaaaaaa");
+        let _ = state.new_person("@b", "bbbbbbbb");
+    }
+    tokio::spawn(tcp_serve(state.clone(), config.tcp_addr(), None));
+    tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    a.send("dig Main Stage").await;
+    a.expect_containing("You dig a new room, Main Stage.").await;
+    a.send("goto main stage").await;
+    a.expect_containing("You go to Main Stage.").await;
+    a.send("motd be kind").await;
+    a.expect_containing("You set the message of the day.").await;
+    a.send("save").await;
+    a.expect_containing("Saved 2 accounts.").await;
+
+    let restored = much::restore(&config).expect("restored state");
+    let _ = std::fs::remove_file(&db);
+
+    let a = restored.person_by_name("@A").expect("@a restored");
+    assert!(restored.person_by_name("@b").is_some());
+    let state = restored.lock().await;
+    let stage = state.room_by_name("main stage").expect("room restored");
+    assert_eq!(a.loc, stage);
+    assert_eq!(state.motd().as_deref(), Some("be kind"));
+}
+
+#[tokio::test]
+async fn autosave_recovers_registrations() {
+    let db = std::env::temp_dir().join(format!("much-test-autosave-{}.db", std::process::id()));
+    let _ = std::fs::remove_file(&db);
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4060".to_string(),
+        db: Some(db.clone()),
+        ..config_timeout(1)
+    };
+
+    let server = ServerBuilder::new()
+        .tcp_addr(config.tcp_addr())
+        .autosave(std::time::Duration::from_secs(1))
+        .with_state(much::restore(&config).expect("fresh state"))
+        .spawn();
     tokio::time::delay_for(tokio::time::Duration::from_millis(30)).await;
 
-    let done = lines.next().await;
+    let mut client = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = client.expect_line().await;
+    client.send("@new").await;
+    client.expect_containing("You must be new here!").await;
+    let _prompt = client.expect_line().await;
+    client.send("password").await;
+    let _prompt = client.expect_line().await;
+    client.send("password").await;
+    client.expect_containing("Here:").await;
+
+    // a crash now loses nothing: the registration was saved on the interval
+    tokio::time::delay_for(tokio::time::Duration::from_millis(1500)).await;
+    let crashed = much::restore(&config).expect("restored state");
+    assert!(crashed.person_by_name("@new").is_some());
+
+    // ...and whatever changed since is saved when we shut down
+    client.send("set bio new here").await;
+    client.expect_containing("You set your bio to: new here").await;
+    server.shutdown().await;
+
+    let restarted = much::restore(&config).expect("restored state");
+    let _ = std::fs::remove_file(&db);
+    let new = restarted.person_by_name("@new").expect("@new restored");
+    assert_eq!(new.bio.as_deref(), Some("new here"));
+}
+
+#[tokio::test]
+async fn long_listings_are_paged() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4061".to_string(),
+        admins: vec!["@a".to_string()],
+        public_find: true,
+        page_size: 2,
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
 
-    match done {
-        Some(Ok(line)) => assert_eq!(line, ""),
-        Some(Err(_e)) => return (),
-        None => return (),
+    c.send("who").await;
+    c.expect_containing("Online (3):").await;
+    assert_eq!(c.expect_line().await, "  @a");
+    assert_eq!(c.expect_line().await, "  @b");
+    assert!(c.expect_line().await.contains("(use 'who 2' for more)"));
+    c.send("who 2").await;
+    c.expect_containing("Online (3):").await;
+    assert!(c.expect_line().await.starts_with("  @c"));
+    // past the end is the last page
+    c.send("who 9").await;
+    c.expect_containing("Online (3):").await;
+    assert!(c.expect_line().await.starts_with("  @c"));
+
+    c.send("find @ 2").await;
+    c.expect_containing("Matching '@' (3):").await;
+    assert!(c.expect_line().await.starts_with("  @c"));
+    c.send("find @").await;
+    c.expect_containing("Matching '@' (3):").await;
+    assert_eq!(c.expect_line().await, "  @a");
+    assert_eq!(c.expect_line().await, "  @b");
+    assert!(c.expect_line().await.contains("(use 'find @ 2' for more)"));
+
+    a.send("sessions").await;
+    a.expect_containing("Sessions (3):").await;
+    a.expect_containing("(use 'sessions 2' for more)").await;
+    a.send("sessions 2").await;
+    a.expect_containing("Sessions (3):").await;
+    assert!(a.expect_line().await.contains("@c"));
+}
+
+#[tokio::test]
+async fn own_arrival_is_silent_on_terminals() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4062".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    let _prompt = a.expect_line().await;
+    a.send("@a").await;
+    let _prompt = a.expect_line().await;
+    a.send("aaaaaaaa").await;
+    a.expect_containing("Logged in").await;
+    loop {
+        let line = a.expect_line().await;
+        assert!(!line.contains("arrived"), "heard our own arrival: '{}'", line);
+        if line.contains("Here:") {
+            break;
+        }
     }
 
-    let done = lines.next().await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+}
 
-    match done {
-        Some(Ok(line)) => panic!("expected empty line, got '{}'", line),
-        Some(Err(_e)) => return (),
-        None => return (),
+#[tokio::test]
+async fn chat_isnt_taken_for_abbreviations() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4063".to_string(),
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+
+    a.send("to be honest").await;
+    a.expect_containing("You say, 'to be honest'").await;
+    b.expect_containing("@a says, 'to be honest'").await;
+    a.send("lo and behold").await;
+    a.expect_containing("You say, 'lo and behold'").await;
+    a.send("go team").await;
+    a.expect_containing("You say, 'go team'").await;
+
+    a.send("topic").await;
+    a.expect_containing("There is no topic.").await;
+
+    // a slash still means a command, abbreviated or not
+    a.send("/to be honest").await;
+    a.expect_containing("You set the topic to: be honest").await;
+}
+
+#[tokio::test]
+async fn flooding_is_limited_but_notices_arent() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4064".to_string(),
+        flood_rate: 0.01,
+        flood_burst: 3,
+        ..config_timeout(1)
+    };
+    let _state = serve(&config, &[("@a", "aaaaaaaa"), ("@b", "bbbbbbbb")]).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+
+    for i in 0..config.flood_burst {
+        a.send(&format!("say {}", i)).await;
+        a.expect_containing(&format!("You say, '{}'", i)).await;
     }
+    a.send("say one too many").await;
+    a.expect_containing("You are sending messages too quickly.").await;
+
+    // @a's bucket is empty, but they still hear comings and goings
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    a.expect_containing("@b arrived.").await;
+    b.send("logout").await;
+    a.expect_containing("@b logged off.").await;
 }
 
+#[tokio::test]
+async fn queued_notices_and_logout_go_out_together() {
+    use tokio::io::AsyncReadExt;
+
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4065".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut b = TestClient::connect(config.tcp_addr()).await;
+    b.login("@b", "bbbbbbbb").await;
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    b.expect_containing("@a arrived.").await;
+
+    // queue everything before @a's connection gets a chance to run
+    {
+        let mut state = state.lock().await;
+        let id = state.person_by_name("@a").expect("@a").id;
+        for i in 0..3 {
+            state.send_to(
+                id,
+                Message::Notice {
+                    text: format!("notice {}", i),
+                },
+            );
+        }
+        assert!(state.disconnect(id, "goodbye").await);
+    }
+
+    let parts = a.into_inner().into_parts();
+    assert!(parts.read_buf.is_empty(), "already read past the login");
+    let mut socket = parts.io;
+    let mut buf = vec![0; 4096];
+    let n = socket.read(&mut buf).await.expect("read batch");
+    let batch = String::from_utf8_lossy(&buf[..n]);
+    for line in &["notice 0", "notice 1", "notice 2", "goodbye", "You have logged out."] {
+        assert!(batch.contains(line), "'{}' missing from '{}'", line, batch);
+    }
+    assert_eq!(socket.read(&mut buf).await.expect("read close"), 0);
+
+    b.expect_containing("@a logged off.").await;
+    b.send("who").await;
+    b.expect_containing("Online (1):").await;
+    let line = b.expect_line().await;
+    assert!(line.starts_with("  @b"), "expected only @b, got '{}'", line);
+
+    let state = state.lock().await;
+    assert_eq!(state.online().count(), 1);
+    assert_eq!(state.stats().connected, 1);
+}
+
+#[tokio::test]
+async fn failed_writes_disconnect() {
+    let config = Config {
+        addr: "127.0.0.1".to_string(),
+        tcp_port: "4066".to_string(),
+        ..config_timeout(1)
+    };
+    let state = serve(&config, ACCOUNTS).await;
+
+    let mut a = TestClient::connect(config.tcp_addr()).await;
+    a.login("@a", "aaaaaaaa").await;
+    let mut c = TestClient::connect(config.tcp_addr()).await;
+    c.login("@c", "cccccccc").await;
+    a.expect_containing("@c arrived.").await;
+    let id = state.person_by_name("@c").expect("@c").id;
+
+    // reset @c's connection and queue them a notice before the server gets
+    // to run, so it finds out by writing rather than reading
+    let socket = c.into_inner().into_inner();
+    socket.set_linger(Some(std::time::Duration::from_secs(0))).expect("linger");
+    drop(socket);
+    state.send_to(
+        id,
+        Message::Notice {
+            text: "are you there?".to_string(),
+        },
+    );
+
+    a.expect_containing("@c disconnected.").await;
+    assert_eq!(state.lock().await.stats().connected, 1);
+}